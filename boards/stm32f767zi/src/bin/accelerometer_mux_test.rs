@@ -14,7 +14,7 @@ use embassy_sync::{
     watch::Watch,
 };
 use hyped_boards_stm32f767zi::tasks::sensors::read_accelerometers_from_mux::{
-    read_accelerometers_from_mux, AccelerometerMuxReadings,
+    read_accelerometers_from_mux, AccelerometerMuxReadings, MAX_ACCELEROMETER_MUX_RECEIVERS,
 };
 use hyped_sensors::SensorValueRange::*;
 use panic_probe as _;
@@ -22,8 +22,11 @@ use static_cell::StaticCell;
 
 type I2c1Bus = Mutex<NoopRawMutex, RefCell<I2c<'static, Blocking>>>;
 
-static ACCELERATION_MUX_READINGS: Watch<CriticalSectionRawMutex, AccelerometerMuxReadings, 1> =
-    Watch::new();
+static ACCELERATION_MUX_READINGS: Watch<
+    CriticalSectionRawMutex,
+    AccelerometerMuxReadings,
+    MAX_ACCELEROMETER_MUX_RECEIVERS,
+> = Watch::new();
 
 #[embassy_executor::main]
 async fn main(spawner: Spawner) -> ! {
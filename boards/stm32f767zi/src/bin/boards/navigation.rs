@@ -0,0 +1,124 @@
+#![no_std]
+#![no_main]
+
+use core::cell::RefCell;
+
+use defmt_rtt as _;
+use embassy_executor::Spawner;
+use embassy_stm32::{
+    bind_interrupts,
+    can::{
+        filter::Mask32, Can, Fifo, Rx0InterruptHandler, Rx1InterruptHandler, SceInterruptHandler,
+        TxInterruptHandler,
+    },
+    i2c::I2c,
+    mode::Blocking,
+    peripherals::CAN1,
+    time::Hertz,
+};
+use embassy_sync::{
+    blocking_mutex::{
+        raw::{CriticalSectionRawMutex, NoopRawMutex},
+        Mutex,
+    },
+    watch::Watch,
+};
+use embassy_time::{Duration, Timer};
+use hyped_boards_stm32f767zi::{
+    board_state::{CURRENT_STATE, EMERGENCY, THIS_BOARD},
+    default_can_config,
+    tasks::{
+        calibrate_accelerometers::calibrate_accelerometers,
+        can::{board_heartbeat::send_heartbeat, receive::can_receiver, send::can_sender},
+        localise::localise,
+        sensors::read_accelerometers_from_mux::{
+            read_accelerometers_from_mux, AccelerometerMuxReadings, MAX_ACCELEROMETER_MUX_RECEIVERS,
+        },
+        state_machine::state_machine,
+    },
+};
+use hyped_communications::boards::Board;
+use hyped_state_machine::states::State;
+use panic_probe as _;
+use static_cell::StaticCell;
+
+bind_interrupts!(struct Irqs {
+    CAN1_RX0 => Rx0InterruptHandler<CAN1>;
+    CAN1_RX1 => Rx1InterruptHandler<CAN1>;
+    CAN1_SCE => SceInterruptHandler<CAN1>;
+    CAN1_TX => TxInterruptHandler<CAN1>;
+});
+
+type I2c1Bus = Mutex<NoopRawMutex, RefCell<I2c<'static, Blocking>>>;
+
+static ACCELEROMETER_MUX_READINGS: Watch<
+    CriticalSectionRawMutex,
+    AccelerometerMuxReadings,
+    MAX_ACCELEROMETER_MUX_RECEIVERS,
+> = Watch::new();
+
+#[embassy_executor::main]
+async fn main(spawner: Spawner) -> ! {
+    THIS_BOARD
+        .init(Board::Navigation)
+        .expect("Failed to initialize board");
+
+    let p = embassy_stm32::init(Default::default());
+
+    // CAN tasks: CAN send/receive, heartbeats to the telemetry board, and the state machine
+    defmt::info!("Setting up CAN...");
+    let mut can = Can::new(p.CAN1, p.PD0, p.PD1, Irqs);
+    default_can_config!(can);
+    can.enable().await;
+    let (can_tx, can_rx) = can.split();
+    spawner.must_spawn(can_receiver(can_rx));
+    spawner.must_spawn(can_sender(can_tx));
+    defmt::info!("CAN setup complete");
+
+    spawner.must_spawn(emergency_handler());
+    spawner.must_spawn(send_heartbeat(Board::Telemetry));
+    // On standby behind the telemetry board, taking over when its heartbeats stop. Heartbeats are
+    // monitored by the state machine rather than `heartbeat_listener`, which would stop the pod
+    // instead.
+    spawner.must_spawn(state_machine());
+
+    // The accelerometers are calibrated in the calibrate state, and feed the localiser
+    let i2c = I2c::new_blocking(p.I2C1, p.PB8, p.PB9, Hertz(200_000), Default::default());
+    static I2C_BUS: StaticCell<I2c1Bus> = StaticCell::new();
+    let i2c_bus = I2C_BUS.init(Mutex::new(RefCell::new(i2c)));
+    spawner.must_spawn(read_accelerometers_from_mux(
+        i2c_bus,
+        ACCELEROMETER_MUX_READINGS.sender(),
+    ));
+    spawner.must_spawn(calibrate_accelerometers(
+        ACCELEROMETER_MUX_READINGS
+            .receiver()
+            .expect("Too many accelerometer reading receivers"),
+    ));
+    spawner.must_spawn(localise(
+        ACCELEROMETER_MUX_READINGS
+            .receiver()
+            .expect("Too many accelerometer reading receivers"),
+    ));
+
+    loop {
+        Timer::after(Duration::from_secs(1)).await;
+    }
+}
+
+#[embassy_executor::task]
+async fn emergency_handler() {
+    let current_state_sender = CURRENT_STATE.sender();
+
+    loop {
+        // All main loops should have logic to handle an emergency signal...
+        if EMERGENCY.receiver().unwrap().get().await {
+            defmt::error!("Emergency signal received! Cleaning up...");
+            // ... and take appropriate action
+            current_state_sender.send(State::Emergency);
+            // Wait for the emergency signal to be sent
+            Timer::after(Duration::from_secs(1)).await;
+            panic!("Terminating due to emergency signal!");
+        }
+    }
+}
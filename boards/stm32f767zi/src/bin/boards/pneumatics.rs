@@ -0,0 +1,216 @@
+#![no_std]
+#![no_main]
+
+use core::cell::RefCell;
+
+use defmt_rtt as _;
+use embassy_executor::Spawner;
+use embassy_futures::join::join;
+use embassy_stm32::{
+    bind_interrupts,
+    can::{
+        filter::Mask32, Can, Fifo, Rx0InterruptHandler, Rx1InterruptHandler, SceInterruptHandler,
+        TxInterruptHandler,
+    },
+    gpio::{Level, Output, Speed},
+    i2c::I2c,
+    mode::Blocking,
+    peripherals::CAN1,
+    time::Hertz,
+};
+use embassy_sync::{
+    blocking_mutex::{raw::NoopRawMutex, Mutex},
+    mutex::Mutex as AsyncMutex,
+};
+use embassy_time::{Duration, Timer};
+use hyped_boards_stm32f767zi::{
+    board_state::{CURRENT_STATE, EMERGENCY, THIS_BOARD},
+    default_can_config,
+    io::{Stm32f767ziGpioOutput, Stm32f767ziI2c},
+    tasks::{
+        can::{
+            board_heartbeat::{heartbeat_listener, send_heartbeat},
+            receive::can_receiver,
+            send::can_sender,
+        },
+        maintenance::run_actuator_commands,
+        state_actions::run_state_actions,
+        state_machine::state_updater,
+    },
+};
+use hyped_communications::boards::Board;
+use hyped_control::pneumatics::Pneumatics;
+use hyped_sensors::time_of_flight::{TimeOfFlight, TimeOfFlightAddresses};
+use hyped_state_machine::{
+    actions::{ActionError, StateAction, StateActions},
+    maintenance::{ActuatorCommand, Actuators},
+    states::State,
+};
+use panic_probe as _;
+use static_cell::StaticCell;
+
+bind_interrupts!(struct Irqs {
+    CAN1_RX0 => Rx0InterruptHandler<CAN1>;
+    CAN1_RX1 => Rx1InterruptHandler<CAN1>;
+    CAN1_SCE => SceInterruptHandler<CAN1>;
+    CAN1_TX => TxInterruptHandler<CAN1>;
+});
+
+type I2c1Bus = Mutex<NoopRawMutex, RefCell<I2c<'static, Blocking>>>;
+
+type BoardPneumatics<'a> = Pneumatics<'a, Stm32f767ziGpioOutput, Stm32f767ziI2c<'static>>;
+
+/// Maximum number of state actions of the pneumatics board
+const MAX_STATE_ACTIONS: usize = 8;
+
+#[embassy_executor::main]
+async fn main(spawner: Spawner) -> ! {
+    THIS_BOARD
+        .init(Board::Pneumatics)
+        .expect("Failed to initialize board");
+
+    let p = embassy_stm32::init(Default::default());
+
+    // CAN tasks: CAN send/receive, heartbeats with the telemetry board, and following the state
+    defmt::info!("Setting up CAN...");
+    let mut can = Can::new(p.CAN1, p.PD0, p.PD1, Irqs);
+    default_can_config!(can);
+    can.enable().await;
+    let (can_tx, can_rx) = can.split();
+    spawner.must_spawn(can_receiver(can_rx));
+    spawner.must_spawn(can_sender(can_tx));
+    defmt::info!("CAN setup complete");
+
+    spawner.must_spawn(state_updater());
+    spawner.must_spawn(emergency_handler());
+    spawner.must_spawn(heartbeat_listener(Board::Telemetry));
+    spawner.must_spawn(send_heartbeat(Board::Telemetry));
+
+    // The time of flight sensor checks that the brakes have moved
+    let i2c = I2c::new_blocking(p.I2C1, p.PB8, p.PB9, Hertz(200_000), Default::default());
+    static I2C_BUS: StaticCell<I2c1Bus> = StaticCell::new();
+    let i2c_bus = I2C_BUS.init(Mutex::new(RefCell::new(i2c)));
+    let brake_pin = Stm32f767ziGpioOutput::new(Output::new(p.PE9, Level::Low, Speed::Low));
+    let lateral_suspension_pin =
+        Stm32f767ziGpioOutput::new(Output::new(p.PE11, Level::Low, Speed::Low));
+    spawner.must_spawn(pneumatics(i2c_bus, brake_pin, lateral_suspension_pin));
+
+    loop {
+        Timer::after(Duration::from_secs(1)).await;
+    }
+}
+
+/// Moves the brakes as the pod changes state, and as commanded by the base station in
+/// maintenance. The brakes are released in `State::Ready`, so that the pod can accelerate.
+#[embassy_executor::task]
+async fn pneumatics(
+    i2c_bus: &'static I2c1Bus,
+    brake_pin: Stm32f767ziGpioOutput,
+    lateral_suspension_pin: Stm32f767ziGpioOutput,
+) -> ! {
+    let mut hyped_i2c = Stm32f767ziI2c::new(i2c_bus);
+    let time_of_flight = TimeOfFlight::new(&mut hyped_i2c, TimeOfFlightAddresses::Address29)
+        .expect("Failed to create time of flight sensor. Check the wiring.");
+    let pneumatics = Pneumatics::new(brake_pin, lateral_suspension_pin, time_of_flight)
+        .await
+        .expect("Failed to engage the brakes on startup");
+    let pneumatics = AsyncMutex::<NoopRawMutex, _>::new(pneumatics);
+    let mut actuators = PneumaticsActuators {
+        pneumatics: &pneumatics,
+    };
+
+    let mut actions = StateActions::<_, MAX_STATE_ACTIONS>::new();
+    let action = |command| PneumaticsAction { actuators, command };
+    actions
+        .on_enter(State::Ready, action(ActuatorCommand::DisengageBrakes))
+        .expect("Too many state actions");
+    actions
+        .on_enter(State::Brake, action(ActuatorCommand::EngageBrakes))
+        .expect("Too many state actions");
+    actions
+        .on_enter(State::Emergency, action(ActuatorCommand::EngageBrakes))
+        .expect("Too many state actions");
+    // Undo whatever the base station commanded in maintenance
+    for command in ActuatorCommand::SAFE {
+        actions
+            .on_exit(State::Maintenance, action(command))
+            .expect("Too many state actions");
+    }
+
+    join(
+        run_state_actions(actions),
+        run_actuator_commands(&mut actuators),
+    )
+    .await
+    .0
+}
+
+/// The pneumatics, shared between the state actions and the actuator commands
+#[derive(Clone, Copy)]
+struct PneumaticsActuators<'a, 'b> {
+    pneumatics: &'a AsyncMutex<NoopRawMutex, BoardPneumatics<'b>>,
+}
+
+impl Actuators for PneumaticsActuators<'_, '_> {
+    async fn actuate(&mut self, command: ActuatorCommand) -> Result<(), ActionError> {
+        actuate(&mut *self.pneumatics.lock().await, command).await
+    }
+}
+
+/// A command carried out on the pneumatics as the pod changes state
+struct PneumaticsAction<'a, 'b> {
+    actuators: PneumaticsActuators<'a, 'b>,
+    command: ActuatorCommand,
+}
+
+impl StateAction for PneumaticsAction<'_, '_> {
+    async fn run(&mut self) -> Result<(), ActionError> {
+        self.actuators.actuate(self.command).await
+    }
+}
+
+/// Carries out a command on the pneumatics, ignoring commands for actuators on other boards.
+/// Failing to engage the brakes leaves the pod unsafe.
+async fn actuate(
+    pneumatics: &mut BoardPneumatics<'_>,
+    command: ActuatorCommand,
+) -> Result<(), ActionError> {
+    match command {
+        ActuatorCommand::EngageBrakes => pneumatics.engage_brakes().await.map_err(|_| {
+            defmt::error!("Failed to engage the brakes");
+            ActionError::Unsafe
+        }),
+        ActuatorCommand::DisengageBrakes => pneumatics.disengage_brakes().await.map_err(|_| {
+            defmt::error!("Failed to disengage the brakes");
+            ActionError::Failed
+        }),
+        ActuatorCommand::DeployLateralSuspension => {
+            pneumatics.deploy_lateral_suspension();
+            Ok(())
+        }
+        ActuatorCommand::RetractLateralSuspension => {
+            pneumatics.retract_lateral_suspension();
+            Ok(())
+        }
+        ActuatorCommand::SwitchOnHighPowerRelay
+        | ActuatorCommand::SwitchOffHighPowerRelay
+        | ActuatorCommand::SetMotorFrequency(_) => Ok(()),
+    }
+}
+
+#[embassy_executor::task]
+async fn emergency_handler() {
+    let current_state_sender = CURRENT_STATE.sender();
+
+    loop {
+        // All main loops should have logic to handle an emergency signal...
+        if EMERGENCY.receiver().unwrap().get().await {
+            defmt::error!("Emergency signal received! Cleaning up...");
+            // ... and take appropriate action, which engages the brakes
+            current_state_sender.send(State::Emergency);
+            // Wait for the brakes to engage and the emergency signal to be sent
+            Timer::after(Duration::from_secs(1)).await;
+            panic!("Terminating due to emergency signal!");
+        }
+    }
+}
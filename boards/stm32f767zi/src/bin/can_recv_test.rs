@@ -54,7 +54,13 @@ async fn main(_spawner: Spawner) {
             let mut data = [0; 8];
             data.copy_from_slice(envelope.frame.data());
             let can_frame = HypedCanFrame::new(can_id, data);
-            let can_message: CanMessage = can_frame.into();
+            let can_message = match CanMessage::try_from(can_frame) {
+                Ok(can_message) => can_message,
+                Err(e) => {
+                    defmt::warn!("Received invalid CAN message: {}", e);
+                    continue;
+                }
+            };
 
             match can_message {
                 CanMessage::MeasurementReading(measurement_reading) => {
@@ -87,6 +93,33 @@ async fn main(_spawner: Spawner) {
                         reason
                     );
                 }
+                CanMessage::StateTransitionRejected(rejection) => {
+                    defmt::info!(
+                        "Received state transition rejection over CAN: {:?}",
+                        rejection
+                    );
+                }
+                CanMessage::ActuatorCommand(board, command) => {
+                    defmt::info!(
+                        "Received actuator command from board {:?} over CAN: {:?}",
+                        board,
+                        command
+                    );
+                }
+                CanMessage::AccelerometerCalibration(board, accelerometer, passed) => {
+                    defmt::info!(
+                        "Received calibration of accelerometer {} from board {:?} over CAN: {}",
+                        accelerometer,
+                        board,
+                        passed
+                    );
+                }
+                CanMessage::StateActionFailed(failure) => {
+                    defmt::info!("Received state action failure over CAN: {:?}", failure);
+                }
+                CanMessage::StateTransitionAudited(audited) => {
+                    defmt::info!("Received audit log entry over CAN: {:?}", audited);
+                }
             }
         }
     }
@@ -9,9 +9,12 @@ use embassy_stm32::{
     peripherals::CAN1,
 };
 use embassy_time::{Duration, Timer};
-use hyped_boards_stm32f767zi::tasks::{
-    can::{board_heartbeat::heartbeat_listener, receive::can_receiver, send::can_sender},
-    state_machine::state_machine,
+use hyped_boards_stm32f767zi::{
+    board_state::THIS_BOARD,
+    tasks::{
+        can::{board_heartbeat::heartbeat_listener, receive::can_receiver, send::can_sender},
+        state_machine::state_machine,
+    },
 };
 use hyped_communications::boards::Board;
 use panic_probe as _;
@@ -25,6 +28,11 @@ bind_interrupts!(struct Irqs {
 
 #[embassy_executor::main]
 async fn main(spawner: Spawner) -> ! {
+    // The test board is not a state machine candidate, so its state machine always leads
+    THIS_BOARD
+        .init(Board::Test)
+        .expect("Failed to initialize board");
+
     let p = embassy_stm32::init(Default::default());
 
     let (can_tx, can_rx) = Can::new(p.CAN1, p.PD0, p.PD1, Irqs).split();
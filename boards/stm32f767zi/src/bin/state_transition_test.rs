@@ -11,17 +11,18 @@ use embassy_stm32::{
 use embassy_time::{Duration, Timer};
 use hyped_boards_stm32f767zi::{
     board_state::{CURRENT_STATE, EMERGENCY, THIS_BOARD},
-    emergency, request_transition,
+    emergency, request_transition, request_transition_and_wait,
     tasks::{
         can::{
             receive::can_receiver,
             send::{can_sender, CAN_SEND},
         },
-        state_machine::state_updater,
+        state_machine::{state_updater, TransitionOutcome},
     },
 };
 use hyped_communications::{
-    emergency::Reason, messages::CanMessage, state_transition::StateTransitionRequest,
+    boards::Board, emergency::Reason, messages::CanMessage,
+    state_transition::StateTransitionRequest,
 };
 use hyped_state_machine::states::State;
 use panic_probe as _;
@@ -35,6 +36,10 @@ bind_interrupts!(struct Irqs {
 
 #[embassy_executor::main]
 async fn main(spawner: Spawner) -> ! {
+    THIS_BOARD
+        .init(Board::StateMachineTester)
+        .expect("Failed to initialize board");
+
     let p = embassy_stm32::init(Default::default());
 
     let (can_tx, can_rx) = Can::new(p.CAN1, p.PD0, p.PD1, Irqs).split();
@@ -48,7 +53,16 @@ async fn main(spawner: Spawner) -> ! {
     Timer::after(Duration::from_secs(1)).await;
     request_transition!(State::Precharge);
     Timer::after(Duration::from_secs(1)).await;
-    request_transition!(State::Accelerate);
+    // Accelerate can't be reached from Precharge, so this should be rejected
+    match request_transition_and_wait!(State::Accelerate) {
+        Ok(TransitionOutcome::Accepted(command)) => {
+            defmt::error!("Unexpectedly entered {:?}", command.to_state)
+        }
+        Ok(TransitionOutcome::Rejected(rejection)) => {
+            defmt::info!("Transition rejected as expected: {:?}", rejection)
+        }
+        Err(_) => defmt::error!("Timed out waiting for the state machine"),
+    }
     Timer::after(Duration::from_secs(1)).await;
     emergency!(Reason::Test);
 
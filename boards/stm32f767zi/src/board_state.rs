@@ -1,9 +1,43 @@
+use core::cell::Cell;
 use embassy_sync::{
-    blocking_mutex::raw::CriticalSectionRawMutex, once_lock::OnceLock, watch::Watch,
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex},
+    once_lock::OnceLock,
+    watch::Watch,
 };
 use hyped_communications::boards::Board;
-use hyped_state_machine::states::State;
+use hyped_state_machine::{guards::SystemSnapshot, states::State};
 
 pub static THIS_BOARD: OnceLock<Board> = OnceLock::new();
 pub static CURRENT_STATE: Watch<CriticalSectionRawMutex, State, 1> = Watch::new();
 pub static EMERGENCY: Watch<CriticalSectionRawMutex, bool, 1> = Watch::new();
+
+/// Latest known state of the pod's systems, checked by the state machine's transition guards
+pub static SYSTEM_SNAPSHOT: Mutex<CriticalSectionRawMutex, Cell<SystemSnapshot>> =
+    Mutex::new(Cell::new(SystemSnapshot::new()));
+
+/// Boards whose heartbeats are currently missing, one bit per `Board`
+static MISSING_HEARTBEATS: Mutex<CriticalSectionRawMutex, Cell<u16>> = Mutex::new(Cell::new(0));
+
+/// Updates the system snapshot in place
+pub fn update_snapshot(update: impl FnOnce(&mut SystemSnapshot)) {
+    SYSTEM_SNAPSHOT.lock(|snapshot| {
+        let mut new_snapshot = snapshot.get();
+        update(&mut new_snapshot);
+        snapshot.set(new_snapshot);
+    });
+}
+
+/// Records whether heartbeats are being received from a board.
+/// Heartbeats are healthy once no monitored board is missing.
+pub fn set_heartbeat_healthy(board: Board, healthy: bool) {
+    let missing = MISSING_HEARTBEATS.lock(|missing| {
+        let bit = 1 << u8::from(board);
+        let new_missing = match healthy {
+            true => missing.get() & !bit,
+            false => missing.get() | bit,
+        };
+        missing.set(new_missing);
+        new_missing
+    });
+    update_snapshot(|snapshot| snapshot.heartbeats_healthy = missing == 0);
+}
@@ -1,8 +1,12 @@
-use crate::tasks::mqtt::send::MQTT_SEND;
-use core::str::FromStr;
+use crate::{board_state::THIS_BOARD, tasks::mqtt::send::MQTT_SEND};
 use defmt::{debug, error, info, warn};
-use heapless::String;
-use hyped_core::{log_types::LogLevel, mqtt::MqttMessage, mqtt_topics::MqttTopic};
+use embassy_time::Instant;
+use hyped_core::{
+    log_types::LogLevel,
+    mqtt::MqttMessage,
+    mqtt_payload::{JsonPayload, LogPayload},
+    mqtt_topics::MqttTopic,
+};
 
 /// Log a message to the console and send it to the MQTT broker
 pub async fn log(level: LogLevel, message: &str) {
@@ -12,10 +16,19 @@ pub async fn log(level: LogLevel, message: &str) {
         LogLevel::Error => error!("{}", message),
         LogLevel::Debug => debug!("{}", message),
     }
-    MQTT_SEND
-        .send(MqttMessage {
-            topic: MqttTopic::Logs,
-            payload: String::<512>::from_str(message).unwrap(),
-        })
-        .await;
+
+    let payload = LogPayload::new(
+        Instant::now().as_millis(),
+        (*THIS_BOARD.get().await).into(),
+        level,
+        message,
+    );
+    match payload.to_payload() {
+        Ok(payload) => {
+            MQTT_SEND
+                .send(MqttMessage::new(MqttTopic::Logs, payload))
+                .await
+        }
+        Err(_) => warn!("Log message too long to send over MQTT"),
+    }
 }
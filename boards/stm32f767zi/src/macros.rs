@@ -24,6 +24,26 @@ macro_rules! request_transition {
     };
 }
 
+/// Sends a state transition request to the state machine over CAN, then waits for the state
+/// machine to accept or reject it. Evaluates to a `Result<TransitionOutcome, TimeoutError>`, which
+/// is an error if there is no outcome within `REQUEST_TIMEOUT_MS`.
+#[macro_export]
+macro_rules! request_transition_and_wait {
+    ($state:expr) => {{
+        let state = $state;
+        let mut outcomes = $crate::tasks::state_machine::TRANSITION_OUTCOMES
+            .subscriber()
+            .expect("Too many transition outcome subscribers");
+        $crate::request_transition!(state);
+        $crate::tasks::state_machine::wait_for_outcome(
+            &mut outcomes,
+            $crate::board_state::THIS_BOARD.get().await.clone(),
+            state,
+        )
+        .await
+    }};
+}
+
 /// Perform default CAN configuration.
 #[macro_export]
 macro_rules! default_can_config {
@@ -1,7 +1,11 @@
+pub mod calibrate_accelerometers;
 pub mod can;
 pub mod can_to_mqtt;
+pub mod localise;
+pub mod maintenance;
 pub mod mqtt;
 pub mod network;
 pub mod read_high_pressure;
 pub mod sensors;
+pub mod state_actions;
 pub mod state_machine;
@@ -0,0 +1,79 @@
+use crate::{
+    board_state::{update_snapshot, CURRENT_STATE, THIS_BOARD},
+    tasks::{
+        can::send::CAN_SEND,
+        sensors::read_accelerometers_from_mux::{
+            to_raw_accelerometer_data, AccelerometerMuxReadings, MAX_ACCELEROMETER_MUX_RECEIVERS,
+        },
+    },
+};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, watch::Receiver};
+use hyped_communications::messages::CanMessage;
+use hyped_localisation::{
+    calibration::AccelerometerCalibrator, preprocessing::accelerometer::AccelerometerPreprocessor,
+};
+use hyped_state_machine::states::State;
+
+use defmt_rtt as _;
+use panic_probe as _;
+
+/// Task that calibrates the accelerometers from the readings taken while the pod is in
+/// `State::Calibrate`, where it is stationary. Whether each accelerometer passed is sent over CAN
+/// once enough readings are collected, for the guard on leaving `State::Calibrate`.
+#[embassy_executor::task]
+pub async fn calibrate_accelerometers(
+    mut readings_receiver: Receiver<
+        'static,
+        CriticalSectionRawMutex,
+        AccelerometerMuxReadings,
+        MAX_ACCELEROMETER_MUX_RECEIVERS,
+    >,
+) -> ! {
+    let expected_readings = AccelerometerPreprocessor::new().expected_gravity_readings();
+    let mut calibrator = AccelerometerCalibrator::new();
+    let mut calibrated = false;
+
+    loop {
+        let readings = readings_receiver.changed().await;
+
+        // Calibrate again every time the pod enters the calibrate state
+        if CURRENT_STATE.try_get() != Some(State::Calibrate) {
+            if calibrator.num_samples() > 0 || calibrated {
+                calibrator = AccelerometerCalibrator::new();
+                calibrated = false;
+            }
+            continue;
+        }
+        if calibrated {
+            continue;
+        }
+
+        let Some(data) = to_raw_accelerometer_data(&readings) else {
+            defmt::warn!("Skipping calibration sample, as an accelerometer could not be read");
+            continue;
+        };
+        calibrator.add_sample(&data);
+        let Some(calibration) = calibrator.finish(expected_readings) else {
+            continue;
+        };
+
+        let this_board = *THIS_BOARD.get().await;
+        for (accelerometer, passed) in calibration.passed().into_iter().enumerate() {
+            defmt::info!(
+                "Accelerometer {} calibrated, passed: {}",
+                accelerometer,
+                passed
+            );
+            CAN_SEND
+                .send(CanMessage::AccelerometerCalibration(
+                    this_board,
+                    accelerometer as u8,
+                    passed,
+                ))
+                .await;
+            // CAN messages are not received by the board that sends them
+            update_snapshot(|snapshot| snapshot.record_calibration(accelerometer, passed));
+        }
+        calibrated = true;
+    }
+}
@@ -3,9 +3,10 @@ use hyped_communications::{
     boards::Board, emergency::Reason, heartbeat::Heartbeat, messages::CanMessage,
 };
 use hyped_core::config::HEARTBEAT_CONFIG;
+use hyped_state_machine::modes::RunMode;
 
 use crate::{
-    board_state::{EMERGENCY, THIS_BOARD},
+    board_state::{set_heartbeat_healthy, CURRENT_STATE, EMERGENCY, THIS_BOARD},
     emergency,
     tasks::can::{receive::INCOMING_HEARTBEATS, send::CAN_SEND},
 };
@@ -19,9 +20,11 @@ use panic_probe as _;
 /// For all other boards, this should be spawned once for the controller board.
 #[embassy_executor::task]
 pub async fn heartbeat_listener(from_board: Board) {
+    set_heartbeat_healthy(from_board, false);
     match wait_for_first_heartbeat(from_board).await {
         Ok(_) => {
             defmt::info!("Board {:?} is alive!", from_board,);
+            set_heartbeat_healthy(from_board, true);
         }
         Err(_) => {
             defmt::error!(
@@ -32,6 +35,7 @@ pub async fn heartbeat_listener(from_board: Board) {
         }
     }
 
+    let mode = RunMode::from_config().expect("Invalid run mode");
     loop {
         // Wait for an incoming heartbeat message from the target board
         match with_timeout(Duration::from_millis(HEARTBEAT_CONFIG.boards.max_latency_ms as u64), async {
@@ -43,6 +47,14 @@ pub async fn heartbeat_listener(from_board: Board) {
                         "Received heartbeat from board {:?}",
                         from_board,
                     );
+                    if heartbeat.mode != mode {
+                        defmt::warn!(
+                            "Board {:?} is running in mode {:?}, but this board is running in mode {:?}",
+                            from_board,
+                            heartbeat.mode,
+                            mode,
+                        );
+                    }
                     break;
                 }
             }
@@ -50,8 +62,9 @@ pub async fn heartbeat_listener(from_board: Board) {
         .await
         // trigger emergency stop if we don't receive a heartbeat in time
         {
-            Ok(_) => {}
+            Ok(_) => set_heartbeat_healthy(from_board, true),
             Err(_) => {
+                set_heartbeat_healthy(from_board, false);
                 defmt::error!(
                     "Emergency stop triggered due to missing heartbeat from board {:?}",
                     from_board
@@ -89,10 +102,17 @@ pub async fn wait_for_first_heartbeat(target_board: Board) -> Result<(), ()> {
 #[embassy_executor::task]
 pub async fn send_heartbeat(to_board: Board) {
     let can_sender = CAN_SEND.sender();
+    let mode = RunMode::from_config().expect("Invalid run mode");
 
     loop {
-        // Send a heartbeat to the controller board every 100ms
-        let heartbeat = Heartbeat::new(to_board, *THIS_BOARD.get().await);
+        // Send a heartbeat to the controller board every 100ms, with the state this board is in
+        // so that a rebooted state machine can recover it
+        let heartbeat = Heartbeat::new(
+            to_board,
+            *THIS_BOARD.get().await,
+            mode,
+            CURRENT_STATE.try_get(),
+        );
         defmt::debug!("Sending heartbeat: {:?}", heartbeat);
         can_sender.send(CanMessage::Heartbeat(heartbeat)).await;
 
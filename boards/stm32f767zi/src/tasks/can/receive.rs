@@ -1,20 +1,30 @@
 use embassy_stm32::can::{CanRx, Id};
-use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
+use embassy_sync::{
+    blocking_mutex::raw::CriticalSectionRawMutex,
+    channel::Channel,
+    pubsub::{PubSubChannel, Subscriber},
+};
 use hyped_can::HypedCanFrame;
 use hyped_communications::{
     heartbeat::Heartbeat,
     measurements::MeasurementReading,
     messages::CanMessage,
-    state_transition::{StateTransitionCommand, StateTransitionRequest},
+    state_transition::{StateActionFailed, StateTransitionCommand, StateTransitionRequest},
 };
 
-use crate::board_state::EMERGENCY;
+use hyped_state_machine::{maintenance::ActuatorCommand, states::State};
+
+use crate::{
+    board_state::{update_snapshot, EMERGENCY},
+    tasks::state_machine::{record_audit_entry, TransitionOutcome, TRANSITION_OUTCOMES},
+};
 
 use defmt_rtt as _;
 use panic_probe as _;
 
 /// Stores incoming state transitions received from CAN.
-/// All boards should listen to this channel and update their states accordingly.
+/// All boards should listen to this channel and update their states accordingly, either in
+/// `state_updater` or in `state_machine`.
 pub static INCOMING_STATE_TRANSITION_COMMANDS: Channel<
     CriticalSectionRawMutex,
     StateTransitionCommand,
@@ -22,7 +32,7 @@ pub static INCOMING_STATE_TRANSITION_COMMANDS: Channel<
 > = Channel::new();
 
 /// Stores incoming state transition requests received from CAN.
-/// Only used by the main control board running the state_machine task.
+/// Only used by the boards running the state_machine task.
 pub static INCOMING_STATE_TRANSITION_REQUESTS: Channel<
     CriticalSectionRawMutex,
     StateTransitionRequest,
@@ -32,18 +42,58 @@ pub static INCOMING_STATE_TRANSITION_REQUESTS: Channel<
 /// Stores heartbeat messages coming in from other boards that we need to respond to.
 pub static INCOMING_HEARTBEATS: Channel<CriticalSectionRawMutex, Heartbeat, 10> = Channel::new();
 
+/// Every heartbeat seen on the CAN bus, whichever board it is sent to.
+/// Used by the state machine to elect its leader and to recover the state after a reboot.
+pub static OBSERVED_HEARTBEATS: PubSubChannel<CriticalSectionRawMutex, Heartbeat, 8, 1, 1> =
+    PubSubChannel::new();
+
+pub type ObservedHeartbeatSubscriber =
+    Subscriber<'static, CriticalSectionRawMutex, Heartbeat, 8, 1, 1>;
+
 /// Stores measurement readings coming in from other boards.
 pub static INCOMING_MEASUREMENTS: Channel<CriticalSectionRawMutex, MeasurementReading, 10> =
     Channel::new();
 
+/// Stores actuator commands sent in maintenance.
+/// Only used by boards that run `run_actuator_commands`.
+pub static INCOMING_ACTUATOR_COMMANDS: Channel<CriticalSectionRawMutex, ActuatorCommand, 4> =
+    Channel::new();
+
+const MAX_STATE_ACTION_FAILURE_SUBSCRIBERS: usize = 2;
+
+/// State actions that failed on any board, including this one.
+/// Used by the state machine to revert the transition, and by boards that send them to the base
+/// station. Every subscriber sees every failure.
+pub static INCOMING_STATE_ACTION_FAILURES: PubSubChannel<
+    CriticalSectionRawMutex,
+    StateActionFailed,
+    4,
+    MAX_STATE_ACTION_FAILURE_SUBSCRIBERS,
+    1,
+> = PubSubChannel::new();
+
+pub type StateActionFailureSubscriber = Subscriber<
+    'static,
+    CriticalSectionRawMutex,
+    StateActionFailed,
+    4,
+    MAX_STATE_ACTION_FAILURE_SUBSCRIBERS,
+    1,
+>;
+
 /// Task that receives CAN messages and puts them into a channel.
-/// Currently only supports `StateTransitionCommand`, `StateTransitionRequest` and `Heartbeat` messages.
+/// Currently only supports `StateTransitionCommand`, `StateTransitionRequest`,
+/// `StateTransitionRejected`, `Heartbeat`, `ActuatorCommand`, `AccelerometerCalibration`,
+/// `StateActionFailed` and `StateTransitionAudited` messages.
 #[embassy_executor::task]
 pub async fn can_receiver(mut rx: CanRx<'static>) {
     let emergency_sender = EMERGENCY.sender();
     let state_transition_commands_sender = INCOMING_STATE_TRANSITION_COMMANDS.sender();
     let state_transition_requests_sender = INCOMING_STATE_TRANSITION_REQUESTS.sender();
     let incoming_heartbeat_sender = INCOMING_HEARTBEATS.sender();
+    let outcome_publisher = TRANSITION_OUTCOMES.immediate_publisher();
+    let heartbeat_publisher = OBSERVED_HEARTBEATS.immediate_publisher();
+    let failure_publisher = INCOMING_STATE_ACTION_FAILURES.immediate_publisher();
 
     loop {
         defmt::debug!("Waiting for CAN message");
@@ -62,33 +112,89 @@ pub async fn can_receiver(mut rx: CanRx<'static>) {
         data.copy_from_slice(envelope.frame.data());
         let can_frame = HypedCanFrame::new(can_id, data);
 
-        let can_message: CanMessage = can_frame.into();
+        let can_message = match CanMessage::try_from(can_frame) {
+            Ok(can_message) => can_message,
+            Err(e) => {
+                defmt::warn!("Ignoring invalid CAN message with ID {}: {}", can_id, e);
+                continue;
+            }
+        };
         defmt::debug!("Received CAN message: {:?}", can_message);
 
         match can_message {
             CanMessage::StateTransitionCommand(state_transition_command) => {
+                outcome_publisher.publish_immediate(TransitionOutcome::Accepted(
+                    state_transition_command.clone(),
+                ));
                 state_transition_commands_sender
                     .send(state_transition_command)
                     .await;
             }
-            // Requests will only be used on the primary board running the state_machine task.
+            // Requests will only be used on the boards running the state_machine task.
             CanMessage::StateTransitionRequest(state_transition) => {
                 state_transition_requests_sender
                     .send(state_transition)
                     .await;
             }
+            CanMessage::StateTransitionRejected(rejection) => {
+                defmt::warn!("State transition rejected: {:?}", rejection);
+                outcome_publisher.publish_immediate(TransitionOutcome::Rejected(rejection));
+            }
             CanMessage::Heartbeat(heartbeat) => {
                 defmt::debug!("Received heartbeat: {:?}", heartbeat);
+                heartbeat_publisher.publish_immediate(heartbeat);
                 incoming_heartbeat_sender.send(heartbeat).await;
             }
             CanMessage::Emergency(board, reason) => {
                 emergency_sender.send(true);
                 defmt::error!("Emergency message from board {}: {}", board, reason);
+                // Let the state machine (if running on this board) record the emergency.
+                // Never wait here, as other boards don't consume state transition requests.
+                let _ = state_transition_requests_sender
+                    .try_send(StateTransitionRequest::new(board, State::Emergency));
             }
             CanMessage::MeasurementReading(measurement_reading) => {
                 defmt::info!("Received measurement reading: {:?}", measurement_reading);
+                // Keep the state machine's guards up to date
+                if let Ok(value) = measurement_reading.reading.try_into() {
+                    update_snapshot(|snapshot| {
+                        snapshot.update(measurement_reading.measurement_id, value)
+                    });
+                }
                 INCOMING_MEASUREMENTS.send(measurement_reading).await;
             }
+            CanMessage::ActuatorCommand(board, command) => {
+                defmt::info!("Received actuator command from {:?}: {:?}", board, command);
+                // Never wait here, as boards without actuators don't consume actuator commands
+                if INCOMING_ACTUATOR_COMMANDS.try_send(command).is_err() {
+                    defmt::debug!("Dropped actuator command, as nothing consumes them");
+                }
+            }
+            CanMessage::AccelerometerCalibration(board, accelerometer, passed) => {
+                defmt::info!(
+                    "Accelerometer {} calibrated by {:?}, passed: {}",
+                    accelerometer,
+                    board,
+                    passed
+                );
+                // Checked by the guard on leaving `State::Calibrate`
+                update_snapshot(|snapshot| {
+                    snapshot.record_calibration(accelerometer as usize, passed)
+                });
+            }
+            CanMessage::StateActionFailed(failure) => {
+                defmt::warn!("State action failed: {:?}", failure);
+                failure_publisher.publish_immediate(failure);
+            }
+            CanMessage::StateTransitionAudited(audited) => {
+                defmt::debug!("State machine audit log entry: {:?}", audited);
+                record_audit_entry(
+                    audited.requester.into(),
+                    audited.from_state,
+                    audited.to_state,
+                    audited.outcome,
+                );
+            }
         }
     }
 }
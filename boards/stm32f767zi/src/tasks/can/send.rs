@@ -1,26 +1,45 @@
+use embassy_futures::select::{select, Either};
 use embassy_stm32::can::{CanTx, ExtendedId, Frame, Id};
 use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
+use embassy_time::{Duration, Instant};
 use hyped_can::HypedCanFrame;
 use hyped_communications::messages::CanMessage;
 
 /// Channel for sending CAN messages.
 pub static CAN_SEND: Channel<CriticalSectionRawMutex, CanMessage, 10> = Channel::new();
 
+/// Channel for CAN messages that must be sent before anything in `CAN_SEND`, e.g. a remote
+/// emergency stop. Each message is paired with the time it was received, to measure latency.
+pub static PRIORITY_CAN_SEND: Channel<CriticalSectionRawMutex, (CanMessage, Instant), 4> =
+    Channel::new();
+
+/// How long each message in `PRIORITY_CAN_SEND` waited before it was sent, to be reported to the
+/// base station
+pub static PRIORITY_CAN_LATENCIES: Channel<CriticalSectionRawMutex, (CanMessage, Duration), 4> =
+    Channel::new();
+
 /// Task that sends CAN messages from a channel.
+/// Messages in `PRIORITY_CAN_SEND` are always sent first.
 #[embassy_executor::task]
 pub async fn can_sender(mut tx: CanTx<'static>) {
     let can_sender = CAN_SEND.receiver();
+    let priority_can_sender = PRIORITY_CAN_SEND.receiver();
 
     // Clear the tx buffer
     tx.flush_all().await;
     defmt::info!("Starting...");
 
     loop {
-        let message = can_sender.receive().await;
+        // `select` polls the priority channel first, so it wins if both have messages
+        let (message, received_at) =
+            match select(priority_can_sender.receive(), can_sender.receive()).await {
+                Either::First((message, received_at)) => (message, Some(received_at)),
+                Either::Second(message) => (message, None),
+            };
 
         defmt::debug!("Sending CAN message: {:?}", message);
 
-        let can_frame: HypedCanFrame = message.into();
+        let can_frame: HypedCanFrame = message.clone().into();
 
         let id = Id::Extended(ExtendedId::new(can_frame.can_id).unwrap());
         let data = can_frame.data;
@@ -29,5 +48,17 @@ pub async fn can_sender(mut tx: CanTx<'static>) {
 
         tx.write(&frame).await;
         defmt::debug!("CAN message sent: {:?}", frame);
+
+        // Reported to the base station by another task, so a full MQTT channel can never hold up
+        // CAN
+        if let Some(received_at) = received_at {
+            let latency = Instant::now() - received_at;
+            if PRIORITY_CAN_LATENCIES.try_send((message, latency)).is_err() {
+                defmt::warn!(
+                    "Priority CAN message sent {}us after receipt, but the latency was not reported",
+                    latency.as_micros()
+                );
+            }
+        }
     }
 }
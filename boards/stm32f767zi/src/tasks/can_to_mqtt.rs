@@ -1,101 +1,455 @@
-use core::str::FromStr;
-use embassy_futures::join::join;
-use heapless::String;
+use embassy_futures::{
+    join::{join, join3},
+    select::{select, Either},
+};
+use embassy_time::{Duration, Instant, Ticker};
+use heapless::{FnvIndexMap, Vec};
 use hyped_communications::{
     boards::Board, messages::CanMessage, state_transition::StateTransitionRequest,
 };
 use hyped_core::{
+    aggregation::{Aggregation, MeasurementAggregator},
+    config::{MeasurementId, TELEMETRY_CONFIG},
     format,
     format_string::show,
-    mqtt::MqttMessage,
-    mqtt_topics::{MqttTopic, MQTT_MEASUREMENT_TOPIC_PREFIX},
+    log_types::LogLevel,
+    mqtt::{
+        cbor::{MeasurementBatch, MAX_BATCH_SIZE},
+        MqttMessage,
+    },
+    mqtt_command::{parse_command, CommandError, CommandRejection},
+    mqtt_payload::{
+        CommandResponsePayload, JsonPayload, MeasurementPayload, MeasurementValue, PayloadEncoding,
+        StateAuditPayload, StatePayload, StateTransitionRejectedPayload,
+    },
+    mqtt_topics::MqttTopic,
+};
+use hyped_state_machine::{
+    audit::{AuditEntry, AuditOutcome, AuditQuery},
+    config::AUDIT_LOG_SIZE,
+    guards::GuardFailure,
+    modes::RunMode,
+    states::State,
 };
-use hyped_state_machine::states::State;
+
+use crate::{board_state::THIS_BOARD, log::log};
 
 use super::{
     can::{
-        receive::{INCOMING_MEASUREMENTS, INCOMING_STATE_TRANSITION_COMMANDS},
-        send::CAN_SEND,
+        receive::{INCOMING_MEASUREMENTS, INCOMING_STATE_ACTION_FAILURES},
+        send::{CAN_SEND, PRIORITY_CAN_LATENCIES},
     },
+    maintenance::{reset_interlocks_after_maintenance, send_mqtt_actuator_command_to_can},
     mqtt::{receive::MQTT_RECEIVE, send::MQTT_SEND},
+    state_machine::{TransitionOutcome, AUDIT_ENTRIES, AUDIT_LOG, TRANSITION_OUTCOMES},
 };
 
 /// Run functions to send CAN messages to MQTT and vice versa.
 #[embassy_executor::task]
 pub async fn can_to_mqtt() {
-    join(
+    join3(
         join(
             send_can_state_transition_command_to_mqtt(),
             send_can_measurement_to_mqtt(),
         ),
-        send_mqtt_state_transition_requests_to_can(),
+        join3(
+            send_mqtt_state_transition_requests_to_can(),
+            send_state_transition_rejections_to_mqtt(),
+            send_priority_can_latencies_to_mqtt(),
+        ),
+        join3(
+            send_audit_entries_to_mqtt(),
+            reset_interlocks_after_maintenance(),
+            send_state_action_failures_to_mqtt(),
+        ),
     )
     .await;
 }
 
-/// Send a CAN state transition command to MQTT.
+/// Send state transition commands to MQTT, whether they come from CAN or from the state machine
+/// on this board. Commands are left in `INCOMING_STATE_TRANSITION_COMMANDS` for the task that
+/// updates the state of this board.
 pub async fn send_can_state_transition_command_to_mqtt() {
-    let state_transition_commands_receiver = INCOMING_STATE_TRANSITION_COMMANDS.receiver();
+    let mut outcomes = TRANSITION_OUTCOMES
+        .subscriber()
+        .expect("Too many transition outcome subscribers");
+    let mode = RunMode::from_config().expect("Invalid run mode");
 
     loop {
-        let state_transition_command = state_transition_commands_receiver.receive().await;
+        let state_transition_command = match outcomes.next_message_pure().await {
+            TransitionOutcome::Accepted(command) => command,
+            TransitionOutcome::Rejected(_) => continue,
+        };
+
+        let payload = StatePayload::new(
+            Instant::now().as_millis(),
+            state_transition_command.from_board.into(),
+            state_transition_command.to_state.into(),
+            mode.into(),
+        )
+        .to_payload()
+        .expect("Failed to serialise state payload");
 
-        let message = MqttMessage::new(
-            MqttTopic::State,
-            String::from_str(state_transition_command.to_state.into()).unwrap(),
-        );
+        let message = MqttMessage::new(MqttTopic::State, payload);
         MQTT_SEND.send(message).await;
     }
 }
 
+/// Send rejected state transition requests to MQTT, so the base station knows why its request
+/// failed.
+pub async fn send_state_transition_rejections_to_mqtt() {
+    let mut outcomes = TRANSITION_OUTCOMES
+        .subscriber()
+        .expect("Too many transition outcome subscribers");
+
+    loop {
+        let rejection = match outcomes.next_message_pure().await {
+            TransitionOutcome::Rejected(rejection) => rejection,
+            TransitionOutcome::Accepted(_) => continue,
+        };
+
+        let failed_guards: Vec<&str, { GuardFailure::COUNT }> = rejection
+            .reason
+            .failed_guards()
+            .iter()
+            .map(|failure| failure.into())
+            .collect();
+        let payload = StateTransitionRejectedPayload {
+            timestamp_ms: Instant::now().as_millis(),
+            board: rejection.from_board.into(),
+            requesting_board: rejection.requesting_board.into(),
+            current_state: rejection.current_state.into(),
+            requested_state: rejection.requested_state.into(),
+            reason: rejection.reason.into(),
+            reason_code: rejection.reason.code(),
+            failed_guards: &failed_guards,
+        };
+        match payload.to_payload() {
+            Ok(payload) => {
+                MQTT_SEND
+                    .send(MqttMessage::new(
+                        MqttTopic::StateTransitionRejected,
+                        payload,
+                    ))
+                    .await
+            }
+            Err(_) => defmt::warn!("Failed to serialise rejection {:?}", rejection),
+        }
+    }
+}
+
+/// Send the state actions that failed on any board to MQTT as errors, as the board could not
+/// complete the transition.
+pub async fn send_state_action_failures_to_mqtt() {
+    let mut failures = INCOMING_STATE_ACTION_FAILURES
+        .subscriber()
+        .expect("Too many state action failure subscribers");
+
+    loop {
+        let failure = failures.next_message_pure().await;
+        log(
+            LogLevel::Error,
+            format!(
+                &mut [0u8; 256],
+                "Board {} failed a state action moving from {} to {} ({:?} {:?}: {:?})",
+                <&str>::from(failure.from_board),
+                <&str>::from(failure.from_state),
+                <&str>::from(failure.to_state),
+                failure.failure.hook,
+                failure.failure.state,
+                failure.failure.error
+            )
+            .unwrap_or("A board failed a state action"),
+        )
+        .await;
+    }
+}
+
+/// Send how long each priority CAN message, e.g. a remote emergency stop, waited before it was
+/// sent to MQTT
+pub async fn send_priority_can_latencies_to_mqtt() {
+    let latencies_receiver = PRIORITY_CAN_LATENCIES.receiver();
+
+    loop {
+        let (message, latency) = latencies_receiver.receive().await;
+        log(
+            LogLevel::Warn,
+            format!(
+                &mut [0u8; 256],
+                "Priority CAN message {:?} sent {}us after receipt",
+                message,
+                latency.as_micros()
+            )
+            .unwrap_or("Priority CAN message sent"),
+        )
+        .await;
+    }
+}
+
+/// Send new entries of the state machine's audit log to MQTT.
+/// The latest entry is retained by the broker, so it is seen even by clients that connect later.
+pub async fn send_audit_entries_to_mqtt() {
+    let audit_entries_receiver = AUDIT_ENTRIES.receiver();
+
+    loop {
+        let entry = audit_entries_receiver.receive().await;
+        send_audit_entry(&entry, true).await;
+    }
+}
+
+/// Send an entry of the state machine's audit log to `MqttTopic::StateAudit`.
+async fn send_audit_entry(entry: &AuditEntry, retain: bool) {
+    let reason = match entry.outcome {
+        AuditOutcome::Accepted => None,
+        AuditOutcome::Rejected(reason) => Some(<&str>::from(reason)),
+    };
+    let payload = StateAuditPayload {
+        timestamp_ms: entry.timestamp_ms,
+        board: (*THIS_BOARD.get().await).into(),
+        sequence: entry.sequence,
+        requester: entry.requester,
+        from_state: entry.from.into(),
+        to_state: entry.to.into(),
+        outcome: entry.outcome.into(),
+        reason,
+    };
+    match payload.to_payload() {
+        Ok(payload) => {
+            let message = match retain {
+                true => MqttMessage::new_retained(MqttTopic::StateAudit, payload),
+                false => MqttMessage::new(MqttTopic::StateAudit, payload),
+            };
+            MQTT_SEND.send(message).await
+        }
+        Err(_) => defmt::warn!("Failed to serialise audit log entry {}", entry.sequence),
+    }
+}
+
+/// Maximum number of measurements that can be batched at once
+const MAX_BATCHED_MEASUREMENTS: usize = 8;
+
+/// Maximum number of measurements that can be aggregated at once
+const MAX_AGGREGATED_MEASUREMENTS: usize = 64;
+
+/// How often to check for aggregation windows that have ended and batches that are too old
+const AGGREGATION_TICK: Duration = Duration::from_millis(10);
+
 /// Send a CAN measurement to MQTT.
+/// Measurements with a binary payload encoding are batched, and a batch is sent once it is full
+/// or its oldest reading is `batch_max_age_ms` old. All others are sent as JSON, either one
+/// reading at a time or aggregated over a window, depending on their kind.
+/// State changes are sent by `send_can_state_transition_command_to_mqtt`, so are never delayed.
 pub async fn send_can_measurement_to_mqtt() {
     let measurements_receiver = INCOMING_MEASUREMENTS.receiver();
+    let batch_size = (TELEMETRY_CONFIG.mqtt.payloads.batch_size as usize).clamp(1, MAX_BATCH_SIZE);
+    let batch_max_age_ms = TELEMETRY_CONFIG.mqtt.payloads.batch_max_age_ms as u64;
+    let mut batches: FnvIndexMap<MeasurementId, MeasurementBatch, MAX_BATCHED_MEASUREMENTS> =
+        FnvIndexMap::new();
+    let mut aggregator = MeasurementAggregator::<MAX_AGGREGATED_MEASUREMENTS>::new();
+    let mut aggregation_ticker = Ticker::every(AGGREGATION_TICK);
 
     defmt::debug!("Task started: send_can_measurement_to_mqtt");
 
     loop {
-        let measurement = measurements_receiver.receive().await;
+        let measurement =
+            match select(measurements_receiver.receive(), aggregation_ticker.next()).await {
+                Either::First(measurement) => measurement,
+                Either::Second(()) => {
+                    let now_ms = Instant::now().as_millis();
+                    while let Some(summary) = aggregator.take_expired(now_ms) {
+                        send_json_payload(summary.measurement_id, summary.to_payload()).await;
+                    }
+                    for batch in batches.values_mut() {
+                        if batch.is_older_than(now_ms, batch_max_age_ms) {
+                            send_measurement_batch(batch).await;
+                        }
+                    }
+                    continue;
+                }
+            };
+        let timestamp_ms = Instant::now().as_millis();
+        let board: &'static str = measurement.board.into();
 
-        let mut topic_string = String::<100>::new();
-        topic_string
-            .push_str(MQTT_MEASUREMENT_TOPIC_PREFIX)
-            .unwrap();
-        topic_string
-            .push_str(measurement.measurement_id.into())
-            .unwrap();
+        let value: MeasurementValue = match measurement.reading.try_into() {
+            Ok(value) => value,
+            Err(e) => {
+                defmt::warn!("Ignoring measurement {:?}: {}", measurement, e);
+                continue;
+            }
+        };
 
-        let topic = topic_string
-            .parse()
-            .expect("Failed to parse measurement ID from CAN bus");
+        if measurement.measurement_id.payload_encoding() == PayloadEncoding::Cbor {
+            if !batches.contains_key(&measurement.measurement_id)
+                && batches
+                    .insert(
+                        measurement.measurement_id,
+                        MeasurementBatch::new(board, measurement.measurement_id),
+                    )
+                    .is_err()
+            {
+                defmt::warn!(
+                    "Too many batched measurements, dropping {:?}",
+                    measurement.measurement_id
+                );
+                continue;
+            }
+            let batch = batches
+                .get_mut(&measurement.measurement_id)
+                .expect("Batch was just inserted");
 
-        let payload =
-            String::from_str(format!(&mut [0u8; 1024], "{}", measurement.reading).unwrap())
-                .unwrap();
+            // A batch only holds readings from one board
+            if batch.board != board {
+                send_measurement_batch(batch).await;
+                batch.board = board;
+            }
+            batch
+                .push(timestamp_ms, value)
+                .expect("Batches are sent before they are full");
+            if batch.len() >= batch_size {
+                send_measurement_batch(batch).await;
+            }
+            continue;
+        }
 
-        let message = MqttMessage::new(topic, payload);
+        match aggregator.add(timestamp_ms, board, measurement.measurement_id, value) {
+            Aggregation::Forward => {
+                let payload =
+                    MeasurementPayload::new(timestamp_ms, board, measurement.measurement_id, value);
+                send_json_payload(measurement.measurement_id, payload).await;
+            }
+            Aggregation::Windowed => {}
+        }
+    }
+}
 
-        defmt::debug!("Sending CAN measurement to MQTT: {:?}", message);
+/// Send a JSON payload to the MQTT topic of a measurement.
+async fn send_json_payload(measurement_id: MeasurementId, payload: impl JsonPayload) {
+    match payload.to_payload() {
+        Ok(payload) => {
+            let message = MqttMessage::new(MqttTopic::Measurement(measurement_id), payload);
+            defmt::debug!("Sending CAN measurement to MQTT: {:?}", message);
+            MQTT_SEND.send(message).await;
+        }
+        Err(_) => defmt::warn!("Failed to serialise measurement {:?}", measurement_id),
+    }
+}
 
-        MQTT_SEND.send(message).await;
+/// Send a batch of measurements to MQTT as CBOR, then clear it.
+async fn send_measurement_batch(batch: &mut MeasurementBatch<'static>) {
+    if batch.is_empty() {
+        return;
+    }
+    match batch.to_payload() {
+        Ok(payload) => {
+            let topic = MqttTopic::MeasurementBatch(batch.measurement_id);
+            MQTT_SEND
+                .send(MqttMessage::new_binary(topic, payload))
+                .await;
+        }
+        Err(e) => defmt::warn!(
+            "Failed to encode batch of {:?}: {:?}",
+            batch.measurement_id,
+            e
+        ),
     }
+    batch.clear();
 }
 
-/// Send MQTT state transition requests to CAN.
+/// Name of the state transition command, and of the field holding the requested state
+pub const STATE_COMMAND: &str = "state";
+
+/// Name of the audit log query command, and of the field holding the query
+pub const AUDIT_COMMAND: &str = "audit";
+
+/// Send MQTT state transition requests and actuator commands to CAN, and answer queries of the
+/// audit log.
+/// Malformed requests are rejected rather than panicking, and every request gets a response on
+/// `MqttTopic::CommandResponse` so the base station knows whether it was accepted.
 pub async fn send_mqtt_state_transition_requests_to_can() {
     let mqtt_receive_receiver = MQTT_RECEIVE.receiver();
 
     loop {
         let mqtt_message = mqtt_receive_receiver.receive().await;
-        if mqtt_message.topic == MqttTopic::State {
-            let state: State = mqtt_message
-                .payload
-                .as_str()
-                .parse()
-                .expect("Failed to parse state");
-            let can_message =
-                CanMessage::StateTransitionRequest(StateTransitionRequest::new(Board::Mqtt, state));
-            CAN_SEND.send(can_message).await;
+        match mqtt_message.topic {
+            MqttTopic::State => {}
+            MqttTopic::StateAuditRequest => {
+                answer_audit_query(mqtt_message.payload.as_bytes()).await;
+                continue;
+            }
+            MqttTopic::MaintenanceCommand => {
+                send_mqtt_actuator_command_to_can(mqtt_message.payload.as_bytes()).await;
+                continue;
+            }
+            _ => continue,
+        }
+
+        let result =
+            parse_command(mqtt_message.payload.as_bytes(), STATE_COMMAND).and_then(|command| {
+                match command.value.parse::<State>() {
+                    Ok(state) => Ok((command.id, state)),
+                    Err(_) => Err(command.reject(CommandError::InvalidValue)),
+                }
+            });
+
+        match result {
+            Ok((id, state)) => {
+                let can_message = CanMessage::StateTransitionRequest(StateTransitionRequest::new(
+                    Board::Mqtt,
+                    state,
+                ));
+                CAN_SEND.send(can_message).await;
+                send_command_response(STATE_COMMAND, Ok(id)).await;
+            }
+            Err(rejection) => {
+                defmt::warn!("Rejected state command: {:?}", rejection);
+                send_command_response(STATE_COMMAND, Err(rejection)).await;
+            }
+        }
+    }
+}
+
+/// Send the entries of the audit log matching a query to `MqttTopic::StateAudit`, oldest first.
+/// The query is `all`, `rejected` or the name of a requester, e.g. `{"id":"7","audit":"mqtt"}`.
+/// The response to the command is sent after the last entry, so marks the end of the results.
+async fn answer_audit_query(payload: &[u8]) {
+    let result = parse_command(payload, AUDIT_COMMAND).and_then(|command| {
+        match command.value.parse::<AuditQuery>() {
+            Ok(query) => Ok((command.id, query)),
+            Err(_) => Err(command.reject(CommandError::InvalidValue)),
+        }
+    });
+    let (id, query) = match result {
+        Ok(query) => query,
+        Err(rejection) => {
+            defmt::warn!("Rejected audit command: {:?}", rejection);
+            send_command_response(AUDIT_COMMAND, Err(rejection)).await;
+            return;
+        }
+    };
+
+    // Copy the entries, so that the state machine can carry on while they are sent
+    let entries: Vec<AuditEntry, AUDIT_LOG_SIZE> =
+        AUDIT_LOG.lock(|log| log.borrow().query(&query).copied().collect());
+    for entry in entries.iter() {
+        send_audit_entry(entry, false).await;
+    }
+    send_command_response(AUDIT_COMMAND, Ok(id)).await;
+}
+
+/// Send the response to a command received over MQTT.
+pub async fn send_command_response(
+    command: &str,
+    result: Result<Option<&str>, CommandRejection<'_>>,
+) {
+    let board = (*THIS_BOARD.get().await).into();
+    match CommandResponsePayload::new(Instant::now().as_millis(), board, command, result)
+        .to_payload()
+    {
+        Ok(payload) => {
+            MQTT_SEND
+                .send(MqttMessage::new(MqttTopic::CommandResponse, payload))
+                .await
         }
+        Err(_) => defmt::warn!("Failed to serialise response to {} command", command),
     }
 }
@@ -0,0 +1,67 @@
+use crate::{
+    board_state::{update_snapshot, SYSTEM_SNAPSHOT, THIS_BOARD},
+    tasks::{
+        can::send::CAN_SEND,
+        sensors::read_accelerometers_from_mux::{
+            to_raw_accelerometer_data, AccelerometerMuxReadings, MAX_ACCELEROMETER_MUX_RECEIVERS,
+        },
+    },
+};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, watch::Receiver};
+use embassy_time::Instant;
+use hyped_communications::{data::CanData, measurements::MeasurementReading, messages::CanMessage};
+use hyped_core::mqtt_payload::MeasurementValue;
+use hyped_localisation::control::localizer::{Localizer, SensorReadings};
+
+use defmt_rtt as _;
+use panic_probe as _;
+
+/// Task that runs the localiser on every new set of accelerometer readings. The estimated
+/// displacement, velocity, acceleration and localisation mode are sent over CAN after every
+/// iteration, for the state machine's guards and events and for the base station.
+#[embassy_executor::task]
+pub async fn localise(
+    mut readings_receiver: Receiver<
+        'static,
+        CriticalSectionRawMutex,
+        AccelerometerMuxReadings,
+        MAX_ACCELEROMETER_MUX_RECEIVERS,
+    >,
+) -> ! {
+    let mut localizer = Localizer::new();
+
+    loop {
+        let readings = readings_receiver.changed().await;
+        let timestamp_ms = Instant::now().as_millis();
+
+        let accelerometer_data = to_raw_accelerometer_data(&readings);
+        if accelerometer_data.is_none() {
+            defmt::warn!("Localising without the accelerometers, as one could not be read");
+        }
+        let snapshot = SYSTEM_SNAPSHOT.lock(|snapshot| snapshot.get());
+        let estimate = localizer.iteration(
+            timestamp_ms,
+            SensorReadings {
+                accelerometer_data,
+                // The pod can't move while the brakes are clamped
+                stationary: snapshot.brakes_engaged == Some(true),
+                ..Default::default()
+            },
+        );
+
+        let this_board = *THIS_BOARD.get().await;
+        for (measurement_id, value) in estimate.measurements() {
+            CAN_SEND
+                .send(CanMessage::MeasurementReading(MeasurementReading::new(
+                    CanData::F32(value),
+                    this_board,
+                    measurement_id,
+                )))
+                .await;
+            // CAN messages are not received by the board that sends them
+            update_snapshot(|snapshot| {
+                snapshot.update(measurement_id, MeasurementValue::Float(value))
+            });
+        }
+    }
+}
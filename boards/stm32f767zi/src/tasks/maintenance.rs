@@ -0,0 +1,126 @@
+use crate::{
+    board_state::{CURRENT_STATE, EMERGENCY, SYSTEM_SNAPSHOT, THIS_BOARD},
+    emergency,
+    tasks::{
+        can::{receive::INCOMING_ACTUATOR_COMMANDS, send::CAN_SEND},
+        can_to_mqtt::send_command_response,
+        state_machine::{TransitionOutcome, TRANSITION_OUTCOMES},
+    },
+};
+use core::cell::Cell;
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use hyped_communications::{boards::Board, emergency::Reason, messages::CanMessage};
+use hyped_core::mqtt_command::{parse_command, CommandError};
+use hyped_state_machine::{
+    actions::ActionError,
+    maintenance::{ActuatorCommand, Actuators, MaintenanceInterlocks},
+    states::State,
+};
+
+use defmt_rtt as _;
+use panic_probe as _;
+
+/// Name of the actuator command, and of the field holding the command
+pub const ACTUATOR_COMMAND: &str = "actuator";
+
+/// Actuator commands sent in maintenance so far, checked by the safety interlocks
+static INTERLOCKS: Mutex<CriticalSectionRawMutex, Cell<MaintenanceInterlocks>> =
+    Mutex::new(Cell::new(MaintenanceInterlocks::new()));
+
+/// Send an actuator command from the base station to CAN, if the safety interlocks allow it.
+/// The command is e.g. `{"id":"3","actuator":"motor_frequency:20"}`.
+pub async fn send_mqtt_actuator_command_to_can(payload: &[u8]) {
+    let result = parse_command(payload, ACTUATOR_COMMAND).and_then(|command| {
+        let actuator_command = command
+            .value
+            .parse::<ActuatorCommand>()
+            .map_err(|_| command.reject(CommandError::InvalidValue))?;
+        let state = CURRENT_STATE.try_get().unwrap_or(State::Idle);
+        let snapshot = SYSTEM_SNAPSHOT.lock(|snapshot| snapshot.get());
+        INTERLOCKS
+            .lock(|interlocks| {
+                let mut new_interlocks = interlocks.get();
+                let checked = new_interlocks.check(state, &snapshot, actuator_command);
+                if checked.is_ok() {
+                    new_interlocks.apply(actuator_command);
+                    interlocks.set(new_interlocks);
+                }
+                checked
+            })
+            .map_err(|interlock| {
+                defmt::warn!(
+                    "Actuator command {:?} interlocked: {:?}",
+                    actuator_command,
+                    interlock
+                );
+                command.reject(CommandError::Interlocked(interlock.into()))
+            })?;
+        Ok((command.id, actuator_command))
+    });
+
+    match result {
+        Ok((id, command)) => {
+            CAN_SEND
+                .send(CanMessage::ActuatorCommand(Board::Mqtt, command))
+                .await;
+            send_command_response(ACTUATOR_COMMAND, Ok(id)).await;
+        }
+        Err(rejection) => {
+            defmt::warn!("Rejected actuator command: {:?}", rejection);
+            send_command_response(ACTUATOR_COMMAND, Err(rejection)).await;
+        }
+    }
+}
+
+/// Forgets the actuator commands sent whenever the pod leaves maintenance, whether the base
+/// station asked for it or heartbeats were lost. The boards owning the actuators make them safe
+/// themselves, see `ActuatorCommand::SAFE`.
+pub async fn reset_interlocks_after_maintenance() {
+    let mut outcomes = TRANSITION_OUTCOMES
+        .subscriber()
+        .expect("Too many transition outcome subscribers");
+    let mut state = CURRENT_STATE.try_get().unwrap_or(State::Idle);
+
+    loop {
+        let new_state = match outcomes.next_message_pure().await {
+            TransitionOutcome::Accepted(command) => command.to_state,
+            TransitionOutcome::Rejected(_) => continue,
+        };
+        if state == State::Maintenance && new_state != State::Maintenance {
+            defmt::info!("Leaving maintenance, resetting actuator interlocks");
+            INTERLOCKS.lock(|interlocks| {
+                let mut new_interlocks = interlocks.get();
+                new_interlocks.reset();
+                interlocks.set(new_interlocks);
+            });
+        }
+        state = new_state;
+    }
+}
+
+/// Carries out the actuator commands received over CAN.
+/// Embassy tasks can't be generic, so each board with actuators calls this from its own task.
+///
+/// Commands are only carried out in maintenance, apart from those that make the pod safe. A
+/// command that may have left the pod unsafe triggers an emergency.
+pub async fn run_actuator_commands(actuators: &mut impl Actuators) -> ! {
+    let actuator_commands_receiver = INCOMING_ACTUATOR_COMMANDS.receiver();
+
+    loop {
+        let command = actuator_commands_receiver.receive().await;
+        let state = CURRENT_STATE.try_get();
+        if state != Some(State::Maintenance) && !command.is_safe() {
+            defmt::warn!("Ignoring actuator command {:?} in {:?}", command, state);
+            continue;
+        }
+
+        match actuators.actuate(command).await {
+            Ok(()) => defmt::info!("Actuator command {:?} done", command),
+            Err(ActionError::Failed) => defmt::warn!("Actuator command {:?} failed", command),
+            Err(ActionError::Unsafe) => {
+                defmt::error!("Actuator command {:?} left the pod unsafe", command);
+                emergency!(Reason::ActuatorCommandFailed);
+            }
+        }
+    }
+}
@@ -1,21 +1,29 @@
 use super::send::MQTT_SEND;
-use core::str::FromStr;
+use crate::board_state::THIS_BOARD;
 use defmt::debug;
 use defmt_rtt as _;
-use embassy_time::{Duration, Timer};
-use heapless::String;
-use hyped_core::{config::HEARTBEAT_CONFIG, mqtt::MqttMessage, mqtt_topics::MqttTopic};
+use embassy_time::{Duration, Instant, Timer};
+use hyped_core::{
+    config::HEARTBEAT_CONFIG,
+    mqtt::MqttMessage,
+    mqtt_payload::{HeartbeatPayload, JsonPayload},
+    mqtt_topics::MqttTopic,
+};
+use hyped_state_machine::modes::RunMode;
 use panic_probe as _;
 
-/// Sends a heartbeat message to the MQTT broker a
+/// Sends a heartbeat message to the MQTT broker, including the run mode of the pod
 #[embassy_executor::task]
 pub async fn base_station_heartbeat() {
+    let mode = RunMode::from_config().expect("Invalid run mode");
+    let board = *THIS_BOARD.get().await;
+
     loop {
+        let payload = HeartbeatPayload::new(Instant::now().as_millis(), board.into(), mode.into())
+            .to_payload()
+            .expect("Failed to serialise heartbeat payload");
         MQTT_SEND
-            .send(MqttMessage::new(
-                MqttTopic::Heartbeat,
-                String::<512>::from_str("").unwrap(),
-            ))
+            .send(MqttMessage::new(MqttTopic::Heartbeat, payload))
             .await;
 
         debug!("Sent heartbeat message");
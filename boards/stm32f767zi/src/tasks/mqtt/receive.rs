@@ -1,5 +1,11 @@
-use crate::log::log;
-use core::str::FromStr;
+use crate::{
+    board_state::{EMERGENCY, THIS_BOARD},
+    log::log,
+    tasks::{
+        can::send::PRIORITY_CAN_SEND,
+        can_to_mqtt::{send_command_response, STATE_COMMAND},
+    },
+};
 use defmt_rtt as _;
 use embassy_net::{tcp::TcpSocket, Ipv4Address, Stack};
 use embassy_stm32::{
@@ -7,17 +13,22 @@ use embassy_stm32::{
     peripherals::ETH,
 };
 use embassy_sync::{blocking_mutex::raw::ThreadModeRawMutex, channel::Channel};
-use heapless::String;
+use embassy_time::Instant;
+use hyped_communications::{emergency::Reason, messages::CanMessage};
 use hyped_core::{
     config::TELEMETRY_CONFIG,
     format,
     format_string::show,
     log_types::LogLevel,
-    mqtt::{HypedMqttClient, MqttMessage},
+    mqtt::{HypedMqttClient, MqttMessage, MqttPayload},
+    mqtt_command::{parse_command, CommandError},
     mqtt_topics::MqttTopic,
 };
 use panic_probe as _;
 
+/// Name of the remote emergency stop command
+const EMERGENCY_STOP_COMMAND: &str = "emergency_stop";
+
 /// Channel containing messages that have been received from the MQTT broker.
 /// This channel is populated by the `mqtt_recv_task` and can be consumed by other tasks.
 /// Note: excludes heartbeat and log messages
@@ -68,32 +79,62 @@ pub async fn mqtt_receive(
 
     loop {
         match mqtt_client.receive_message().await {
-            Ok((topic_str, message)) => {
+            Ok((topic_str, payload)) => {
                 let topic: Result<MqttTopic, &str> = topic_str.parse();
 
                 match topic {
-                    // Ignore heartbeat and log messages
+                    // Ignore heartbeat and log messages, and our own command responses and
+                    // audit log entries
                     Ok(MqttTopic::Heartbeat) => {}
                     Ok(MqttTopic::Logs) => {}
-                    Ok(topic) => {
+                    Ok(MqttTopic::CommandResponse) => {}
+                    Ok(MqttTopic::StateAudit) => {}
+                    Ok(MqttTopic::EmergencyStop) => {
+                        remote_emergency_stop(Instant::now(), payload).await;
+                    }
+                    Ok(topic) => match MqttPayload::from_bytes(payload) {
                         // Send message to channel so that it can be consumed by other tasks
-                        MQTT_RECEIVE
-                            .send(MqttMessage::new(
-                                topic,
-                                String::from_str(message)
-                                    .expect("Failed to convert message to string"),
-                            ))
+                        Some(payload) => {
+                            MQTT_RECEIVE
+                                .send(MqttMessage {
+                                    topic,
+                                    payload,
+                                    retain: false,
+                                })
+                                .await
+                        }
+                        None => {
+                            log(
+                                LogLevel::Warn,
+                                format!(
+                                    &mut [0u8; 1024],
+                                    "Ignoring message on {}: payload of {} bytes is too large",
+                                    topic_str,
+                                    payload.len()
+                                )
+                                .unwrap_or("Ignoring message: payload is too large"),
+                            )
                             .await;
-                    }
+                            if topic == MqttTopic::State {
+                                send_command_response(
+                                    STATE_COMMAND,
+                                    Err(CommandError::TooLarge.into()),
+                                )
+                                .await;
+                            }
+                        }
+                    },
                     Err(_) => {
                         // Log warning for unknown topic
                         log(
                             LogLevel::Warn,
                             format!(
                                 &mut [0u8; 1024],
-                                "Received message on unknown topic {}: {}", topic_str, message
+                                "Received message on unknown topic {}: {}",
+                                topic_str,
+                                core::str::from_utf8(payload).unwrap_or("<binary>")
                             )
-                            .unwrap(),
+                            .unwrap_or("Received message on unknown topic"),
                         )
                         .await
                     }
@@ -112,3 +153,22 @@ pub async fn mqtt_receive(
         }
     }
 }
+
+/// Stops the pod as quickly as possible, bypassing `MQTT_RECEIVE` and the state machine.
+/// The payload is not required to be valid, as a stop must never be rejected.
+async fn remote_emergency_stop(received_at: Instant, payload: &[u8]) {
+    let this_board = *THIS_BOARD.get().await;
+    PRIORITY_CAN_SEND
+        .send((
+            CanMessage::Emergency(this_board, Reason::RemoteEmergencyStop),
+            received_at,
+        ))
+        .await;
+    // CAN messages are not received by the board that sends them
+    EMERGENCY.sender().send(true);
+
+    let id = parse_command(payload, EMERGENCY_STOP_COMMAND)
+        .ok()
+        .and_then(|command| command.id);
+    send_command_response(EMERGENCY_STOP_COMMAND, Ok(id)).await;
+}
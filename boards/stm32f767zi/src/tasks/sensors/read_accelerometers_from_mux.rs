@@ -13,22 +13,55 @@ use embassy_time::{Duration, Timer};
 use heapless::Vec;
 use hyped_core::config::{LOCALISATION_CONFIG, SENSORS_CONFIG};
 use hyped_i2c::{i2c_mux::DEFAULT_MUX_ADDRESS, HypedI2c};
+use hyped_localisation::{
+    config::{GRAVITY, NUM_AXIS},
+    types::RawAccelerometerData,
+};
 use hyped_sensors::{
     accelerometer::{self, AccelerationValues, Accelerometer, AccelerometerAddresses},
     SensorValueRange::{self},
 };
 const NUM_ACCELEROMETERS: usize = LOCALISATION_CONFIG.accelerometers.num_sensors as usize;
 
+/// Maximum number of tasks receiving the readings, e.g. the calibration and the localiser
+pub const MAX_ACCELEROMETER_MUX_RECEIVERS: usize = 2;
+
+/// Converts the accelerometer readings, in mg, into m/s^2
+const MILLI_G: f32 = GRAVITY / 1000.0;
+
 pub type AccelerometerMuxReadings =
     Vec<Option<SensorValueRange<AccelerationValues>>, NUM_ACCELEROMETERS>;
 
+/// Converts the readings of every accelerometer into m/s^2, if they could all be read
+pub fn to_raw_accelerometer_data(
+    readings: &AccelerometerMuxReadings,
+) -> Option<RawAccelerometerData<NUM_ACCELEROMETERS, NUM_AXIS>> {
+    let mut data = RawAccelerometerData::new();
+    for reading in readings.iter() {
+        let values = match reading.as_ref()? {
+            SensorValueRange::Safe(values)
+            | SensorValueRange::Warning(values)
+            | SensorValueRange::Critical(values) => values,
+        };
+        let axes: Vec<f32, NUM_AXIS> =
+            Vec::from_slice(&[values.x * MILLI_G, values.y * MILLI_G, values.z * MILLI_G]).ok()?;
+        data.push(axes).ok()?;
+    }
+    (data.len() == NUM_ACCELEROMETERS).then_some(data)
+}
+
 type I2c1Bus = Mutex<NoopRawMutex, RefCell<I2c<'static, Blocking>>>;
 
 /// Task that reads the accelerometers on the muxes given in `mux_address_channel_pairs`
 #[embassy_executor::task]
 pub async fn read_accelerometers_from_mux(
     i2c_bus: &'static I2c1Bus,
-    sender: Sender<'static, CriticalSectionRawMutex, AccelerometerMuxReadings, 1>,
+    sender: Sender<
+        'static,
+        CriticalSectionRawMutex,
+        AccelerometerMuxReadings,
+        MAX_ACCELEROMETER_MUX_RECEIVERS,
+    >,
 ) -> ! {
     let mut hyped_i2c = Stm32f767ziI2c::new(i2c_bus);
 
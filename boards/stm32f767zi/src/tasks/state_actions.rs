@@ -0,0 +1,58 @@
+use crate::{
+    board_state::{CURRENT_STATE, EMERGENCY, THIS_BOARD},
+    emergency,
+    tasks::can::{receive::INCOMING_STATE_ACTION_FAILURES, send::CAN_SEND},
+};
+use hyped_communications::{
+    emergency::Reason, messages::CanMessage, state_transition::StateActionFailed,
+};
+use hyped_state_machine::actions::{StateAction, StateActions};
+
+use defmt_rtt as _;
+use panic_probe as _;
+
+/// Runs a board's state actions whenever the pod changes state.
+/// Embassy tasks can't be generic, so each board calls this from its own task with its actions.
+///
+/// The pod has already changed state when the actions run, so a failed action is reported with a
+/// `StateActionFailed` message. The leading state machine then rejects the transition and moves
+/// the pod back to the state it came from. An action that leaves the pod unsafe triggers an
+/// emergency instead.
+pub async fn run_state_actions<A: StateAction, const N: usize>(
+    mut actions: StateActions<A, N>,
+) -> ! {
+    let mut current_state = CURRENT_STATE
+        .receiver()
+        .expect("Too many current state receivers");
+    let mut state = current_state.get().await;
+
+    loop {
+        let new_state = current_state.changed().await;
+        if new_state == state {
+            continue;
+        }
+
+        if let Err(failure) = actions.transition(state, new_state).await {
+            defmt::error!(
+                "State action failed moving from {:?} to {:?}: {:?}",
+                state,
+                new_state,
+                failure
+            );
+            if failure.requires_emergency() {
+                emergency!(Reason::StateActionFailed);
+            } else {
+                let failure =
+                    StateActionFailed::new(*THIS_BOARD.get().await, state, new_state, failure);
+                CAN_SEND
+                    .send(CanMessage::StateActionFailed(failure.clone()))
+                    .await;
+                // CAN messages are not received by the board that sends them
+                INCOMING_STATE_ACTION_FAILURES
+                    .immediate_publisher()
+                    .publish_immediate(failure);
+            }
+        }
+        state = new_state;
+    }
+}
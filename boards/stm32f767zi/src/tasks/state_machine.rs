@@ -1,59 +1,501 @@
-use super::can::receive::INCOMING_STATE_TRANSITION_COMMANDS;
+use super::can::receive::{
+    ObservedHeartbeatSubscriber, INCOMING_STATE_ACTION_FAILURES,
+    INCOMING_STATE_TRANSITION_COMMANDS, OBSERVED_HEARTBEATS,
+};
 use crate::{
-    board_state::{CURRENT_STATE, THIS_BOARD},
+    board_state::{set_heartbeat_healthy, CURRENT_STATE, SYSTEM_SNAPSHOT, THIS_BOARD},
+    log::log,
     tasks::can::{receive::INCOMING_STATE_TRANSITION_REQUESTS, send::CAN_SEND},
 };
-use hyped_communications::{messages::CanMessage, state_transition::StateTransitionCommand};
-use hyped_state_machine::state_machine::StateMachine;
+use core::cell::RefCell;
+use embassy_futures::select::{select, select4, Either, Either4};
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex},
+    channel::Channel,
+    pubsub::{PubSubChannel, Subscriber},
+};
+use embassy_time::{with_timeout, Duration, Instant, Ticker, TimeoutError, Timer};
+use hyped_communications::{
+    boards::Board,
+    messages::CanMessage,
+    state_transition::{
+        AuditRequester, StateActionFailed, StateTransitionAudited, StateTransitionCommand,
+        StateTransitionRejected,
+    },
+};
+use hyped_core::{format, format_string::show, log_types::LogLevel};
+use hyped_state_machine::{
+    audit::{AuditEntry, AuditLog, AuditOutcome},
+    authorisation::AuthorisationPolicy,
+    config::{AUDIT_LOG_SIZE, CHECK_PERIOD_MS, RECOVERY_WINDOW_MS, REQUEST_TIMEOUT_MS},
+    events, guards,
+    modes::RunMode,
+    redundancy::{HeartbeatMonitor, LeaderElection, Role, StateRecovery},
+    state_machine::{RejectionReason, StateMachine, TransitionRejection},
+    states::State,
+    timeouts,
+};
 
 use defmt_rtt as _;
 use panic_probe as _;
 
+/// The result of a state transition request
+#[derive(Debug, Clone, PartialEq, defmt::Format)]
+pub enum TransitionOutcome {
+    /// The state machine commanded a transition
+    Accepted(StateTransitionCommand),
+    Rejected(StateTransitionRejected),
+}
+
+const MAX_OUTCOME_SUBSCRIBERS: usize = 4;
+
+/// Outcomes of state transition requests, from CAN or from the state machine on this board.
+/// Every subscriber sees every outcome.
+pub static TRANSITION_OUTCOMES: PubSubChannel<
+    CriticalSectionRawMutex,
+    TransitionOutcome,
+    4,
+    MAX_OUTCOME_SUBSCRIBERS,
+    1,
+> = PubSubChannel::new();
+
+pub type TransitionOutcomeSubscriber =
+    Subscriber<'static, CriticalSectionRawMutex, TransitionOutcome, 4, MAX_OUTCOME_SUBSCRIBERS, 1>;
+
+/// The latest state transitions and requests handled by the state machine, whether on this board
+/// or on the leader, for analysis after a run
+pub static AUDIT_LOG: Mutex<CriticalSectionRawMutex, RefCell<AuditLog<AUDIT_LOG_SIZE>>> =
+    Mutex::new(RefCell::new(AuditLog::new()));
+
+/// New entries of `AUDIT_LOG`, to be sent to the base station
+pub static AUDIT_ENTRIES: Channel<CriticalSectionRawMutex, AuditEntry, 8> = Channel::new();
+
+/// The latest transition made by the leader, which is reverted if a board can't run its state
+/// actions for it
+#[derive(Clone, Copy)]
+struct Transition {
+    from: State,
+    to: State,
+    /// Recorded in the audit log, see `AuditEntry::requester`
+    requester: AuditRequester,
+    /// The board told when the transition is reverted, if a board requested it
+    requesting_board: Option<Board>,
+}
+
+/// Waits for the outcome of a request made by `requesting_board` to transition to `to_state`.
+/// Transitions made for other boards, or by the state machine itself, are not the outcome.
+/// Subscribe before sending the request, so that the outcome can't be missed.
+/// Gives up after `REQUEST_TIMEOUT_MS`, e.g. when no state machine is running.
+pub async fn wait_for_outcome(
+    outcomes: &mut TransitionOutcomeSubscriber,
+    requesting_board: Board,
+    to_state: State,
+) -> Result<TransitionOutcome, TimeoutError> {
+    with_timeout(
+        Duration::from_millis(REQUEST_TIMEOUT_MS),
+        next_outcome(outcomes, requesting_board, to_state),
+    )
+    .await
+}
+
+async fn next_outcome(
+    outcomes: &mut TransitionOutcomeSubscriber,
+    requesting_board: Board,
+    to_state: State,
+) -> TransitionOutcome {
+    loop {
+        match outcomes.next_message_pure().await {
+            TransitionOutcome::Accepted(command)
+                if command.requesting_board == Some(requesting_board)
+                    && command.to_state == to_state =>
+            {
+                return TransitionOutcome::Accepted(command)
+            }
+            TransitionOutcome::Rejected(rejection)
+                if rejection.requesting_board == requesting_board
+                    && rejection.requested_state == to_state =>
+            {
+                return TransitionOutcome::Rejected(rejection)
+            }
+            _ => {}
+        }
+    }
+}
+
 /// Handles the state machine logic by receiving state transition requests and sending new states.
-/// Should only be run on one board.
+/// Also moves the pod on by itself when it times out in a state, or when an event is detected.
+///
+/// Runs on every board listed in `redundancy` in `config/state_machine.yaml`, in place of
+/// `state_updater`. Only the elected leader handles requests; the others follow the state
+/// transition commands sent by the leader and take over if its heartbeats stop. After booting,
+/// the state is recovered from the heartbeats of the other boards rather than starting in `Idle`.
+/// Any other board running this task is the only candidate, so it always leads.
+///
+/// Every candidate monitors the heartbeats of the other boards, whether it leads or not, so that
+/// the heartbeat guards and events work straight after a takeover. Missing heartbeats never
+/// trigger an emergency here, see `heartbeat_listener`.
+///
+/// If a board can't run its state actions for the latest transition, the leader rejects it and
+/// moves the pod back to the state it came from, see `run_state_actions`.
 #[embassy_executor::task]
 pub async fn state_machine() {
-    // Initialise the state machine with the initial state
-    let mut state_machine = StateMachine::new();
+    // Initialise the state machine with the initial state and the transitions of the run mode
+    let mode = RunMode::from_config().expect("Invalid run mode");
+    let mut state_machine = StateMachine::new(mode);
+    guards::add_default_guards(&mut state_machine).expect("Too many default guards");
+    timeouts::add_configured_timeouts(&mut state_machine).expect("Invalid state timeouts");
+    events::add_configured_event_transitions(&mut state_machine)
+        .expect("Invalid event transitions");
+    let policy = AuthorisationPolicy::from_config();
+
+    let mut heartbeats = OBSERVED_HEARTBEATS
+        .subscriber()
+        .expect("Too many heartbeat subscribers");
+    let mut action_failures = INCOMING_STATE_ACTION_FAILURES
+        .subscriber()
+        .expect("Too many state action failure subscribers");
+
+    // Carry on from the state the other boards are in, in case this board rebooted mid-run
+    let recovered_state = recover_state(mode, &mut heartbeats).await;
+    state_machine.follow(recovered_state);
+    CURRENT_STATE.sender().send(recovered_state);
+    defmt::info!("Starting state machine in {:?}", recovered_state);
 
-    let state_sender = CURRENT_STATE.sender();
+    let this_board: &str = (*THIS_BOARD.get().await).into();
+    let mut election = LeaderElection::from_config(this_board, Instant::now().as_millis())
+        .expect("Invalid state machine candidates");
+    let mut role = election.role(Instant::now().as_millis());
+    log_role(role).await;
+    let mut heartbeat_monitor = HeartbeatMonitor::from_config();
 
     let incoming_state_transition_requests = INCOMING_STATE_TRANSITION_REQUESTS.receiver();
+    let incoming_state_transitions = INCOMING_STATE_TRANSITION_COMMANDS.receiver();
     let can_sender = CAN_SEND.sender();
+    let outcome_publisher = TRANSITION_OUTCOMES.immediate_publisher();
+    let mut ticker = Ticker::every(Duration::from_millis(CHECK_PERIOD_MS));
+    let mut entered_at = Instant::now();
+    let mut last_transition: Option<Transition> = None;
 
     loop {
-        let state_transition = incoming_state_transition_requests.receive().await;
-        let to_state = state_transition.to_state;
+        match select4(
+            incoming_state_transition_requests.receive(),
+            ticker.next(),
+            incoming_state_transitions.receive(),
+            heartbeats.next_message_pure(),
+        )
+        .await
+        {
+            // Requests are answered by the leader
+            Either4::First(_) if role == Role::Standby => {}
+            Either4::First(state_transition) => {
+                let from_state = state_machine.current_state;
+                let to_state = state_transition.to_state;
+                let requesting_board = state_transition.requesting_board;
+                let snapshot = SYSTEM_SNAPSHOT.lock(|snapshot| snapshot.get());
+
+                let result = if policy.allows(requesting_board.into(), to_state) {
+                    state_machine.handle_transition(&to_state, &snapshot)
+                } else {
+                    log_unauthorised_request(
+                        requesting_board,
+                        state_machine.current_state,
+                        to_state,
+                    )
+                    .await;
+                    Err(TransitionRejection {
+                        from: state_machine.current_state,
+                        to: to_state,
+                        reason: RejectionReason::Unauthorised,
+                    })
+                };
 
-        let new_state = state_machine.handle_transition(&to_state);
+                match result {
+                    Ok(state) => {
+                        defmt::info!("State transition successful. New state: {:?}", state);
+                        audit(
+                            requesting_board.into(),
+                            from_state,
+                            state,
+                            AuditOutcome::Accepted,
+                        )
+                        .await;
+                        entered_at = Instant::now();
+                        last_transition = Some(Transition {
+                            from: from_state,
+                            to: state,
+                            requester: requesting_board.into(),
+                            requesting_board: Some(requesting_board),
+                        });
+                        enter_state(state, Some(requesting_board)).await;
+                    }
+                    Err(rejection) => {
+                        log_rejection(&rejection);
+                        audit(
+                            requesting_board.into(),
+                            rejection.from,
+                            rejection.to,
+                            AuditOutcome::Rejected(rejection.reason),
+                        )
+                        .await;
+
+                        // Let the requesting board know why its request failed
+                        let rejection = StateTransitionRejected::new(
+                            *THIS_BOARD.get().await,
+                            requesting_board,
+                            rejection.from,
+                            rejection.to,
+                            rejection.reason,
+                        );
+                        can_sender
+                            .send(CanMessage::StateTransitionRejected(rejection.clone()))
+                            .await;
+                        outcome_publisher.publish_immediate(TransitionOutcome::Rejected(rejection));
+                    }
+                }
+            }
+            Either4::Second(()) => {
+                let now_ms = Instant::now().as_millis();
+                for (board, healthy) in heartbeat_monitor.statuses(now_ms) {
+                    set_heartbeat_healthy(board, healthy);
+                }
 
-        match new_state {
-            Some(state) => {
-                defmt::info!("State transition successful. New state: {:?}", state);
+                let new_role = election.role(now_ms);
+                if new_role != role {
+                    role = new_role;
+                    log_role(role).await;
+                    // The time the leader entered the state is not known, so start timing again
+                    entered_at = Instant::now();
+                    last_transition = None;
+                }
+                if role == Role::Standby {
+                    continue;
+                }
 
-                // Update this board's state
-                state_sender.send(state);
+                // Only the latest transition is reverted, so that a failure while moving back
+                // can't move the pod back and forth
+                while let Some(failure) = action_failures.try_next_message_pure() {
+                    let Some(transition) = last_transition else {
+                        continue;
+                    };
+                    if (failure.from_state, failure.to_state) == (transition.from, transition.to) {
+                        last_transition = None;
+                        state_machine.follow(transition.from);
+                        entered_at = Instant::now();
+                        revert_transition(transition, &failure).await;
+                    }
+                }
 
-                // Send the new state to the CAN bus
-                let can_message = CanMessage::StateTransitionCommand(StateTransitionCommand::new(
-                    *THIS_BOARD.get().await,
-                    state,
-                ));
-                can_sender.send(can_message).await;
+                // Check whether the pod has been in this state for too long,
+                // or whether something has happened that moves it on
+                let snapshot = SYSTEM_SNAPSHOT.lock(|snapshot| snapshot.get());
+                let time_in_state_ms = entered_at.elapsed().as_millis();
+                let from_state = state_machine.current_state;
+                let new_state = match state_machine.handle_timeout(time_in_state_ms) {
+                    Some(state) => Some((AuditRequester::Timeout, state)),
+                    None => state_machine
+                        .handle_events(&snapshot)
+                        .map(|state| (AuditRequester::Event, state)),
+                };
+                if let Some((requester, state)) = new_state {
+                    audit(requester, from_state, state, AuditOutcome::Accepted).await;
+                    entered_at = Instant::now();
+                    last_transition = Some(Transition {
+                        from: from_state,
+                        to: state,
+                        requester,
+                        requesting_board: None,
+                    });
+                    enter_state(state, None).await;
+                }
             }
-            None => {
-                defmt::error!(
-                    "State transition failed. Invalid transition from {:?} to {:?}",
-                    state_machine.current_state,
-                    to_state
+            // Follow the states commanded by the leader
+            Either4::Third(command) if role == Role::Standby => {
+                if command.to_state != state_machine.current_state {
+                    defmt::info!("Following state: {:?}", command.to_state);
+                    state_machine.follow(command.to_state);
+                    entered_at = Instant::now();
+                    last_transition = None;
+                    CURRENT_STATE.sender().send(command.to_state);
+                }
+            }
+            Either4::Third(command) => {
+                defmt::warn!(
+                    "Ignoring state transition command from {:?}, as this board leads",
+                    command.from_board
                 );
             }
+            // Boards that don't know the state yet can't lead, see `LeaderElection::heartbeat`
+            Either4::Fourth(heartbeat) => {
+                let now_ms = Instant::now().as_millis();
+                if heartbeat.state.is_some() {
+                    election.heartbeat(heartbeat.from.into(), now_ms);
+                }
+                // The other candidates are covered by the election
+                if !election.is_candidate(heartbeat.from.into()) {
+                    heartbeat_monitor.heartbeat(heartbeat.from, now_ms);
+                }
+            }
         }
     }
 }
 
+/// Collects the states in the heartbeats of the other boards for `RECOVERY_WINDOW_MS`, returning
+/// the state the pod is most likely in, see `StateRecovery`
+async fn recover_state(mode: RunMode, heartbeats: &mut ObservedHeartbeatSubscriber) -> State {
+    let mut recovery = StateRecovery::new(mode);
+    let deadline = Instant::now() + Duration::from_millis(RECOVERY_WINDOW_MS);
+    loop {
+        match select(heartbeats.next_message_pure(), Timer::at(deadline)).await {
+            Either::First(heartbeat) => {
+                if let Some(state) = heartbeat.state {
+                    recovery.report(heartbeat.from.into(), state);
+                }
+            }
+            Either::Second(()) => return recovery.state(),
+        }
+    }
+}
+
+/// Lets the base station know whether the state machine on this board is in charge
+async fn log_role(role: Role) {
+    match role {
+        Role::Leader => log(LogLevel::Warn, "State machine is now the leader").await,
+        Role::Standby => log(LogLevel::Info, "State machine is on standby").await,
+    }
+}
+
+/// Tells this board and all other boards that the state machine has entered a new state, in answer
+/// to a request from `requesting_board` if there is one
+async fn enter_state(state: State, requesting_board: Option<Board>) {
+    // Update this board's state
+    CURRENT_STATE.sender().send(state);
+
+    // Send the new state to the CAN bus
+    let command = StateTransitionCommand::new(*THIS_BOARD.get().await, state, requesting_board);
+    CAN_SEND
+        .send(CanMessage::StateTransitionCommand(command.clone()))
+        .await;
+    // CAN messages are not received by the board that sends them
+    TRANSITION_OUTCOMES
+        .immediate_publisher()
+        .publish_immediate(TransitionOutcome::Accepted(command));
+}
+
+/// Rejects a transition that `failure.from_board` could not run its state actions for, moving the
+/// pod back to the state it came from. The requesting board, if any, is told why.
+async fn revert_transition(transition: Transition, failure: &StateActionFailed) {
+    defmt::error!(
+        "Reverting the transition from {:?} to {:?}, as {:?} failed a state action",
+        transition.from,
+        transition.to,
+        failure.from_board
+    );
+    let reason = RejectionReason::ActionFailed;
+    audit(
+        transition.requester,
+        transition.from,
+        transition.to,
+        AuditOutcome::Rejected(reason),
+    )
+    .await;
+    enter_state(transition.from, None).await;
+
+    if let Some(requesting_board) = transition.requesting_board {
+        let rejection = StateTransitionRejected::new(
+            *THIS_BOARD.get().await,
+            requesting_board,
+            transition.from,
+            transition.to,
+            reason,
+        );
+        CAN_SEND
+            .send(CanMessage::StateTransitionRejected(rejection.clone()))
+            .await;
+        TRANSITION_OUTCOMES
+            .immediate_publisher()
+            .publish_immediate(TransitionOutcome::Rejected(rejection));
+    }
+}
+
+/// Records a state transition or request in the audit log, and sends it to the other boards, so
+/// that the board connected to the base station has it whichever board leads
+async fn audit(requester: AuditRequester, from: State, to: State, outcome: AuditOutcome) {
+    record_audit_entry(requester.into(), from, to, outcome);
+    let audited =
+        StateTransitionAudited::new(*THIS_BOARD.get().await, requester, from, to, outcome);
+    CAN_SEND
+        .send(CanMessage::StateTransitionAudited(audited))
+        .await;
+}
+
+/// Records a state transition or request in the audit log of this board, and queues it to be sent
+/// to the base station. Entries from the leader on another board are timestamped when received.
+pub fn record_audit_entry(requester: &'static str, from: State, to: State, outcome: AuditOutcome) {
+    let entry = AUDIT_LOG.lock(|log| {
+        log.borrow_mut()
+            .record(Instant::now().as_millis(), requester, from, to, outcome)
+    });
+    // The entry stays in the log even if it can't be sent, e.g. on boards not connected to MQTT
+    if AUDIT_ENTRIES.try_send(entry).is_err() {
+        defmt::debug!("Audit log entry {} was not sent", entry.sequence);
+    }
+}
+
+fn log_rejection(rejection: &TransitionRejection) {
+    match rejection.reason {
+        RejectionReason::InvalidTransition => {
+            defmt::error!(
+                "State transition failed. Invalid transition from {:?} to {:?}",
+                rejection.from,
+                rejection.to
+            );
+        }
+        RejectionReason::GuardsFailed(failed) => {
+            defmt::error!(
+                "State transition from {:?} to {:?} rejected by guards:",
+                rejection.from,
+                rejection.to
+            );
+            for failure in failed.iter() {
+                defmt::error!("  {:?}", failure);
+            }
+        }
+        RejectionReason::Unauthorised => {
+            defmt::error!(
+                "State transition from {:?} to {:?} rejected, as the requesting board is not authorised",
+                rejection.from,
+                rejection.to
+            );
+        }
+        RejectionReason::ActionFailed => {
+            defmt::error!(
+                "State transition from {:?} to {:?} rejected, as a state action failed",
+                rejection.from,
+                rejection.to
+            );
+        }
+    }
+}
+
+/// Logs a request from a board that may not request the transition as a security event,
+/// so that it is seen at the base station
+async fn log_unauthorised_request(requesting_board: Board, from: State, to: State) {
+    log(
+        LogLevel::Warn,
+        format!(
+            &mut [0u8; 1024],
+            "Security event: board {} is not authorised to request the transition from {} to {}",
+            <&str>::from(requesting_board),
+            <&str>::from(from),
+            <&str>::from(to)
+        )
+        .unwrap_or("Security event: unauthorised state transition request"),
+    )
+    .await;
+}
+
 /// Task that updates the current state of the system by receiving state transitions from the CAN.
-/// Should be run on all boards except the one running the state machine task.
+/// Should be run on all boards except those running the state machine task.
 #[embassy_executor::task]
 pub async fn state_updater() {
     let state_updater = CURRENT_STATE.sender();
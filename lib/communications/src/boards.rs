@@ -16,6 +16,21 @@ impl From<Board> for u8 {
     }
 }
 
+impl From<Board> for &str {
+    fn from(board: Board) -> Self {
+        match board {
+            Board::Telemetry => "telemetry",
+            Board::Navigation => "navigation",
+            Board::Pneumatics => "pneumatics",
+            Board::Test => "test",
+            Board::TemperatureTester => "temperature_tester",
+            Board::KeyenceTester => "keyence_tester",
+            Board::StateMachineTester => "state_machine_tester",
+            Board::Mqtt => "mqtt",
+        }
+    }
+}
+
 impl TryFrom<u8> for Board {
     type Error = &'static str;
 
@@ -68,4 +83,12 @@ mod tests {
         assert_eq!(Board::Mqtt, Board::try_from(Board::Mqtt as u8).unwrap());
         assert_eq!(Board::try_from(8), Err("Invalid Board index"));
     }
+
+    #[test]
+    fn test_board_name() {
+        let name: &str = Board::TemperatureTester.into();
+        assert_eq!(name, "temperature_tester");
+        let name: &str = Board::Mqtt.into();
+        assert_eq!(name, "mqtt");
+    }
 }
@@ -40,7 +40,8 @@ impl CanId {
 
 impl From<CanId> for u32 {
     fn from(val: CanId) -> Self {
-        let priority: u32 = if val.priority { 1 } else { 0 };
+        // CAN arbitration favours lower IDs, so the priority bit is cleared for high priority
+        let priority: u32 = if val.priority { 0 } else { 1 };
         let board: u32 = u8::from(val.board) as u32;
         let message_type: u32 = u8::from(val.message_data_type) as u32;
         let message_identifier: u32 = u16::from(val.message_identifier) as u32;
@@ -48,7 +49,7 @@ impl From<CanId> for u32 {
         // Make sure that measurement_identifier is 13 bits
         assert!(message_identifier < (1 << 13));
 
-        // Format: priority (1 bit) | message_type (8 bits) | message_identifier (12 bits) | board (8 bits) = 29 bits
+        // Format: !priority (1 bit) | message_type (8 bits) | message_identifier (12 bits) | board (8 bits) = 29 bits
         (priority << 28) | (message_type << 20) | (message_identifier << 8) | board
     }
 }
@@ -61,7 +62,7 @@ macro_rules! extract_bits {
 
 impl From<u32> for CanId {
     fn from(id: u32) -> Self {
-        let priority = extract_bits!(id, 28, 29) == 1;
+        let priority = extract_bits!(id, 28, 29) == 0;
         let message_type = CanDataType::try_from(extract_bits!(id, 20, 28) as u8)
             .expect("Failed to decode message type");
         let message_identifier = MessageIdentifier::try_from(extract_bits!(id, 8, 20) as u16)
@@ -104,4 +105,22 @@ mod tests {
 
         assert_eq!(can_id, CanId::from(encoded_can_id));
     }
+
+    #[test]
+    fn high_priority_wins_arbitration() {
+        let high: u32 = CanId::new_high_priority(
+            Board::Test,
+            CanDataType::Emergency,
+            MessageIdentifier::Emergency,
+        )
+        .into();
+        let low: u32 = CanId::new(
+            Board::Telemetry,
+            CanDataType::State,
+            MessageIdentifier::StateTransitionCommand,
+        )
+        .into();
+
+        assert!(high < low);
+    }
 }
@@ -1,9 +1,18 @@
 use core::fmt::Display;
 
+use hyped_core::mqtt_payload::MeasurementValue;
+use hyped_state_machine::{modes::RunMode, states::State};
+
 use crate::emergency::Reason;
 
 use super::boards::Board;
 
+/// Sent in a heartbeat in place of the state, when the sender does not know the current state
+const UNKNOWN_STATE: u8 = u8::MAX;
+
+/// Sent in a state transition command in place of the requesting board, when no board requested it
+const NO_BOARD: u8 = u8::MAX;
+
 #[derive(Debug, Clone, Copy, PartialEq, defmt::Format)]
 pub enum CanData {
     Bool(bool),
@@ -11,8 +20,49 @@ pub enum CanData {
     F32(f32),
     State(u8),
     U32(u32),
-    Heartbeat(Board),
+    /// The board the heartbeat is sent to, and the run mode and current state of the sender
+    Heartbeat(Board, RunMode, Option<State>),
     Emergency(Reason),
+    /// A rejected state transition request, see `StateTransitionRejected`
+    StateTransitionRejected {
+        requesting_board: Board,
+        current_state: u8,
+        requested_state: u8,
+        reason: u8,
+        failed_guards: u16,
+    },
+    /// An actuator command sent in maintenance, see `ActuatorCommand`
+    ActuatorCommand {
+        command: u8,
+        argument: u32,
+    },
+    /// Whether an accelerometer passed calibration
+    AccelerometerCalibration {
+        accelerometer: u8,
+        passed: bool,
+    },
+    /// A state action that failed, see `StateActionFailed`
+    StateActionFailed {
+        from_state: u8,
+        to_state: u8,
+        action_state: u8,
+        hook: u8,
+        error: u8,
+    },
+    /// A state transition command, and the board whose request it answers, see
+    /// `StateTransitionCommand`
+    StateTransitionCommand {
+        to_state: u8,
+        requesting_board: Option<Board>,
+    },
+    /// An entry of the leading state machine's audit log, see `StateTransitionAudited`
+    StateTransitionAudited {
+        requester: u8,
+        from_state: u8,
+        to_state: u8,
+        outcome: u8,
+        failed_guards: u16,
+    },
 }
 
 impl Display for CanData {
@@ -23,8 +73,74 @@ impl Display for CanData {
             CanData::F32(f) => write!(formatter, "{f}"),
             CanData::State(s) => write!(formatter, "{s}"),
             CanData::U32(u) => write!(formatter, "{u}"),
-            CanData::Heartbeat(board) => write!(formatter, "{board:?}"),
+            CanData::Heartbeat(board, mode, state) => {
+                write!(formatter, "{board:?} {mode:?} {state:?}")
+            }
             CanData::Emergency(reason) => write!(formatter, "{reason:?}"),
+            CanData::StateTransitionRejected {
+                requesting_board,
+                current_state,
+                requested_state,
+                reason,
+                failed_guards,
+            } => write!(
+                formatter,
+                "{requesting_board:?}: {current_state} -> {requested_state} ({reason}, {failed_guards:#06x})"
+            ),
+            CanData::ActuatorCommand { command, argument } => {
+                write!(formatter, "{command} ({argument})")
+            }
+            CanData::AccelerometerCalibration {
+                accelerometer,
+                passed,
+            } => write!(formatter, "{accelerometer}: {passed}"),
+            CanData::StateActionFailed {
+                from_state,
+                to_state,
+                action_state,
+                hook,
+                error,
+            } => write!(
+                formatter,
+                "{from_state} -> {to_state}: {action_state} ({hook}, {error})"
+            ),
+            CanData::StateTransitionCommand {
+                to_state,
+                requesting_board,
+            } => write!(formatter, "{to_state} ({requesting_board:?})"),
+            CanData::StateTransitionAudited {
+                requester,
+                from_state,
+                to_state,
+                outcome,
+                failed_guards,
+            } => write!(
+                formatter,
+                "{requester}: {from_state} -> {to_state} ({outcome}, {failed_guards:#06x})"
+            ),
+        }
+    }
+}
+
+impl TryFrom<CanData> for MeasurementValue {
+    type Error = &'static str;
+
+    /// Gets the value of a measurement reading, for sending to the base station
+    fn try_from(val: CanData) -> Result<Self, Self::Error> {
+        match val {
+            CanData::Bool(b) => Ok(MeasurementValue::Bool(b)),
+            CanData::TwoU16(u16s) => Ok(MeasurementValue::Pair(u16s)),
+            CanData::F32(f) => Ok(MeasurementValue::Float(f)),
+            CanData::U32(u) => Ok(MeasurementValue::Integer(u)),
+            CanData::State(_)
+            | CanData::Heartbeat(..)
+            | CanData::Emergency(_)
+            | CanData::StateTransitionRejected { .. }
+            | CanData::ActuatorCommand { .. }
+            | CanData::AccelerometerCalibration { .. }
+            | CanData::StateActionFailed { .. }
+            | CanData::StateTransitionCommand { .. }
+            | CanData::StateTransitionAudited { .. } => Err("CanData is not a measurement value"),
         }
     }
 }
@@ -38,8 +154,14 @@ impl From<CanData> for u8 {
             CanData::F32(_) => 2,
             CanData::State(_) => 3,
             CanData::U32(_) => 4,
-            CanData::Heartbeat(_) => 5,
+            CanData::Heartbeat(..) => 5,
             CanData::Emergency(_) => 6,
+            CanData::StateTransitionRejected { .. } => 7,
+            CanData::ActuatorCommand { .. } => 8,
+            CanData::AccelerometerCalibration { .. } => 9,
+            CanData::StateActionFailed { .. } => 10,
+            CanData::StateTransitionCommand { .. } => 11,
+            CanData::StateTransitionAudited { .. } => 12,
         }
     }
 }
@@ -53,8 +175,41 @@ impl From<u8> for CanData {
             2 => CanData::F32(0.0),
             3 => CanData::State(0),
             4 => CanData::U32(0),
-            5 => CanData::Heartbeat(Board::Test),
+            5 => CanData::Heartbeat(Board::Test, RunMode::default(), None),
             6 => CanData::Emergency(Reason::Unknown),
+            7 => CanData::StateTransitionRejected {
+                requesting_board: Board::Test,
+                current_state: 0,
+                requested_state: 0,
+                reason: 0,
+                failed_guards: 0,
+            },
+            8 => CanData::ActuatorCommand {
+                command: 0,
+                argument: 0,
+            },
+            9 => CanData::AccelerometerCalibration {
+                accelerometer: 0,
+                passed: false,
+            },
+            10 => CanData::StateActionFailed {
+                from_state: 0,
+                to_state: 0,
+                action_state: 0,
+                hook: 0,
+                error: 0,
+            },
+            11 => CanData::StateTransitionCommand {
+                to_state: 0,
+                requesting_board: None,
+            },
+            12 => CanData::StateTransitionAudited {
+                requester: 0,
+                from_state: 0,
+                to_state: 0,
+                outcome: 0,
+                failed_guards: 0,
+            },
             _ => panic!("Invalid CanData index"),
         }
     }
@@ -100,10 +255,12 @@ impl From<CanData> for [u8; 8] {
                 data[1..5].copy_from_slice(&u32_bytes);
                 data
             }
-            CanData::Heartbeat(board) => {
+            CanData::Heartbeat(board, mode, state) => {
                 let mut data: [u8; 8] = [0; 8];
                 data[0] = val.into();
                 data[1] = board.into();
+                data[2] = mode.into();
+                data[3] = state.map_or(UNKNOWN_STATE, u8::from);
                 data
             }
             CanData::Emergency(reason) => {
@@ -112,6 +269,81 @@ impl From<CanData> for [u8; 8] {
                 data[1] = reason as u8;
                 data
             }
+            CanData::StateTransitionRejected {
+                requesting_board,
+                current_state,
+                requested_state,
+                reason,
+                failed_guards,
+            } => {
+                let mut data: [u8; 8] = [0; 8];
+                data[0] = val.into();
+                data[1] = requesting_board.into();
+                data[2] = current_state;
+                data[3] = requested_state;
+                data[4] = reason;
+                data[5..7].copy_from_slice(&failed_guards.to_le_bytes());
+                data
+            }
+            CanData::ActuatorCommand { command, argument } => {
+                let mut data: [u8; 8] = [0; 8];
+                data[0] = val.into();
+                data[1] = command;
+                data[2..6].copy_from_slice(&argument.to_le_bytes());
+                data
+            }
+            CanData::AccelerometerCalibration {
+                accelerometer,
+                passed,
+            } => {
+                let mut data: [u8; 8] = [0; 8];
+                data[0] = val.into();
+                data[1] = accelerometer;
+                data[2] = passed as u8;
+                data
+            }
+            CanData::StateActionFailed {
+                from_state,
+                to_state,
+                action_state,
+                hook,
+                error,
+            } => {
+                let mut data: [u8; 8] = [0; 8];
+                data[0] = val.into();
+                data[1] = from_state;
+                data[2] = to_state;
+                data[3] = action_state;
+                data[4] = hook;
+                data[5] = error;
+                data
+            }
+            CanData::StateTransitionCommand {
+                to_state,
+                requesting_board,
+            } => {
+                let mut data: [u8; 8] = [0; 8];
+                data[0] = val.into();
+                data[1] = to_state;
+                data[2] = requesting_board.map_or(NO_BOARD, u8::from);
+                data
+            }
+            CanData::StateTransitionAudited {
+                requester,
+                from_state,
+                to_state,
+                outcome,
+                failed_guards,
+            } => {
+                let mut data: [u8; 8] = [0; 8];
+                data[0] = val.into();
+                data[1] = requester;
+                data[2] = from_state;
+                data[3] = to_state;
+                data[4] = outcome;
+                data[5..7].copy_from_slice(&failed_guards.to_le_bytes());
+                data
+            }
         }
     }
 }
@@ -144,8 +376,45 @@ impl From<[u8; 8]> for CanData {
                 let u = u32::from_le_bytes(u32_bytes);
                 CanData::U32(u)
             }
-            CanData::Heartbeat(_) => CanData::Heartbeat(data[1].try_into().unwrap()),
+            CanData::Heartbeat(..) => CanData::Heartbeat(
+                data[1].try_into().unwrap(),
+                data[2].try_into().unwrap(),
+                State::try_from(data[3]).ok(),
+            ),
             CanData::Emergency(_) => CanData::Emergency(data[1].try_into().unwrap()),
+            CanData::StateTransitionRejected { .. } => CanData::StateTransitionRejected {
+                requesting_board: data[1].try_into().unwrap(),
+                current_state: data[2],
+                requested_state: data[3],
+                reason: data[4],
+                failed_guards: u16::from_le_bytes([data[5], data[6]]),
+            },
+            CanData::ActuatorCommand { .. } => CanData::ActuatorCommand {
+                command: data[1],
+                argument: u32::from_le_bytes([data[2], data[3], data[4], data[5]]),
+            },
+            CanData::AccelerometerCalibration { .. } => CanData::AccelerometerCalibration {
+                accelerometer: data[1],
+                passed: data[2] != 0,
+            },
+            CanData::StateActionFailed { .. } => CanData::StateActionFailed {
+                from_state: data[1],
+                to_state: data[2],
+                action_state: data[3],
+                hook: data[4],
+                error: data[5],
+            },
+            CanData::StateTransitionCommand { .. } => CanData::StateTransitionCommand {
+                to_state: data[1],
+                requesting_board: Board::try_from(data[2]).ok(),
+            },
+            CanData::StateTransitionAudited { .. } => CanData::StateTransitionAudited {
+                requester: data[1],
+                from_state: data[2],
+                to_state: data[3],
+                outcome: data[4],
+                failed_guards: u16::from_le_bytes([data[5], data[6]]),
+            },
         }
     }
 }
@@ -161,6 +430,12 @@ pub enum CanDataType {
     U32 = 4,
     Heartbeat = 5,
     Emergency = 6,
+    StateTransitionRejected = 7,
+    ActuatorCommand = 8,
+    AccelerometerCalibration = 9,
+    StateActionFailed = 10,
+    StateTransitionCommand = 11,
+    StateTransitionAudited = 12,
 }
 
 impl From<CanDataType> for u8 {
@@ -181,6 +456,12 @@ impl TryFrom<u8> for CanDataType {
             4 => Ok(CanDataType::U32),
             5 => Ok(CanDataType::Heartbeat),
             6 => Ok(CanDataType::Emergency),
+            7 => Ok(CanDataType::StateTransitionRejected),
+            8 => Ok(CanDataType::ActuatorCommand),
+            9 => Ok(CanDataType::AccelerometerCalibration),
+            10 => Ok(CanDataType::StateActionFailed),
+            11 => Ok(CanDataType::StateTransitionCommand),
+            12 => Ok(CanDataType::StateTransitionAudited),
             _ => Err("Invalid CanDataType index"),
         }
     }
@@ -194,8 +475,14 @@ impl From<CanData> for CanDataType {
             CanData::F32(_) => CanDataType::F32,
             CanData::State(_) => CanDataType::State,
             CanData::U32(_) => CanDataType::F32,
-            CanData::Heartbeat(_) => CanDataType::Heartbeat,
+            CanData::Heartbeat(..) => CanDataType::Heartbeat,
             CanData::Emergency(_) => CanDataType::Emergency,
+            CanData::StateTransitionRejected { .. } => CanDataType::StateTransitionRejected,
+            CanData::ActuatorCommand { .. } => CanDataType::ActuatorCommand,
+            CanData::AccelerometerCalibration { .. } => CanDataType::AccelerometerCalibration,
+            CanData::StateActionFailed { .. } => CanDataType::StateActionFailed,
+            CanData::StateTransitionCommand { .. } => CanDataType::StateTransitionCommand,
+            CanData::StateTransitionAudited { .. } => CanDataType::StateTransitionAudited,
         }
     }
 }
@@ -208,8 +495,41 @@ impl From<CanDataType> for CanData {
             CanDataType::F32 => CanData::F32(0.0),
             CanDataType::State => CanData::State(0),
             CanDataType::U32 => CanData::U32(0),
-            CanDataType::Heartbeat => CanData::Heartbeat(Board::Test),
+            CanDataType::Heartbeat => CanData::Heartbeat(Board::Test, RunMode::default(), None),
             CanDataType::Emergency => CanData::Emergency(Reason::Unknown),
+            CanDataType::StateTransitionRejected => CanData::StateTransitionRejected {
+                requesting_board: Board::Test,
+                current_state: 0,
+                requested_state: 0,
+                reason: 0,
+                failed_guards: 0,
+            },
+            CanDataType::ActuatorCommand => CanData::ActuatorCommand {
+                command: 0,
+                argument: 0,
+            },
+            CanDataType::AccelerometerCalibration => CanData::AccelerometerCalibration {
+                accelerometer: 0,
+                passed: false,
+            },
+            CanDataType::StateActionFailed => CanData::StateActionFailed {
+                from_state: 0,
+                to_state: 0,
+                action_state: 0,
+                hook: 0,
+                error: 0,
+            },
+            CanDataType::StateTransitionCommand => CanData::StateTransitionCommand {
+                to_state: 0,
+                requesting_board: None,
+            },
+            CanDataType::StateTransitionAudited => CanData::StateTransitionAudited {
+                requester: 0,
+                from_state: 0,
+                to_state: 0,
+                outcome: 0,
+                failed_guards: 0,
+            },
         }
     }
 }
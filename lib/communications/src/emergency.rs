@@ -9,6 +9,12 @@ pub enum Reason {
     MissingHeartbeat = 5,
     TemperatureUpperLimitFailure = 6,
     TemperatureLowerLimitFailure = 7,
+    /// Stop commanded by an operator at the base station
+    RemoteEmergencyStop = 8,
+    /// A state action left the pod unsafe, see `hyped_state_machine::actions`
+    StateActionFailed = 9,
+    /// An actuator command sent in maintenance may have left the pod unsafe
+    ActuatorCommandFailed = 10,
 }
 
 impl TryFrom<u8> for Reason {
@@ -23,6 +29,9 @@ impl TryFrom<u8> for Reason {
             5 => Ok(Reason::MissingHeartbeat),
             6 => Ok(Reason::TemperatureUpperLimitFailure),
             7 => Ok(Reason::TemperatureLowerLimitFailure),
+            8 => Ok(Reason::RemoteEmergencyStop),
+            9 => Ok(Reason::StateActionFailed),
+            10 => Ok(Reason::ActuatorCommandFailed),
             _ => Err("Invalid reason for emergency stop"),
         }
     }
@@ -59,9 +68,21 @@ mod tests {
             Reason::TemperatureLowerLimitFailure,
             Reason::try_from(Reason::TemperatureLowerLimitFailure as u8).unwrap()
         );
+        assert_eq!(
+            Reason::RemoteEmergencyStop,
+            Reason::try_from(Reason::RemoteEmergencyStop as u8).unwrap()
+        );
+        assert_eq!(
+            Reason::StateActionFailed,
+            Reason::try_from(Reason::StateActionFailed as u8).unwrap()
+        );
+        assert_eq!(
+            Reason::ActuatorCommandFailed,
+            Reason::try_from(Reason::ActuatorCommandFailed as u8).unwrap()
+        );
         assert_eq!(
             Err("Invalid reason for emergency stop"),
-            Reason::try_from(8)
+            Reason::try_from(11)
         );
     }
 }
@@ -1,13 +1,24 @@
 use super::boards::Board;
+use hyped_state_machine::{modes::RunMode, states::State};
 
 #[derive(Debug, defmt::Format, Clone, Copy, PartialEq)]
 pub struct Heartbeat {
     pub to: Board,
     pub from: Board,
+    /// The run mode the sending board is configured with
+    pub mode: RunMode,
+    /// The state the sending board is in, or `None` if it does not know yet, e.g. just after
+    /// booting
+    pub state: Option<State>,
 }
 
 impl Heartbeat {
-    pub fn new(to: Board, from: Board) -> Self {
-        Self { to, from }
+    pub fn new(to: Board, from: Board, mode: RunMode, state: Option<State>) -> Self {
+        Self {
+            to,
+            from,
+            mode,
+            state,
+        }
     }
 }
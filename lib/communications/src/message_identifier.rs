@@ -7,6 +7,11 @@ pub enum MessageIdentifier {
     StateTransitionRequest,
     Heartbeat,
     Emergency,
+    StateTransitionRejected,
+    ActuatorCommand,
+    AccelerometerCalibration,
+    StateActionFailed,
+    StateTransitionAudited,
 }
 
 // 12 bits
@@ -16,6 +21,11 @@ const STATE_TRANSITION_COMMAND_ID: u16 = MAX_MESSAGE_IDENTIFIER - 1;
 const STATE_TRANSITION_REQUEST_ID: u16 = MAX_MESSAGE_IDENTIFIER - 2;
 const HEARTBEAT_ID: u16 = MAX_MESSAGE_IDENTIFIER - 3;
 const EMERGENCY_ID: u16 = MAX_MESSAGE_IDENTIFIER - 4;
+const STATE_TRANSITION_REJECTED_ID: u16 = MAX_MESSAGE_IDENTIFIER - 5;
+const ACTUATOR_COMMAND_ID: u16 = MAX_MESSAGE_IDENTIFIER - 6;
+const ACCELEROMETER_CALIBRATION_ID: u16 = MAX_MESSAGE_IDENTIFIER - 7;
+const STATE_ACTION_FAILED_ID: u16 = MAX_MESSAGE_IDENTIFIER - 8;
+const STATE_TRANSITION_AUDITED_ID: u16 = MAX_MESSAGE_IDENTIFIER - 9;
 
 impl From<MessageIdentifier> for u16 {
     fn from(val: MessageIdentifier) -> Self {
@@ -25,6 +35,11 @@ impl From<MessageIdentifier> for u16 {
             MessageIdentifier::Heartbeat => HEARTBEAT_ID,
             MessageIdentifier::StateTransitionRequest => STATE_TRANSITION_REQUEST_ID,
             MessageIdentifier::StateTransitionCommand => STATE_TRANSITION_COMMAND_ID,
+            MessageIdentifier::StateTransitionRejected => STATE_TRANSITION_REJECTED_ID,
+            MessageIdentifier::ActuatorCommand => ACTUATOR_COMMAND_ID,
+            MessageIdentifier::AccelerometerCalibration => ACCELEROMETER_CALIBRATION_ID,
+            MessageIdentifier::StateActionFailed => STATE_ACTION_FAILED_ID,
+            MessageIdentifier::StateTransitionAudited => STATE_TRANSITION_AUDITED_ID,
         }
     }
 }
@@ -38,6 +53,11 @@ impl TryFrom<u16> for MessageIdentifier {
             STATE_TRANSITION_REQUEST_ID => Ok(MessageIdentifier::StateTransitionRequest),
             HEARTBEAT_ID => Ok(MessageIdentifier::Heartbeat),
             EMERGENCY_ID => Ok(MessageIdentifier::Emergency),
+            STATE_TRANSITION_REJECTED_ID => Ok(MessageIdentifier::StateTransitionRejected),
+            ACTUATOR_COMMAND_ID => Ok(MessageIdentifier::ActuatorCommand),
+            ACCELEROMETER_CALIBRATION_ID => Ok(MessageIdentifier::AccelerometerCalibration),
+            STATE_ACTION_FAILED_ID => Ok(MessageIdentifier::StateActionFailed),
+            STATE_TRANSITION_AUDITED_ID => Ok(MessageIdentifier::StateTransitionAudited),
             _ => match MeasurementId::try_from(id) {
                 Ok(measurement_id) => Ok(MessageIdentifier::Measurement(measurement_id)),
                 Err(e) => Err(e),
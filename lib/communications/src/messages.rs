@@ -1,7 +1,18 @@
 use hyped_can::HypedCanFrame;
 use hyped_state_machine::states::State;
 
-use crate::{boards::Board, emergency::Reason, state_transition::StateTransitionCommand};
+use hyped_state_machine::{
+    actions::ActionFailure, audit::AuditOutcome, maintenance::ActuatorCommand,
+    state_machine::RejectionReason,
+};
+
+use crate::{
+    boards::Board,
+    emergency::Reason,
+    state_transition::{
+        StateActionFailed, StateTransitionAudited, StateTransitionCommand, StateTransitionRejected,
+    },
+};
 
 use super::{
     can_id::CanId,
@@ -19,6 +30,14 @@ pub enum CanMessage {
     StateTransitionRequest(StateTransitionRequest),
     Heartbeat(Heartbeat),
     Emergency(Board, Reason),
+    StateTransitionRejected(StateTransitionRejected),
+    /// A command to move an actuator in maintenance, and the board that sent it
+    ActuatorCommand(Board, ActuatorCommand),
+    /// The board that calibrated an accelerometer, the index of the accelerometer and whether it
+    /// passed calibration
+    AccelerometerCalibration(Board, u8, bool),
+    StateActionFailed(StateActionFailed),
+    StateTransitionAudited(StateTransitionAudited),
 }
 
 // Converts a CanMessage into a HypedCanFrame ready to be sent over the CAN bus
@@ -38,12 +57,16 @@ impl From<CanMessage> for HypedCanFrame {
             CanMessage::StateTransitionCommand(state_transition) => {
                 let can_id = CanId::new(
                     state_transition.from_board,
-                    CanDataType::State,
+                    CanDataType::StateTransitionCommand,
                     MessageIdentifier::StateTransitionCommand,
                 );
                 HypedCanFrame::new(
                     can_id.into(),
-                    CanData::State(state_transition.to_state.into()).into(),
+                    CanData::StateTransitionCommand {
+                        to_state: state_transition.to_state.into(),
+                        requesting_board: state_transition.requesting_board,
+                    }
+                    .into(),
                 )
             }
             CanMessage::StateTransitionRequest(state_transition) => {
@@ -63,25 +86,104 @@ impl From<CanMessage> for HypedCanFrame {
                     CanDataType::Heartbeat,
                     MessageIdentifier::Heartbeat,
                 );
-                HypedCanFrame::new(can_id.into(), CanData::Heartbeat(heartbeat.to).into())
+                HypedCanFrame::new(
+                    can_id.into(),
+                    CanData::Heartbeat(heartbeat.to, heartbeat.mode, heartbeat.state).into(),
+                )
             }
             CanMessage::Emergency(board, reason) => {
-                let can_id =
-                    CanId::new(board, CanDataType::Emergency, MessageIdentifier::Emergency);
+                // Emergencies must win arbitration against all other traffic on the bus
+                let can_id = CanId::new_high_priority(
+                    board,
+                    CanDataType::Emergency,
+                    MessageIdentifier::Emergency,
+                );
                 HypedCanFrame::new(can_id.into(), CanData::Emergency(reason).into())
             }
+            CanMessage::StateTransitionRejected(rejection) => {
+                let can_id = CanId::new(
+                    rejection.from_board,
+                    CanDataType::StateTransitionRejected,
+                    MessageIdentifier::StateTransitionRejected,
+                );
+                let data = CanData::StateTransitionRejected {
+                    requesting_board: rejection.requesting_board,
+                    current_state: rejection.current_state.into(),
+                    requested_state: rejection.requested_state.into(),
+                    reason: rejection.reason.code(),
+                    failed_guards: rejection.reason.failed_guards().into(),
+                };
+                HypedCanFrame::new(can_id.into(), data.into())
+            }
+            CanMessage::ActuatorCommand(board, command) => {
+                let can_id = CanId::new(
+                    board,
+                    CanDataType::ActuatorCommand,
+                    MessageIdentifier::ActuatorCommand,
+                );
+                let data = CanData::ActuatorCommand {
+                    command: command.code(),
+                    argument: command.argument(),
+                };
+                HypedCanFrame::new(can_id.into(), data.into())
+            }
+            CanMessage::AccelerometerCalibration(board, accelerometer, passed) => {
+                let can_id = CanId::new(
+                    board,
+                    CanDataType::AccelerometerCalibration,
+                    MessageIdentifier::AccelerometerCalibration,
+                );
+                let data = CanData::AccelerometerCalibration {
+                    accelerometer,
+                    passed,
+                };
+                HypedCanFrame::new(can_id.into(), data.into())
+            }
+            CanMessage::StateActionFailed(failure) => {
+                let can_id = CanId::new(
+                    failure.from_board,
+                    CanDataType::StateActionFailed,
+                    MessageIdentifier::StateActionFailed,
+                );
+                let data = CanData::StateActionFailed {
+                    from_state: failure.from_state.into(),
+                    to_state: failure.to_state.into(),
+                    action_state: failure.failure.state.into(),
+                    hook: failure.failure.hook.into(),
+                    error: failure.failure.error.into(),
+                };
+                HypedCanFrame::new(can_id.into(), data.into())
+            }
+            CanMessage::StateTransitionAudited(audited) => {
+                let can_id = CanId::new(
+                    audited.from_board,
+                    CanDataType::StateTransitionAudited,
+                    MessageIdentifier::StateTransitionAudited,
+                );
+                let data = CanData::StateTransitionAudited {
+                    requester: audited.requester.into(),
+                    from_state: audited.from_state.into(),
+                    to_state: audited.to_state.into(),
+                    outcome: audited.outcome.code(),
+                    failed_guards: audited.outcome.failed_guards().into(),
+                };
+                HypedCanFrame::new(can_id.into(), data.into())
+            }
         }
     }
 }
 
-// Converts an incoming HypedCanFrame read from the CAN bus into a CanMessage
-impl From<HypedCanFrame> for CanMessage {
-    fn from(frame: HypedCanFrame) -> Self {
+// Converts an incoming HypedCanFrame read from the CAN bus into a CanMessage, rejecting frames
+// holding invalid data
+impl TryFrom<HypedCanFrame> for CanMessage {
+    type Error = &'static str;
+
+    fn try_from(frame: HypedCanFrame) -> Result<Self, Self::Error> {
         let can_id: CanId = frame.can_id.into();
         let message_identifier = can_id.message_identifier;
         let board = can_id.board;
 
-        match message_identifier {
+        let message = match message_identifier {
             MessageIdentifier::Measurement(measurement_id) => {
                 let reading: CanData = frame.data.into();
                 let measurement_reading = MeasurementReading {
@@ -94,43 +196,128 @@ impl From<HypedCanFrame> for CanMessage {
             MessageIdentifier::StateTransitionCommand => {
                 let reading: CanData = frame.data.into();
                 match reading {
-                    CanData::State(state) => {
-                        let to_state: State = state.try_into().expect("Invalid State!");
-                        let state_transition = StateTransitionCommand::new(board, to_state);
+                    CanData::StateTransitionCommand {
+                        to_state,
+                        requesting_board,
+                    } => {
+                        let to_state: State = to_state.try_into()?;
+                        let state_transition =
+                            StateTransitionCommand::new(board, to_state, requesting_board);
                         CanMessage::StateTransitionCommand(state_transition)
                     }
-                    _ => panic!("Invalid CanData for StateTransition"),
+                    _ => return Err("Invalid CanData for StateTransition"),
                 }
             }
             MessageIdentifier::StateTransitionRequest => {
                 let reading: CanData = frame.data.into();
                 match reading {
                     CanData::State(state) => {
-                        let to_state: State = state.try_into().expect("Invalid State!");
+                        let to_state: State = state.try_into()?;
                         let state_transition = StateTransitionRequest::new(board, to_state);
                         CanMessage::StateTransitionRequest(state_transition)
                     }
-                    _ => panic!("Invalid CanData for StateTransitionRequest"),
+                    _ => return Err("Invalid CanData for StateTransitionRequest"),
                 }
             }
             MessageIdentifier::Heartbeat => {
                 let reading: CanData = frame.data.into();
                 match reading {
-                    CanData::Heartbeat(to) => {
-                        let heartbeat = Heartbeat::new(to, board);
+                    CanData::Heartbeat(to, mode, state) => {
+                        let heartbeat = Heartbeat::new(to, board, mode, state);
                         CanMessage::Heartbeat(heartbeat)
                     }
-                    _ => panic!("Invalid CanData for Heartbeat"),
+                    _ => return Err("Invalid CanData for Heartbeat"),
                 }
             }
             MessageIdentifier::Emergency => {
                 let reading: CanData = frame.data.into();
                 match reading {
                     CanData::Emergency(reason) => CanMessage::Emergency(board, reason),
-                    _ => panic!("Invalid CanData for Emergency"),
+                    _ => return Err("Invalid CanData for Emergency"),
                 }
             }
-        }
+            MessageIdentifier::StateTransitionRejected => {
+                let reading: CanData = frame.data.into();
+                match reading {
+                    CanData::StateTransitionRejected {
+                        requesting_board,
+                        current_state,
+                        requested_state,
+                        reason,
+                        failed_guards,
+                    } => CanMessage::StateTransitionRejected(StateTransitionRejected::new(
+                        board,
+                        requesting_board,
+                        current_state.try_into()?,
+                        requested_state.try_into()?,
+                        RejectionReason::from_code(reason, failed_guards.into())?,
+                    )),
+                    _ => return Err("Invalid CanData for StateTransitionRejected"),
+                }
+            }
+            MessageIdentifier::ActuatorCommand => {
+                let reading: CanData = frame.data.into();
+                match reading {
+                    CanData::ActuatorCommand { command, argument } => CanMessage::ActuatorCommand(
+                        board,
+                        ActuatorCommand::from_parts(command, argument)?,
+                    ),
+                    _ => return Err("Invalid CanData for ActuatorCommand"),
+                }
+            }
+            MessageIdentifier::AccelerometerCalibration => {
+                let reading: CanData = frame.data.into();
+                match reading {
+                    CanData::AccelerometerCalibration {
+                        accelerometer,
+                        passed,
+                    } => CanMessage::AccelerometerCalibration(board, accelerometer, passed),
+                    _ => return Err("Invalid CanData for AccelerometerCalibration"),
+                }
+            }
+            MessageIdentifier::StateActionFailed => {
+                let reading: CanData = frame.data.into();
+                match reading {
+                    CanData::StateActionFailed {
+                        from_state,
+                        to_state,
+                        action_state,
+                        hook,
+                        error,
+                    } => CanMessage::StateActionFailed(StateActionFailed::new(
+                        board,
+                        from_state.try_into()?,
+                        to_state.try_into()?,
+                        ActionFailure {
+                            state: action_state.try_into()?,
+                            hook: hook.try_into()?,
+                            error: error.try_into()?,
+                        },
+                    )),
+                    _ => return Err("Invalid CanData for StateActionFailed"),
+                }
+            }
+            MessageIdentifier::StateTransitionAudited => {
+                let reading: CanData = frame.data.into();
+                match reading {
+                    CanData::StateTransitionAudited {
+                        requester,
+                        from_state,
+                        to_state,
+                        outcome,
+                        failed_guards,
+                    } => CanMessage::StateTransitionAudited(StateTransitionAudited::new(
+                        board,
+                        requester.try_into()?,
+                        from_state.try_into()?,
+                        to_state.try_into()?,
+                        AuditOutcome::from_code(outcome, failed_guards.into())?,
+                    )),
+                    _ => return Err("Invalid CanData for StateTransitionAudited"),
+                }
+            }
+        };
+        Ok(message)
     }
 }
 
@@ -138,7 +325,15 @@ impl From<HypedCanFrame> for CanMessage {
 mod tests {
     use hyped_can::HypedCanFrame;
     use hyped_core::config::MeasurementId;
-    use hyped_state_machine::states::State;
+    use hyped_state_machine::{
+        actions::{ActionError, ActionFailure, ActionHook},
+        audit::AuditOutcome,
+        guards::{FailedGuards, GuardFailure},
+        maintenance::ActuatorCommand,
+        modes::RunMode,
+        state_machine::RejectionReason,
+        states::State,
+    };
 
     use crate::{
         boards::Board,
@@ -146,7 +341,10 @@ mod tests {
         heartbeat::Heartbeat,
         measurements::MeasurementReading,
         messages::CanMessage,
-        state_transition::{StateTransitionCommand, StateTransitionRequest},
+        state_transition::{
+            AuditRequester, StateActionFailed, StateTransitionAudited, StateTransitionCommand,
+            StateTransitionRejected, StateTransitionRequest,
+        },
     };
 
     #[test]
@@ -159,17 +357,27 @@ mod tests {
         let can_message = CanMessage::MeasurementReading(measurement_reading);
 
         let can_frame: HypedCanFrame = can_message.clone().into();
-        let can_message_from_frame: CanMessage = can_frame.into();
+        let can_message_from_frame: CanMessage = can_frame.try_into().unwrap();
 
         assert_eq!(can_message, can_message_from_frame)
     }
 
     #[test]
     fn it_works_state_transition_command() {
-        let state_transition = StateTransitionCommand::new(Board::Test, State::Emergency);
+        let state_transition = StateTransitionCommand::new(Board::Test, State::Emergency, None);
         let state_transition = CanMessage::StateTransitionCommand(state_transition);
         let can_frame: HypedCanFrame = state_transition.clone().into();
-        let can_message_from_frame: CanMessage = can_frame.into();
+        let can_message_from_frame: CanMessage = can_frame.try_into().unwrap();
+        assert_eq!(state_transition, can_message_from_frame)
+    }
+
+    #[test]
+    fn it_works_state_transition_command_with_requesting_board() {
+        let state_transition =
+            StateTransitionCommand::new(Board::Test, State::Accelerate, Some(Board::Telemetry));
+        let state_transition = CanMessage::StateTransitionCommand(state_transition);
+        let can_frame: HypedCanFrame = state_transition.clone().into();
+        let can_message_from_frame: CanMessage = can_frame.try_into().unwrap();
         assert_eq!(state_transition, can_message_from_frame)
     }
 
@@ -178,15 +386,120 @@ mod tests {
         let state_transition = StateTransitionRequest::new(Board::Test, State::Emergency);
         let state_transition = CanMessage::StateTransitionRequest(state_transition);
         let can_frame: HypedCanFrame = state_transition.clone().into();
-        let can_message_from_frame: CanMessage = can_frame.into();
+        let can_message_from_frame: CanMessage = can_frame.try_into().unwrap();
         assert_eq!(state_transition, can_message_from_frame)
     }
 
     #[test]
     fn it_works_heartbeat() {
-        let heartbeat = CanMessage::Heartbeat(Heartbeat::new(Board::KeyenceTester, Board::Test));
+        let heartbeat = CanMessage::Heartbeat(Heartbeat::new(
+            Board::KeyenceTester,
+            Board::Test,
+            RunMode::LevitationOnly,
+            Some(State::Accelerate),
+        ));
+        let can_frame: HypedCanFrame = heartbeat.clone().into();
+        let can_message_from_frame: CanMessage = can_frame.try_into().unwrap();
+        assert_eq!(heartbeat, can_message_from_frame);
+
+        let heartbeat = CanMessage::Heartbeat(Heartbeat::new(
+            Board::KeyenceTester,
+            Board::Test,
+            RunMode::LevitationOnly,
+            None,
+        ));
         let can_frame: HypedCanFrame = heartbeat.clone().into();
-        let can_message_from_frame: CanMessage = can_frame.into();
+        let can_message_from_frame: CanMessage = can_frame.try_into().unwrap();
         assert_eq!(heartbeat, can_message_from_frame)
     }
+
+    #[test]
+    fn it_works_state_transition_rejected() {
+        let mut failed = FailedGuards::default();
+        failed.insert(GuardFailure::BrakesEngaged);
+        failed.insert(GuardFailure::LocalisationInvalid);
+        let rejection = CanMessage::StateTransitionRejected(StateTransitionRejected::new(
+            Board::Telemetry,
+            Board::Mqtt,
+            State::Ready,
+            State::Accelerate,
+            RejectionReason::GuardsFailed(failed),
+        ));
+        let can_frame: HypedCanFrame = rejection.clone().into();
+        let can_message_from_frame: CanMessage = can_frame.try_into().unwrap();
+        assert_eq!(rejection, can_message_from_frame)
+    }
+
+    #[test]
+    fn it_works_actuator_command() {
+        let command =
+            CanMessage::ActuatorCommand(Board::Mqtt, ActuatorCommand::SetMotorFrequency(40));
+        let can_frame: HypedCanFrame = command.clone().into();
+        let can_message_from_frame: CanMessage = can_frame.try_into().unwrap();
+        assert_eq!(command, can_message_from_frame)
+    }
+
+    #[test]
+    fn invalid_actuator_command_is_rejected() {
+        let command = CanMessage::ActuatorCommand(Board::Mqtt, ActuatorCommand::EngageBrakes);
+        let mut can_frame: HypedCanFrame = command.into();
+        can_frame.data[1] = 200;
+        assert_eq!(
+            CanMessage::try_from(can_frame),
+            Err("Invalid actuator command")
+        );
+    }
+
+    #[test]
+    fn it_works_accelerometer_calibration() {
+        let calibration = CanMessage::AccelerometerCalibration(Board::Navigation, 3, true);
+        let can_frame: HypedCanFrame = calibration.clone().into();
+        let can_message_from_frame: CanMessage = can_frame.try_into().unwrap();
+        assert_eq!(calibration, can_message_from_frame)
+    }
+
+    #[test]
+    fn it_works_state_action_failed() {
+        let failure = CanMessage::StateActionFailed(StateActionFailed::new(
+            Board::Pneumatics,
+            State::Ready,
+            State::Accelerate,
+            ActionFailure {
+                state: State::Accelerate,
+                hook: ActionHook::Enter,
+                error: ActionError::Failed,
+            },
+        ));
+        let can_frame: HypedCanFrame = failure.clone().into();
+        let can_message_from_frame: CanMessage = can_frame.try_into().unwrap();
+        assert_eq!(failure, can_message_from_frame)
+    }
+
+    #[test]
+    fn it_works_state_transition_audited() {
+        let mut failed = FailedGuards::default();
+        failed.insert(GuardFailure::BrakesEngaged);
+        for (requester, outcome) in [
+            (AuditRequester::Board(Board::Mqtt), AuditOutcome::Accepted),
+            (
+                AuditRequester::Timeout,
+                AuditOutcome::Rejected(RejectionReason::GuardsFailed(failed)),
+            ),
+            (
+                AuditRequester::Event,
+                AuditOutcome::Rejected(RejectionReason::ActionFailed),
+            ),
+        ] {
+            let audited = CanMessage::StateTransitionAudited(StateTransitionAudited::new(
+                Board::Navigation,
+                requester,
+                State::Ready,
+                State::Accelerate,
+                outcome,
+            ));
+            let can_frame: HypedCanFrame = audited.clone().into();
+            let can_message_from_frame: CanMessage = can_frame.try_into().unwrap();
+            assert_eq!(audited, can_message_from_frame)
+        }
+    }
 }
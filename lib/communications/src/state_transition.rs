@@ -1,5 +1,15 @@
 use crate::boards::Board;
-use hyped_state_machine::states::State;
+use hyped_state_machine::{
+    actions::ActionFailure,
+    audit::{AuditOutcome, EVENT_REQUESTER, TIMEOUT_REQUESTER},
+    state_machine::RejectionReason,
+    states::State,
+};
+
+/// Sent over CAN in place of a board for transitions made when the pod timed out in a state
+const TIMEOUT_REQUESTER_CODE: u8 = u8::MAX;
+/// Sent over CAN in place of a board for transitions made when the state machine detected an event
+const EVENT_REQUESTER_CODE: u8 = u8::MAX - 1;
 
 /// A request to transition to a new state from a given board.
 /// Will be input to the state machine, which will output a command to transition to the new state if the request is valid.
@@ -21,17 +31,159 @@ impl StateTransitionRequest {
 /// A command to transition to a new state.
 /// All boards must obey this command and transition to the new state.
 /// `from_board` needed for CAN ID.
+/// `requesting_board` is the board whose `StateTransitionRequest` was accepted, so that it can
+/// tell the answer to its request apart from other transitions. It is `None` for transitions made
+/// by the state machine itself, e.g. on a timeout or event.
 #[derive(Debug, PartialEq, Clone, defmt::Format)]
 pub struct StateTransitionCommand {
     pub from_board: Board,
     pub to_state: State,
+    pub requesting_board: Option<Board>,
 }
 
 impl StateTransitionCommand {
-    pub fn new(from_board: Board, to_state: State) -> Self {
+    pub fn new(from_board: Board, to_state: State, requesting_board: Option<Board>) -> Self {
         StateTransitionCommand {
             from_board,
             to_state,
+            requesting_board,
+        }
+    }
+}
+
+/// Sent by the state machine when it rejects a `StateTransitionRequest`,
+/// so that the requesting board knows its request failed.
+/// `from_board` needed for CAN ID.
+#[derive(Debug, PartialEq, Clone, defmt::Format)]
+pub struct StateTransitionRejected {
+    pub from_board: Board,
+    pub requesting_board: Board,
+    pub current_state: State,
+    pub requested_state: State,
+    pub reason: RejectionReason,
+}
+
+impl StateTransitionRejected {
+    pub fn new(
+        from_board: Board,
+        requesting_board: Board,
+        current_state: State,
+        requested_state: State,
+        reason: RejectionReason,
+    ) -> Self {
+        StateTransitionRejected {
+            from_board,
+            requesting_board,
+            current_state,
+            requested_state,
+            reason,
+        }
+    }
+}
+
+/// Sent by a board that could not run one of its state actions after the pod changed state,
+/// so that the base station knows the board did not complete the transition.
+/// Actions that leave the pod unsafe raise an emergency instead.
+/// `from_board` needed for CAN ID.
+#[derive(Debug, PartialEq, Clone, defmt::Format)]
+pub struct StateActionFailed {
+    pub from_board: Board,
+    pub from_state: State,
+    pub to_state: State,
+    pub failure: ActionFailure,
+}
+
+impl StateActionFailed {
+    pub fn new(
+        from_board: Board,
+        from_state: State,
+        to_state: State,
+        failure: ActionFailure,
+    ) -> Self {
+        StateActionFailed {
+            from_board,
+            from_state,
+            to_state,
+            failure,
+        }
+    }
+}
+
+/// Who made a state transition, or the request for one, recorded in the audit log
+#[derive(Debug, PartialEq, Clone, Copy, defmt::Format)]
+pub enum AuditRequester {
+    Board(Board),
+    /// The state machine moved the pod on after it timed out in a state
+    Timeout,
+    /// The state machine moved the pod on after detecting an event
+    Event,
+}
+
+impl From<Board> for AuditRequester {
+    fn from(board: Board) -> Self {
+        AuditRequester::Board(board)
+    }
+}
+
+/// Uses the names recorded in `AuditEntry::requester`
+impl From<AuditRequester> for &'static str {
+    fn from(requester: AuditRequester) -> Self {
+        match requester {
+            AuditRequester::Board(board) => board.into(),
+            AuditRequester::Timeout => TIMEOUT_REQUESTER,
+            AuditRequester::Event => EVENT_REQUESTER,
+        }
+    }
+}
+
+impl From<AuditRequester> for u8 {
+    fn from(requester: AuditRequester) -> Self {
+        match requester {
+            AuditRequester::Board(board) => board.into(),
+            AuditRequester::Timeout => TIMEOUT_REQUESTER_CODE,
+            AuditRequester::Event => EVENT_REQUESTER_CODE,
+        }
+    }
+}
+
+impl TryFrom<u8> for AuditRequester {
+    type Error = &'static str;
+
+    fn try_from(code: u8) -> Result<Self, Self::Error> {
+        match code {
+            TIMEOUT_REQUESTER_CODE => Ok(AuditRequester::Timeout),
+            EVENT_REQUESTER_CODE => Ok(AuditRequester::Event),
+            code => Board::try_from(code).map(AuditRequester::Board),
+        }
+    }
+}
+
+/// Sent by the leading state machine for every entry it records in its audit log, so that the
+/// board serving the base station keeps the log whichever board leads.
+/// `from_board` needed for CAN ID.
+#[derive(Debug, PartialEq, Clone, defmt::Format)]
+pub struct StateTransitionAudited {
+    pub from_board: Board,
+    pub requester: AuditRequester,
+    pub from_state: State,
+    pub to_state: State,
+    pub outcome: AuditOutcome,
+}
+
+impl StateTransitionAudited {
+    pub fn new(
+        from_board: Board,
+        requester: AuditRequester,
+        from_state: State,
+        to_state: State,
+        outcome: AuditOutcome,
+    ) -> Self {
+        StateTransitionAudited {
+            from_board,
+            requester,
+            from_state,
+            to_state,
+            outcome,
         }
     }
 }
@@ -0,0 +1,311 @@
+use crate::{
+    config::MeasurementId,
+    measurements::MeasurementRange,
+    mqtt_payload::{AggregatePayload, MeasurementValue},
+};
+use heapless::FnvIndexMap;
+
+/// What to do with a reading after it has been given to the `MeasurementAggregator`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Aggregation {
+    /// The reading should be sent straight away, e.g. because it is critical
+    Forward,
+    /// The reading has been added to a window and will be sent as part of its summary
+    Windowed,
+}
+
+/// Summary statistics of the readings of one measurement over a window
+#[derive(Debug, Clone, Copy, PartialEq, defmt::Format)]
+pub struct WindowSummary<'a> {
+    pub measurement_id: MeasurementId,
+    /// The board that took the most recent reading
+    pub board: &'a str,
+    /// Timestamp of the earliest reading in the window, in milliseconds
+    pub start_ms: u64,
+    /// Timestamp of the latest reading in the window, in milliseconds. Readings from CAN may
+    /// arrive out of order, so this is not always the timestamp of `last`.
+    pub end_ms: u64,
+    pub count: u32,
+    pub min: f32,
+    pub max: f32,
+    pub mean: f32,
+    pub last: f32,
+}
+
+impl<'a> WindowSummary<'a> {
+    /// Converts the summary into a payload that can be sent over MQTT
+    pub fn to_payload(&self) -> AggregatePayload<'a> {
+        AggregatePayload {
+            timestamp_ms: self.end_ms,
+            board: self.board,
+            measurement_id: self.measurement_id,
+            window_ms: self.end_ms.saturating_sub(self.start_ms),
+            count: self.count,
+            min: self.min,
+            max: self.max,
+            mean: self.mean,
+            last: self.last,
+        }
+    }
+}
+
+/// A window that is still collecting readings
+#[derive(Debug, Clone, Copy)]
+struct Window<'a> {
+    board: &'a str,
+    start_ms: u64,
+    end_ms: u64,
+    count: u32,
+    min: f32,
+    max: f32,
+    sum: f32,
+    last: f32,
+}
+
+impl<'a> Window<'a> {
+    fn new(board: &'a str, timestamp_ms: u64, value: f32) -> Self {
+        Window {
+            board,
+            start_ms: timestamp_ms,
+            end_ms: timestamp_ms,
+            count: 1,
+            min: value,
+            max: value,
+            sum: value,
+            last: value,
+        }
+    }
+
+    fn add(&mut self, board: &'a str, timestamp_ms: u64, value: f32) {
+        self.board = board;
+        self.start_ms = self.start_ms.min(timestamp_ms);
+        self.end_ms = self.end_ms.max(timestamp_ms);
+        self.count += 1;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.sum += value;
+        self.last = value;
+    }
+
+    fn summary(&self, measurement_id: MeasurementId) -> WindowSummary<'a> {
+        WindowSummary {
+            measurement_id,
+            board: self.board,
+            start_ms: self.start_ms,
+            end_ms: self.end_ms,
+            count: self.count,
+            min: self.min,
+            max: self.max,
+            mean: self.sum / self.count as f32,
+            last: self.last,
+        }
+    }
+}
+
+/// Aggregates readings of measurements over windows, so that high rate measurements don't flood
+/// MQTT. The window length is configured per measurement kind in `config/telemetry.yaml`.
+///
+/// Readings of measurements without a window, non-numeric readings and readings in the critical
+/// range are forwarded straight away. Critical readings are also included in their window.
+pub struct MeasurementAggregator<'a, const N: usize> {
+    windows: FnvIndexMap<MeasurementId, Window<'a>, N>,
+}
+
+impl<'a, const N: usize> Default for MeasurementAggregator<'a, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a, const N: usize> MeasurementAggregator<'a, N> {
+    pub fn new() -> Self {
+        MeasurementAggregator {
+            windows: FnvIndexMap::new(),
+        }
+    }
+
+    /// Adds a reading, returning whether it should also be sent straight away
+    pub fn add(
+        &mut self,
+        timestamp_ms: u64,
+        board: &'a str,
+        measurement_id: MeasurementId,
+        value: MeasurementValue,
+    ) -> Aggregation {
+        let value = match value.as_f32() {
+            Some(value) if !value.is_nan() => value,
+            _ => return Aggregation::Forward,
+        };
+        if measurement_id.aggregation_window_ms().is_none() {
+            return Aggregation::Forward;
+        }
+
+        match self.windows.get_mut(&measurement_id) {
+            Some(window) => window.add(board, timestamp_ms, value),
+            None => {
+                let window = Window::new(board, timestamp_ms, value);
+                if self.windows.insert(measurement_id, window).is_err() {
+                    // No space for another window, so don't aggregate this measurement
+                    return Aggregation::Forward;
+                }
+            }
+        }
+
+        match measurement_id.limits().classify(value) {
+            MeasurementRange::Critical => Aggregation::Forward,
+            MeasurementRange::Safe | MeasurementRange::Warning => Aggregation::Windowed,
+        }
+    }
+
+    /// Removes and returns the summary of a window that has lasted at least its configured
+    /// length at `now_ms`. Call repeatedly until `None` to get all expired windows.
+    pub fn take_expired(&mut self, now_ms: u64) -> Option<WindowSummary<'a>> {
+        let measurement_id = self
+            .windows
+            .iter()
+            .find(|(measurement_id, window)| {
+                let window_ms = measurement_id.aggregation_window_ms().unwrap_or(0);
+                now_ms >= window.start_ms + window_ms
+            })
+            .map(|(measurement_id, _)| *measurement_id)?;
+        self.take(measurement_id)
+    }
+
+    /// Removes and returns the summary of the window of a measurement, regardless of its length
+    pub fn take(&mut self, measurement_id: MeasurementId) -> Option<WindowSummary<'a>> {
+        self.windows
+            .remove(&measurement_id)
+            .map(|window| window.summary(measurement_id))
+    }
+
+    /// Removes and returns the summary of any window, regardless of its length.
+    /// Call repeatedly until `None` to empty the aggregator, e.g. when the state changes.
+    pub fn take_any(&mut self) -> Option<WindowSummary<'a>> {
+        let measurement_id = *self.windows.keys().next()?;
+        self.take(measurement_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Velocity is aggregated over 100ms windows in `config/telemetry.yaml`
+    const WINDOW_MS: u64 = 100;
+
+    #[test]
+    fn test_summary_over_window() {
+        let mut aggregator = MeasurementAggregator::<4>::new();
+        for (i, value) in [3.0, 1.0, 5.0, 3.0].into_iter().enumerate() {
+            assert_eq!(
+                aggregator.add(
+                    i as u64 * 10,
+                    "navigation",
+                    MeasurementId::Velocity,
+                    MeasurementValue::Float(value)
+                ),
+                Aggregation::Windowed
+            );
+        }
+        assert_eq!(aggregator.take_expired(WINDOW_MS - 1), None);
+        assert_eq!(
+            aggregator.take_expired(WINDOW_MS),
+            Some(WindowSummary {
+                measurement_id: MeasurementId::Velocity,
+                board: "navigation",
+                start_ms: 0,
+                end_ms: 30,
+                count: 4,
+                min: 1.0,
+                max: 5.0,
+                mean: 3.0,
+                last: 3.0,
+            })
+        );
+        assert_eq!(aggregator.take_expired(WINDOW_MS), None);
+    }
+
+    #[test]
+    fn test_out_of_order_readings() {
+        let mut aggregator = MeasurementAggregator::<4>::new();
+        for timestamp_ms in [50, 20, 70, 10] {
+            aggregator.add(
+                timestamp_ms,
+                "navigation",
+                MeasurementId::Velocity,
+                MeasurementValue::Float(1.0),
+            );
+        }
+        let summary = aggregator.take(MeasurementId::Velocity).unwrap();
+        assert_eq!(summary.start_ms, 10);
+        assert_eq!(summary.end_ms, 70);
+        assert_eq!(summary.to_payload().window_ms, 60);
+
+        let reversed = WindowSummary {
+            start_ms: 70,
+            end_ms: 10,
+            ..summary
+        };
+        assert_eq!(reversed.to_payload().window_ms, 0);
+    }
+
+    #[test]
+    fn test_critical_values_bypass_window() {
+        let mut aggregator = MeasurementAggregator::<4>::new();
+        aggregator.add(
+            0,
+            "navigation",
+            MeasurementId::Velocity,
+            MeasurementValue::Float(1.0),
+        );
+        assert_eq!(
+            aggregator.add(
+                10,
+                "navigation",
+                MeasurementId::Velocity,
+                MeasurementValue::Float(-1.0)
+            ),
+            Aggregation::Forward
+        );
+        let summary = aggregator.take(MeasurementId::Velocity).unwrap();
+        assert_eq!(summary.count, 2);
+        assert_eq!(summary.min, -1.0);
+    }
+
+    #[test]
+    fn test_non_numeric_values_bypass_window() {
+        let mut aggregator = MeasurementAggregator::<4>::new();
+        assert_eq!(
+            aggregator.add(
+                0,
+                "navigation",
+                MeasurementId::Keyence1,
+                MeasurementValue::Pair([1, 2])
+            ),
+            Aggregation::Forward
+        );
+        assert_eq!(aggregator.take_any(), None);
+    }
+
+    #[test]
+    fn test_forward_when_full() {
+        let mut aggregator = MeasurementAggregator::<2>::new();
+        for measurement_id in [MeasurementId::Velocity, MeasurementId::PressureBackPull] {
+            aggregator.add(
+                0,
+                "navigation",
+                measurement_id,
+                MeasurementValue::Float(1.0),
+            );
+        }
+        assert_eq!(
+            aggregator.add(
+                0,
+                "pneumatics",
+                MeasurementId::PressureBrakesReservoir,
+                MeasurementValue::Float(5.0)
+            ),
+            Aggregation::Forward
+        );
+    }
+}
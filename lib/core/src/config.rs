@@ -1,7 +1,11 @@
+use crate::{
+    measurements::{Limits, MeasurementLimits},
+    mqtt_payload::PayloadEncoding,
+};
 use config_to_rs::config_to_rs;
 use core::str::FromStr;
 use heapless::String;
-use hyped_measurement_ids::gen_measurement_ids;
+use hyped_measurement_ids::{gen_aggregation_windows, gen_measurement_ids, gen_payload_encodings};
 
 /// Configuration for the pods
 /// The configuration is loaded from the `config/pods.yaml` file, and can be read using standard
@@ -34,10 +38,15 @@ pub struct ControlConfig;
 #[config_to_rs(yaml, "../../../config/levitation.yaml")]
 pub struct LevitationConfig;
 
+#[config_to_rs(yaml, "../../../config/state_machine.yaml")]
+pub struct StateMachineConfig;
+
 // TODOLater: this should be in a config
 pub static POD_NAME: &str = "poddington";
 
 gen_measurement_ids!("config/pods.yaml", "poddington");
+gen_payload_encodings!("config/telemetry.yaml");
+gen_aggregation_windows!("config/telemetry.yaml");
 
 mod test {
     #[test]
@@ -1,9 +1,13 @@
 #![cfg_attr(not(test), no_std)]
 
+pub mod aggregation;
 pub mod config;
 pub mod format_string;
 pub mod log_types;
 pub mod logging;
+pub mod measurements;
 pub mod mqtt;
+pub mod mqtt_command;
+pub mod mqtt_payload;
 pub mod mqtt_topics;
 pub mod types;
@@ -1,4 +1,4 @@
-#[derive(PartialEq, PartialOrd)]
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
 pub enum LogLevel {
     Debug = 0,
     Info = 1,
@@ -6,6 +6,17 @@ pub enum LogLevel {
     Error = 3,
 }
 
+impl From<LogLevel> for &str {
+    fn from(val: LogLevel) -> Self {
+        match val {
+            LogLevel::Debug => "debug",
+            LogLevel::Info => "info",
+            LogLevel::Warn => "warn",
+            LogLevel::Error => "error",
+        }
+    }
+}
+
 pub enum LogTarget {
     Console,
     Mqtt,
@@ -0,0 +1,113 @@
+/// A lower and upper bound for a measurement
+#[derive(Debug, Clone, Copy, PartialEq, defmt::Format)]
+pub struct Limits {
+    pub low: f32,
+    pub high: f32,
+}
+
+impl Limits {
+    /// Returns true if the value is outwith the bounds
+    pub fn is_breached_by(&self, value: f32) -> bool {
+        value < self.low || value > self.high
+    }
+}
+
+/// The limits of a measurement, as defined in `config/pods.yaml`.
+/// Every measurement has critical limits, but warning limits are optional.
+#[derive(Debug, Clone, Copy, PartialEq, defmt::Format)]
+pub struct MeasurementLimits {
+    pub critical: Limits,
+    pub warning: Option<Limits>,
+}
+
+impl MeasurementLimits {
+    /// Classifies a value into one of the ranges defined by the limits.
+    /// Uses the same logic as the limit checks on the telemetry server.
+    pub fn classify(&self, value: f32) -> MeasurementRange {
+        if self.critical.is_breached_by(value) {
+            return MeasurementRange::Critical;
+        }
+        match self.warning {
+            Some(warning) if warning.is_breached_by(value) => MeasurementRange::Warning,
+            _ => MeasurementRange::Safe,
+        }
+    }
+}
+
+/// The range a measurement value falls into
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum MeasurementRange {
+    /// This is the normal range of values for the measurement.
+    Safe,
+    /// Values are outwith the normal range, but not yet critical.
+    Warning,
+    /// Values are outwith the critical limits.
+    Critical,
+}
+
+impl From<MeasurementRange> for &str {
+    fn from(val: MeasurementRange) -> Self {
+        match val {
+            MeasurementRange::Safe => "safe",
+            MeasurementRange::Warning => "warning",
+            MeasurementRange::Critical => "critical",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::MeasurementId;
+
+    #[test]
+    fn test_classify_with_warning_limits() {
+        let limits = MeasurementLimits {
+            critical: Limits {
+                low: -0.2,
+                high: 5.5,
+            },
+            warning: Some(Limits {
+                low: -0.19,
+                high: 5.2,
+            }),
+        };
+        assert_eq!(limits.classify(1.0), MeasurementRange::Safe);
+        assert_eq!(limits.classify(5.3), MeasurementRange::Warning);
+        assert_eq!(limits.classify(5.6), MeasurementRange::Critical);
+        assert_eq!(limits.classify(-0.3), MeasurementRange::Critical);
+    }
+
+    #[test]
+    fn test_classify_without_warning_limits() {
+        let limits = MeasurementLimits {
+            critical: Limits {
+                low: 0.0,
+                high: 100.0,
+            },
+            warning: None,
+        };
+        assert_eq!(limits.classify(99.0), MeasurementRange::Safe);
+        assert_eq!(limits.classify(101.0), MeasurementRange::Critical);
+    }
+
+    #[test]
+    fn test_generated_measurement_info() {
+        let measurement_id = MeasurementId::PressureBackPull;
+        assert_eq!(measurement_id.kind(), "pressure");
+        assert_eq!(measurement_id.unit(), "bar");
+        assert_eq!(
+            measurement_id.limits(),
+            MeasurementLimits {
+                critical: Limits {
+                    low: -0.2,
+                    high: 5.5
+                },
+                warning: Some(Limits {
+                    low: -0.19,
+                    high: 5.2
+                }),
+            }
+        );
+    }
+}
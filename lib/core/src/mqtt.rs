@@ -3,7 +3,7 @@ use crate::{
     mqtt_topics::MqttTopic,
 };
 use embassy_net::tcp::TcpSocket;
-use heapless::String;
+use heapless::{String, Vec};
 use rust_mqtt::{
     client::{
         client::MqttClient,
@@ -13,15 +13,74 @@ use rust_mqtt::{
     utils::rng_generator::CountingRng,
 };
 
+pub mod cbor;
+
+/// The payload of an MQTT message, either text (e.g. JSON) or binary (e.g. CBOR)
+#[derive(Debug, Clone, PartialEq, defmt::Format)]
+pub enum MqttPayload {
+    Text(String<512>),
+    Binary(Vec<u8, 512>),
+}
+
+impl MqttPayload {
+    /// Creates a payload from received bytes, as text if they are valid UTF-8.
+    /// Returns `None` if the payload is too large.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        match core::str::from_utf8(bytes) {
+            Ok(text) => String::try_from(text).ok().map(MqttPayload::Text),
+            Err(_) => Vec::from_slice(bytes).ok().map(MqttPayload::Binary),
+        }
+    }
+
+    /// Returns the raw bytes of the payload, ready to be sent
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            MqttPayload::Text(text) => text.as_bytes(),
+            MqttPayload::Binary(bytes) => bytes,
+        }
+    }
+
+    /// Returns the payload as a string if it is a text payload
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            MqttPayload::Text(text) => Some(text.as_str()),
+            MqttPayload::Binary(_) => None,
+        }
+    }
+}
+
 #[derive(defmt::Format)]
 pub struct MqttMessage {
     pub topic: MqttTopic,
-    pub payload: String<512>,
+    pub payload: MqttPayload,
+    /// Whether the broker keeps the message for clients that subscribe later
+    pub retain: bool,
 }
 
 impl MqttMessage {
     pub fn new(topic: MqttTopic, payload: String<512>) -> Self {
-        MqttMessage { topic, payload }
+        MqttMessage {
+            topic,
+            payload: MqttPayload::Text(payload),
+            retain: false,
+        }
+    }
+
+    /// Creates a message that the broker keeps, replacing the last retained message on the topic
+    pub fn new_retained(topic: MqttTopic, payload: String<512>) -> Self {
+        MqttMessage {
+            topic,
+            payload: MqttPayload::Text(payload),
+            retain: true,
+        }
+    }
+
+    pub fn new_binary(topic: MqttTopic, payload: Vec<u8, 512>) -> Self {
+        MqttMessage {
+            topic,
+            payload: MqttPayload::Binary(payload),
+            retain: false,
+        }
     }
 }
 
@@ -122,12 +181,11 @@ impl<T: embedded_io_async::Read + embedded_io_async::Write, R: rand_core::RngCor
         }
     }
 
-    pub async fn receive_message(&mut self) -> Result<(&str, &str), ReasonCode> {
+    /// Receives a message, returning its topic and raw payload.
+    /// The payload is not checked, as it may not be valid UTF-8.
+    pub async fn receive_message(&mut self) -> Result<(&str, &[u8]), ReasonCode> {
         match self.client.receive_message().await {
-            Ok((topic, payload)) => {
-                let payload_str = core::str::from_utf8(payload).unwrap();
-                Ok((topic, payload_str))
-            }
+            Ok((topic, payload)) => Ok((topic, payload)),
             Err(mqtt_error) => match mqtt_error {
                 ReasonCode::NetworkError => {
                     info!("MQTT Network Error");
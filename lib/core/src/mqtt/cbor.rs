@@ -0,0 +1,300 @@
+use crate::{
+    config::MeasurementId,
+    mqtt_payload::{MeasurementValue, MAX_PAYLOAD_SIZE, PAYLOAD_SCHEMA_VERSION},
+};
+use heapless::Vec;
+
+/// Maximum number of samples in a single `MeasurementBatch`.
+/// A float sample takes at most 15 bytes, so a full batch fits in one MQTT payload.
+pub const MAX_BATCH_SIZE: usize = 32;
+
+/// Keys of the CBOR map used for measurement batches.
+/// Integer keys are used rather than strings to keep the payload small.
+pub mod batch_keys {
+    pub const VERSION: u64 = 0;
+    pub const BOARD: u64 = 1;
+    pub const MEASUREMENT: u64 = 2;
+    pub const SAMPLES: u64 = 3;
+}
+
+const MAJOR_UNSIGNED: u8 = 0;
+const MAJOR_TEXT: u8 = 3;
+const MAJOR_ARRAY: u8 = 4;
+const MAJOR_MAP: u8 = 5;
+
+const FALSE: u8 = 0xF4;
+const TRUE: u8 = 0xF5;
+const FLOAT_32: u8 = 0xFA;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum CborError {
+    /// The buffer is too small to hold the encoded data
+    BufferFull,
+    /// The batch already holds `MAX_BATCH_SIZE` samples
+    BatchFull,
+}
+
+/// Minimal CBOR (RFC 8949) encoder writing into a fixed size buffer.
+/// Only supports the types needed for telemetry payloads.
+pub struct CborEncoder<'a> {
+    buffer: &'a mut [u8],
+    used: usize,
+}
+
+impl<'a> CborEncoder<'a> {
+    pub fn new(buffer: &'a mut [u8]) -> Self {
+        CborEncoder { buffer, used: 0 }
+    }
+
+    /// Returns the encoded bytes
+    pub fn into_bytes(self) -> &'a [u8] {
+        &self.buffer[..self.used]
+    }
+
+    pub fn unsigned(&mut self, value: u64) -> Result<(), CborError> {
+        self.head(MAJOR_UNSIGNED, value)
+    }
+
+    pub fn text(&mut self, value: &str) -> Result<(), CborError> {
+        self.head(MAJOR_TEXT, value.len() as u64)?;
+        self.write(value.as_bytes())
+    }
+
+    /// Starts an array of `len` items, which must be written next
+    pub fn array(&mut self, len: usize) -> Result<(), CborError> {
+        self.head(MAJOR_ARRAY, len as u64)
+    }
+
+    /// Starts a map of `len` key-value pairs, which must be written next
+    pub fn map(&mut self, len: usize) -> Result<(), CborError> {
+        self.head(MAJOR_MAP, len as u64)
+    }
+
+    pub fn bool(&mut self, value: bool) -> Result<(), CborError> {
+        self.write(&[if value { TRUE } else { FALSE }])
+    }
+
+    pub fn f32(&mut self, value: f32) -> Result<(), CborError> {
+        self.write(&[FLOAT_32])?;
+        self.write(&value.to_be_bytes())
+    }
+
+    pub fn measurement_value(&mut self, value: &MeasurementValue) -> Result<(), CborError> {
+        match value {
+            MeasurementValue::Bool(b) => self.bool(*b),
+            MeasurementValue::Integer(i) => self.unsigned(*i as u64),
+            MeasurementValue::Float(f) => self.f32(*f),
+            MeasurementValue::Pair([a, b]) => {
+                self.array(2)?;
+                self.unsigned(*a as u64)?;
+                self.unsigned(*b as u64)
+            }
+        }
+    }
+
+    /// Writes the initial byte of a data item, followed by its argument
+    fn head(&mut self, major_type: u8, value: u64) -> Result<(), CborError> {
+        let major_type = major_type << 5;
+        if value < 24 {
+            self.write(&[major_type | value as u8])
+        } else if value <= u8::MAX as u64 {
+            self.write(&[major_type | 24, value as u8])
+        } else if value <= u16::MAX as u64 {
+            self.write(&[major_type | 25])?;
+            self.write(&(value as u16).to_be_bytes())
+        } else if value <= u32::MAX as u64 {
+            self.write(&[major_type | 26])?;
+            self.write(&(value as u32).to_be_bytes())
+        } else {
+            self.write(&[major_type | 27])?;
+            self.write(&value.to_be_bytes())
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), CborError> {
+        let end = self.used + bytes.len();
+        if end > self.buffer.len() {
+            return Err(CborError::BufferFull);
+        }
+        self.buffer[self.used..end].copy_from_slice(bytes);
+        self.used = end;
+        Ok(())
+    }
+}
+
+/// A single reading in a `MeasurementBatch`
+#[derive(Debug, Clone, Copy, PartialEq, defmt::Format)]
+pub struct Sample {
+    /// Time since the sending board booted, in milliseconds
+    pub timestamp_ms: u64,
+    pub value: MeasurementValue,
+}
+
+/// Many readings of one measurement, sent in a single MQTT message.
+///
+/// Encoded as a CBOR map using the keys in `batch_keys`:
+/// `{0: version, 1: board, 2: measurement, 3: [[timestamp_ms, value], ...]}`
+#[derive(Debug, Clone, PartialEq)]
+pub struct MeasurementBatch<'a> {
+    pub board: &'a str,
+    pub measurement_id: MeasurementId,
+    samples: Vec<Sample, MAX_BATCH_SIZE>,
+}
+
+impl<'a> MeasurementBatch<'a> {
+    pub fn new(board: &'a str, measurement_id: MeasurementId) -> Self {
+        MeasurementBatch {
+            board,
+            measurement_id,
+            samples: Vec::new(),
+        }
+    }
+
+    /// Adds a reading to the batch
+    pub fn push(&mut self, timestamp_ms: u64, value: MeasurementValue) -> Result<(), CborError> {
+        self.samples
+            .push(Sample {
+                timestamp_ms,
+                value,
+            })
+            .map_err(|_| CborError::BatchFull)
+    }
+
+    pub fn samples(&self) -> &[Sample] {
+        &self.samples
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// Whether the oldest reading in the batch was taken at least `max_age_ms` before `now_ms`
+    pub fn is_older_than(&self, now_ms: u64, max_age_ms: u64) -> bool {
+        self.samples
+            .first()
+            .is_some_and(|oldest| now_ms.saturating_sub(oldest.timestamp_ms) >= max_age_ms)
+    }
+
+    /// Removes all samples, so the batch can be reused
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+
+    /// Encodes the batch as CBOR into the given buffer, returning the written bytes
+    pub fn encode<'b>(&self, buffer: &'b mut [u8]) -> Result<&'b [u8], CborError> {
+        let mut encoder = CborEncoder::new(buffer);
+        encoder.map(4)?;
+        encoder.unsigned(batch_keys::VERSION)?;
+        encoder.unsigned(PAYLOAD_SCHEMA_VERSION as u64)?;
+        encoder.unsigned(batch_keys::BOARD)?;
+        encoder.text(self.board)?;
+        encoder.unsigned(batch_keys::MEASUREMENT)?;
+        encoder.text(self.measurement_id.into())?;
+        encoder.unsigned(batch_keys::SAMPLES)?;
+        encoder.array(self.samples.len())?;
+        for sample in &self.samples {
+            encoder.array(2)?;
+            encoder.unsigned(sample.timestamp_ms)?;
+            encoder.measurement_value(&sample.value)?;
+        }
+        Ok(encoder.into_bytes())
+    }
+
+    /// Encodes the batch as CBOR, ready to be put into an `MqttMessage`
+    pub fn to_payload(&self) -> Result<Vec<u8, MAX_PAYLOAD_SIZE>, CborError> {
+        let mut buffer = [0u8; MAX_PAYLOAD_SIZE];
+        let bytes = self.encode(&mut buffer)?;
+        Vec::from_slice(bytes).map_err(|_| CborError::BufferFull)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_unsigned() {
+        let mut buffer = [0u8; 16];
+        let mut encoder = CborEncoder::new(&mut buffer);
+        encoder.unsigned(10).unwrap();
+        encoder.unsigned(100).unwrap();
+        encoder.unsigned(1000).unwrap();
+        encoder.unsigned(1_000_000).unwrap();
+        assert_eq!(
+            encoder.into_bytes(),
+            &[0x0A, 0x18, 0x64, 0x19, 0x03, 0xE8, 0x1A, 0x00, 0x0F, 0x42, 0x40]
+        );
+    }
+
+    #[test]
+    fn test_encode_text_and_float() {
+        let mut buffer = [0u8; 16];
+        let mut encoder = CborEncoder::new(&mut buffer);
+        encoder.text("IETF").unwrap();
+        encoder.f32(100000.0).unwrap();
+        assert_eq!(
+            encoder.into_bytes(),
+            &[0x64, 0x49, 0x45, 0x54, 0x46, 0xFA, 0x47, 0xC3, 0x50, 0x00]
+        );
+    }
+
+    #[test]
+    fn test_encode_buffer_full() {
+        let mut buffer = [0u8; 2];
+        let mut encoder = CborEncoder::new(&mut buffer);
+        assert_eq!(encoder.text("IETF"), Err(CborError::BufferFull));
+    }
+
+    #[test]
+    fn test_encode_batch() {
+        let mut batch = MeasurementBatch::new("nav", MeasurementId::Velocity);
+        batch.push(1, MeasurementValue::Float(1.5)).unwrap();
+        batch.push(2, MeasurementValue::Integer(3)).unwrap();
+
+        let mut buffer = [0u8; MAX_PAYLOAD_SIZE];
+        let bytes = batch.encode(&mut buffer).unwrap();
+        assert_eq!(
+            bytes,
+            &[
+                0xA4, // map(4)
+                0x00, 0x02, // version: 2
+                0x01, 0x63, b'n', b'a', b'v', // board: "nav"
+                0x02, 0x68, b'v', b'e', b'l', b'o', b'c', b'i', b't', b'y', // "velocity"
+                0x03, 0x82, // samples: array(2)
+                0x82, 0x01, 0xFA, 0x3F, 0xC0, 0x00, 0x00, // [1, 1.5]
+                0x82, 0x02, 0x03, // [2, 3]
+            ]
+        );
+    }
+
+    #[test]
+    fn test_full_batch_fits_in_payload() {
+        let mut batch = MeasurementBatch::new("navigation", MeasurementId::Accelerometer1);
+        for _ in 0..MAX_BATCH_SIZE {
+            batch
+                .push(u32::MAX as u64, MeasurementValue::Float(f32::MAX))
+                .unwrap();
+        }
+        assert_eq!(
+            batch.push(0, MeasurementValue::Float(0.0)),
+            Err(CborError::BatchFull)
+        );
+        assert!(batch.to_payload().is_ok());
+    }
+
+    #[test]
+    fn test_batch_age() {
+        let mut batch = MeasurementBatch::new("nav", MeasurementId::Velocity);
+        assert!(!batch.is_older_than(1000, 100));
+        batch.push(500, MeasurementValue::Float(1.0)).unwrap();
+        batch.push(550, MeasurementValue::Float(2.0)).unwrap();
+        assert!(!batch.is_older_than(599, 100));
+        assert!(batch.is_older_than(600, 100));
+        // A clock behind the readings never makes the batch old
+        assert!(!batch.is_older_than(0, 100));
+    }
+}
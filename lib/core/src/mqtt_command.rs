@@ -0,0 +1,269 @@
+/// Why a command received over MQTT was rejected
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum CommandError {
+    /// The payload is larger than an `MqttMessage` can hold
+    TooLarge,
+    /// The payload is not valid UTF-8
+    InvalidUtf8,
+    /// The payload looks like JSON, but could not be parsed
+    InvalidJson,
+    /// The field holding the command value is missing or empty
+    MissingField,
+    /// The command value is not one the pod understands, e.g. an unknown state
+    InvalidValue,
+    /// The command is valid, but the given safety interlock prevents it, e.g.
+    /// `not_in_maintenance` for an actuator command outside the maintenance state
+    Interlocked(&'static str),
+}
+
+impl From<CommandError> for &str {
+    fn from(val: CommandError) -> Self {
+        match val {
+            CommandError::TooLarge => "too_large",
+            CommandError::InvalidUtf8 => "invalid_utf8",
+            CommandError::InvalidJson => "invalid_json",
+            CommandError::MissingField => "missing_field",
+            CommandError::InvalidValue => "invalid_value",
+            CommandError::Interlocked(_) => "interlocked",
+        }
+    }
+}
+
+/// A command received over MQTT
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Command<'a> {
+    /// Correlation ID chosen by the sender, echoed back in the response
+    pub id: Option<&'a str>,
+    pub value: &'a str,
+}
+
+impl<'a> Command<'a> {
+    /// Rejects the command, keeping its correlation ID for the response
+    pub fn reject(&self, error: CommandError) -> CommandRejection<'a> {
+        CommandRejection { id: self.id, error }
+    }
+}
+
+/// A command that could not be accepted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct CommandRejection<'a> {
+    /// Correlation ID of the command, if it could be read
+    pub id: Option<&'a str>,
+    pub error: CommandError,
+}
+
+impl From<CommandError> for CommandRejection<'_> {
+    fn from(error: CommandError) -> Self {
+        CommandRejection { id: None, error }
+    }
+}
+
+/// Parses a command payload without panicking on malformed input.
+///
+/// Accepts either a bare value, e.g. `calibrate`, or a flat JSON object holding the value in
+/// `field` and an optional correlation ID, e.g. `{"id":"42","state":"calibrate"}`.
+/// JSON values must be strings without escapes, or numbers.
+pub fn parse_command<'a>(
+    payload: &'a [u8],
+    field: &str,
+) -> Result<Command<'a>, CommandRejection<'a>> {
+    let payload = core::str::from_utf8(payload)
+        .map_err(|_| CommandError::InvalidUtf8)?
+        .trim();
+
+    if !payload.starts_with('{') {
+        if payload.is_empty() {
+            return Err(CommandError::MissingField.into());
+        }
+        return Ok(Command {
+            id: None,
+            value: payload,
+        });
+    }
+
+    let mut id = None;
+    let mut value = None;
+    for pair in JsonObject::new(payload)? {
+        let (key, pair_value) = pair?;
+        if key == "id" {
+            id = Some(pair_value);
+        } else if key == field {
+            value = Some(pair_value);
+        }
+    }
+
+    match value {
+        Some(value) if !value.is_empty() => Ok(Command { id, value }),
+        _ => Err(CommandRejection {
+            id,
+            error: CommandError::MissingField,
+        }),
+    }
+}
+
+/// Iterator over the key-value pairs of a flat JSON object
+struct JsonObject<'a> {
+    rest: &'a str,
+    done: bool,
+}
+
+impl<'a> JsonObject<'a> {
+    fn new(json: &'a str) -> Result<Self, CommandError> {
+        let inner = json
+            .strip_prefix('{')
+            .and_then(|json| json.strip_suffix('}'))
+            .ok_or(CommandError::InvalidJson)?
+            .trim();
+        Ok(JsonObject {
+            rest: inner,
+            done: inner.is_empty(),
+        })
+    }
+
+    fn pair(&mut self) -> Result<(&'a str, &'a str), CommandError> {
+        let key = self.string()?;
+        self.rest = self
+            .rest
+            .trim_start()
+            .strip_prefix(':')
+            .ok_or(CommandError::InvalidJson)?
+            .trim_start();
+        let value = if self.rest.starts_with('"') {
+            self.string()?
+        } else {
+            self.number()?
+        };
+
+        self.rest = self.rest.trim_start();
+        match self.rest.strip_prefix(',') {
+            Some(rest) => self.rest = rest.trim_start(),
+            None if self.rest.is_empty() => self.done = true,
+            None => return Err(CommandError::InvalidJson),
+        }
+        Ok((key, value))
+    }
+
+    fn string(&mut self) -> Result<&'a str, CommandError> {
+        let rest = self
+            .rest
+            .strip_prefix('"')
+            .ok_or(CommandError::InvalidJson)?;
+        let end = rest.find('"').ok_or(CommandError::InvalidJson)?;
+        let string = &rest[..end];
+        if string.contains('\\') {
+            return Err(CommandError::InvalidJson);
+        }
+        self.rest = &rest[end + 1..];
+        Ok(string)
+    }
+
+    fn number(&mut self) -> Result<&'a str, CommandError> {
+        let end = self
+            .rest
+            .find(|c: char| !(c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E')))
+            .unwrap_or(self.rest.len());
+        if end == 0 {
+            return Err(CommandError::InvalidJson);
+        }
+        let number = &self.rest[..end];
+        self.rest = &self.rest[end..];
+        Ok(number)
+    }
+}
+
+impl<'a> Iterator for JsonObject<'a> {
+    type Item = Result<(&'a str, &'a str), CommandError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let pair = self.pair();
+        if pair.is_err() {
+            self.done = true;
+        }
+        Some(pair)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bare_value() {
+        assert_eq!(
+            parse_command(b" calibrate\n", "state"),
+            Ok(Command {
+                id: None,
+                value: "calibrate"
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_json() {
+        assert_eq!(
+            parse_command(b"{\"id\": \"abc\", \"state\": \"calibrate\"}", "state"),
+            Ok(Command {
+                id: Some("abc"),
+                value: "calibrate"
+            })
+        );
+        assert_eq!(
+            parse_command(b"{\"state\":\"idle\",\"id\":42,\"extra\":\"x\"}", "state"),
+            Ok(Command {
+                id: Some("42"),
+                value: "idle"
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_missing_field_keeps_id() {
+        assert_eq!(
+            parse_command(b"{\"id\":\"abc\",\"stat\":\"idle\"}", "state"),
+            Err(CommandRejection {
+                id: Some("abc"),
+                error: CommandError::MissingField
+            })
+        );
+        assert_eq!(
+            parse_command(b"  ", "state"),
+            Err(CommandError::MissingField.into())
+        );
+    }
+
+    #[test]
+    fn test_parse_malformed() {
+        for payload in [
+            &b"{\"state\":\"idle\""[..],
+            b"{\"state\" \"idle\"}",
+            b"{\"state\":\"idle\",}",
+            b"{\"state\":\"id\\\"le\"}",
+            b"{\"state\":{}}",
+            b"{\"state\":\"idle\" \"id\":1}",
+        ] {
+            assert_eq!(
+                parse_command(payload, "state"),
+                Err(CommandError::InvalidJson.into())
+            );
+        }
+        assert_eq!(
+            parse_command(&[0xFF, 0xFE], "state"),
+            Err(CommandError::InvalidUtf8.into())
+        );
+    }
+
+    #[test]
+    fn test_reject_keeps_id() {
+        let command = parse_command(b"{\"id\":\"7\",\"state\":\"flying\"}", "state").unwrap();
+        assert_eq!(
+            command.reject(CommandError::InvalidValue),
+            CommandRejection {
+                id: Some("7"),
+                error: CommandError::InvalidValue
+            }
+        );
+    }
+}
@@ -0,0 +1,694 @@
+use crate::{
+    config::MeasurementId,
+    format_string::FormatString,
+    log_types::LogLevel,
+    measurements::MeasurementRange,
+    mqtt_command::{CommandError, CommandRejection},
+};
+use core::{
+    fmt::{self, Write},
+    str::FromStr,
+};
+use heapless::String;
+
+/// Version of the JSON payload schema sent to the base station.
+/// Increment this whenever a field is added, removed or changes meaning.
+pub const PAYLOAD_SCHEMA_VERSION: u8 = 2;
+
+/// Maximum size of an MQTT payload
+pub const MAX_PAYLOAD_SIZE: usize = 512;
+
+/// The encoding used for the MQTT payloads of a measurement.
+/// Configured per measurement topic in `config/telemetry.yaml`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum PayloadEncoding {
+    /// One JSON payload per reading, see `MeasurementPayload`
+    Json,
+    /// Batches of readings encoded as CBOR, see `mqtt::cbor::MeasurementBatch`
+    Cbor,
+}
+
+/// The value of a measurement reading
+#[derive(Debug, Clone, Copy, PartialEq, defmt::Format)]
+pub enum MeasurementValue {
+    Bool(bool),
+    Integer(u32),
+    Float(f32),
+    Pair([u16; 2]),
+}
+
+impl MeasurementValue {
+    /// Returns the value as a float if it is numeric
+    pub fn as_f32(&self) -> Option<f32> {
+        match self {
+            MeasurementValue::Integer(i) => Some(*i as f32),
+            MeasurementValue::Float(f) => Some(*f),
+            MeasurementValue::Bool(_) | MeasurementValue::Pair(_) => None,
+        }
+    }
+}
+
+/// A payload that can be serialised to JSON and sent over MQTT
+pub trait JsonPayload {
+    /// Writes the payload as JSON into the given buffer, returning the written string
+    fn write_json<'a>(&self, buffer: &'a mut [u8]) -> Result<&'a str, fmt::Error>;
+
+    /// Serialises the payload to JSON, ready to be put into an `MqttMessage`
+    fn to_payload(&self) -> Result<String<MAX_PAYLOAD_SIZE>, fmt::Error> {
+        let mut buffer = [0u8; MAX_PAYLOAD_SIZE];
+        String::from_str(self.write_json(&mut buffer)?).map_err(|_| fmt::Error)
+    }
+}
+
+/// A measurement reading from one of the boards.
+///
+/// E.g. `{"version":2,"type":"measurement","timestamp_ms":1200,"board":"navigation",
+/// "measurement":"velocity","value":4.2,"unit":"m/s","range":"safe"}`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MeasurementPayload<'a> {
+    /// Time since the sending board booted, in milliseconds
+    pub timestamp_ms: u64,
+    /// The board that took the measurement
+    pub board: &'a str,
+    pub measurement_id: MeasurementId,
+    pub value: MeasurementValue,
+}
+
+impl<'a> MeasurementPayload<'a> {
+    pub fn new(
+        timestamp_ms: u64,
+        board: &'a str,
+        measurement_id: MeasurementId,
+        value: MeasurementValue,
+    ) -> Self {
+        MeasurementPayload {
+            timestamp_ms,
+            board,
+            measurement_id,
+            value,
+        }
+    }
+
+    /// Classifies the value against the limits of the measurement.
+    /// Returns `None` for non-numeric values, which have no limits.
+    pub fn range(&self) -> Option<MeasurementRange> {
+        let value = self.value.as_f32()?;
+        if value.is_nan() {
+            return None;
+        }
+        Some(self.measurement_id.limits().classify(value))
+    }
+}
+
+impl JsonPayload for MeasurementPayload<'_> {
+    fn write_json<'a>(&self, buffer: &'a mut [u8]) -> Result<&'a str, fmt::Error> {
+        let mut writer = FormatString::new(buffer);
+        write_header(&mut writer, "measurement", self.timestamp_ms, self.board)?;
+        writer.write_str(",\"measurement\":")?;
+        write_json_string(&mut writer, self.measurement_id.into())?;
+        writer.write_str(",\"value\":")?;
+        write_json_value(&mut writer, &self.value)?;
+        writer.write_str(",\"unit\":")?;
+        write_json_string(&mut writer, self.measurement_id.unit())?;
+        writer.write_str(",\"range\":")?;
+        match self.range() {
+            Some(range) => write_json_string(&mut writer, range.into())?,
+            None => writer.write_str("null")?,
+        }
+        writer.write_char('}')?;
+        writer.as_str().ok_or(fmt::Error)
+    }
+}
+
+/// Summary statistics of the readings of a measurement over a window,
+/// see `aggregation::MeasurementAggregator`.
+/// `value` is the most recent reading, so the payload can be read like a `MeasurementPayload`.
+///
+/// E.g. `{"version":2,"type":"aggregate","timestamp_ms":1200,"board":"navigation",
+/// "measurement":"velocity","value":4.2,"min":4,"max":4.3,"mean":4.1,"count":20,
+/// "window_ms":95,"unit":"m/s","range":"safe"}`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AggregatePayload<'a> {
+    /// Time of the last reading in the window, in milliseconds since the sending board booted
+    pub timestamp_ms: u64,
+    /// The board that took the measurement
+    pub board: &'a str,
+    pub measurement_id: MeasurementId,
+    /// Time between the first and last reading in the window, in milliseconds
+    pub window_ms: u64,
+    pub count: u32,
+    pub min: f32,
+    pub max: f32,
+    pub mean: f32,
+    pub last: f32,
+}
+
+impl AggregatePayload<'_> {
+    /// Classifies the window by its most extreme values against the limits of the measurement
+    pub fn range(&self) -> MeasurementRange {
+        let limits = self.measurement_id.limits();
+        match (limits.classify(self.min), limits.classify(self.max)) {
+            (MeasurementRange::Critical, _) | (_, MeasurementRange::Critical) => {
+                MeasurementRange::Critical
+            }
+            (MeasurementRange::Warning, _) | (_, MeasurementRange::Warning) => {
+                MeasurementRange::Warning
+            }
+            _ => MeasurementRange::Safe,
+        }
+    }
+}
+
+impl JsonPayload for AggregatePayload<'_> {
+    fn write_json<'a>(&self, buffer: &'a mut [u8]) -> Result<&'a str, fmt::Error> {
+        let mut writer = FormatString::new(buffer);
+        write_header(&mut writer, "aggregate", self.timestamp_ms, self.board)?;
+        writer.write_str(",\"measurement\":")?;
+        write_json_string(&mut writer, self.measurement_id.into())?;
+        for (key, value) in [
+            ("value", self.last),
+            ("min", self.min),
+            ("max", self.max),
+            ("mean", self.mean),
+        ] {
+            write!(writer, ",\"{}\":", key)?;
+            write_json_value(&mut writer, &MeasurementValue::Float(value))?;
+        }
+        write!(
+            writer,
+            ",\"count\":{},\"window_ms\":{}",
+            self.count, self.window_ms
+        )?;
+        writer.write_str(",\"unit\":")?;
+        write_json_string(&mut writer, self.measurement_id.unit())?;
+        writer.write_str(",\"range\":")?;
+        write_json_string(&mut writer, self.range().into())?;
+        writer.write_char('}')?;
+        writer.as_str().ok_or(fmt::Error)
+    }
+}
+
+/// The current state of the pod, and the run mode that decides the states it goes through.
+///
+/// E.g. `{"version":2,"type":"state","timestamp_ms":1200,"board":"telemetry","state":"idle",
+/// "mode":"LEVITATION_ONLY"}`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StatePayload<'a> {
+    /// Time since the sending board booted, in milliseconds
+    pub timestamp_ms: u64,
+    /// The board that commanded the state
+    pub board: &'a str,
+    pub state: &'a str,
+    pub mode: &'a str,
+}
+
+impl<'a> StatePayload<'a> {
+    pub fn new(timestamp_ms: u64, board: &'a str, state: &'a str, mode: &'a str) -> Self {
+        StatePayload {
+            timestamp_ms,
+            board,
+            state,
+            mode,
+        }
+    }
+}
+
+impl JsonPayload for StatePayload<'_> {
+    fn write_json<'a>(&self, buffer: &'a mut [u8]) -> Result<&'a str, fmt::Error> {
+        let mut writer = FormatString::new(buffer);
+        write_header(&mut writer, "state", self.timestamp_ms, self.board)?;
+        writer.write_str(",\"state\":")?;
+        write_json_string(&mut writer, self.state)?;
+        writer.write_str(",\"mode\":")?;
+        write_json_string(&mut writer, self.mode)?;
+        writer.write_char('}')?;
+        writer.as_str().ok_or(fmt::Error)
+    }
+}
+
+/// A heartbeat sent to the base station, so it knows the board is alive and how it is configured.
+///
+/// E.g. `{"version":2,"type":"heartbeat","timestamp_ms":1200,"board":"telemetry",
+/// "mode":"LEVITATION_ONLY"}`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HeartbeatPayload<'a> {
+    /// Time since the sending board booted, in milliseconds
+    pub timestamp_ms: u64,
+    pub board: &'a str,
+    /// The run mode the board is configured with
+    pub mode: &'a str,
+}
+
+impl<'a> HeartbeatPayload<'a> {
+    pub fn new(timestamp_ms: u64, board: &'a str, mode: &'a str) -> Self {
+        HeartbeatPayload {
+            timestamp_ms,
+            board,
+            mode,
+        }
+    }
+}
+
+impl JsonPayload for HeartbeatPayload<'_> {
+    fn write_json<'a>(&self, buffer: &'a mut [u8]) -> Result<&'a str, fmt::Error> {
+        let mut writer = FormatString::new(buffer);
+        write_header(&mut writer, "heartbeat", self.timestamp_ms, self.board)?;
+        writer.write_str(",\"mode\":")?;
+        write_json_string(&mut writer, self.mode)?;
+        writer.write_char('}')?;
+        writer.as_str().ok_or(fmt::Error)
+    }
+}
+
+/// A state transition request that was rejected by the state machine.
+///
+/// E.g. `{"version":2,"type":"state_transition_rejected","timestamp_ms":1200,
+/// "board":"telemetry","requesting_board":"mqtt","current_state":"ready",
+/// "requested_state":"accelerate","reason":"guards_failed","reason_code":1,
+/// "failed_guards":["brakes_engaged"]}`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StateTransitionRejectedPayload<'a> {
+    /// Time since the sending board booted, in milliseconds
+    pub timestamp_ms: u64,
+    /// The board running the state machine
+    pub board: &'a str,
+    pub requesting_board: &'a str,
+    pub current_state: &'a str,
+    pub requested_state: &'a str,
+    pub reason: &'a str,
+    pub reason_code: u8,
+    /// Names of the guards that failed, empty unless `reason` is `guards_failed`
+    pub failed_guards: &'a [&'a str],
+}
+
+impl JsonPayload for StateTransitionRejectedPayload<'_> {
+    fn write_json<'a>(&self, buffer: &'a mut [u8]) -> Result<&'a str, fmt::Error> {
+        let mut writer = FormatString::new(buffer);
+        write_header(
+            &mut writer,
+            "state_transition_rejected",
+            self.timestamp_ms,
+            self.board,
+        )?;
+        for (key, value) in [
+            ("requesting_board", self.requesting_board),
+            ("current_state", self.current_state),
+            ("requested_state", self.requested_state),
+            ("reason", self.reason),
+        ] {
+            write!(writer, ",\"{}\":", key)?;
+            write_json_string(&mut writer, value)?;
+        }
+        write!(writer, ",\"reason_code\":{}", self.reason_code)?;
+        writer.write_str(",\"failed_guards\":[")?;
+        for (i, guard) in self.failed_guards.iter().enumerate() {
+            if i > 0 {
+                writer.write_char(',')?;
+            }
+            write_json_string(&mut writer, guard)?;
+        }
+        writer.write_str("]}")?;
+        writer.as_str().ok_or(fmt::Error)
+    }
+}
+
+/// An entry of the state machine's audit log, sent when it is recorded and when the log is
+/// queried. `reason` is `null` unless the request was rejected.
+///
+/// E.g. `{"version":2,"type":"state_audit","timestamp_ms":1200,"board":"telemetry",
+/// "sequence":3,"requester":"mqtt","from_state":"idle","to_state":"calibrate",
+/// "outcome":"rejected","reason":"unauthorised"}`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StateAuditPayload<'a> {
+    /// Time since the board running the state machine booted when the entry was recorded,
+    /// in milliseconds
+    pub timestamp_ms: u64,
+    /// The board running the state machine
+    pub board: &'a str,
+    /// Position of the entry in the audit log, counting from boot
+    pub sequence: u32,
+    pub requester: &'a str,
+    pub from_state: &'a str,
+    pub to_state: &'a str,
+    pub outcome: &'a str,
+    pub reason: Option<&'a str>,
+}
+
+impl JsonPayload for StateAuditPayload<'_> {
+    fn write_json<'a>(&self, buffer: &'a mut [u8]) -> Result<&'a str, fmt::Error> {
+        let mut writer = FormatString::new(buffer);
+        write_header(&mut writer, "state_audit", self.timestamp_ms, self.board)?;
+        write!(writer, ",\"sequence\":{}", self.sequence)?;
+        for (key, value) in [
+            ("requester", self.requester),
+            ("from_state", self.from_state),
+            ("to_state", self.to_state),
+            ("outcome", self.outcome),
+        ] {
+            write!(writer, ",\"{}\":", key)?;
+            write_json_string(&mut writer, value)?;
+        }
+        writer.write_str(",\"reason\":")?;
+        match self.reason {
+            Some(reason) => write_json_string(&mut writer, reason)?,
+            None => writer.write_str("null")?,
+        }
+        writer.write_char('}')?;
+        writer.as_str().ok_or(fmt::Error)
+    }
+}
+
+/// A log message from one of the boards.
+///
+/// E.g. `{"version":2,"type":"log","timestamp_ms":1200,"board":"telemetry","level":"info",
+/// "message":"Connected!"}`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LogPayload<'a> {
+    /// Time since the sending board booted, in milliseconds
+    pub timestamp_ms: u64,
+    /// The board that logged the message
+    pub board: &'a str,
+    pub level: LogLevel,
+    pub message: &'a str,
+}
+
+impl<'a> LogPayload<'a> {
+    pub fn new(timestamp_ms: u64, board: &'a str, level: LogLevel, message: &'a str) -> Self {
+        LogPayload {
+            timestamp_ms,
+            board,
+            level,
+            message,
+        }
+    }
+}
+
+impl JsonPayload for LogPayload<'_> {
+    fn write_json<'a>(&self, buffer: &'a mut [u8]) -> Result<&'a str, fmt::Error> {
+        let mut writer = FormatString::new(buffer);
+        write_header(&mut writer, "log", self.timestamp_ms, self.board)?;
+        writer.write_str(",\"level\":")?;
+        write_json_string(&mut writer, self.level.into())?;
+        writer.write_str(",\"message\":")?;
+        write_json_string(&mut writer, self.message)?;
+        writer.write_char('}')?;
+        writer.as_str().ok_or(fmt::Error)
+    }
+}
+
+/// The response to a command received over MQTT, so the sender knows whether it was accepted.
+///
+/// E.g. `{"version":2,"type":"command_response","timestamp_ms":1200,"board":"telemetry",
+/// "command":"state","id":"42","status":"error","error":"invalid_value"}`.
+/// Commands refused by a safety interlock also name it, e.g. `"interlock":"brakes_released"`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CommandResponsePayload<'a> {
+    /// Time since the sending board booted, in milliseconds
+    pub timestamp_ms: u64,
+    /// The board that handled the command
+    pub board: &'a str,
+    /// The kind of command, e.g. `state`
+    pub command: &'a str,
+    /// `Ok` with the correlation ID if the command was accepted
+    pub result: Result<Option<&'a str>, CommandRejection<'a>>,
+}
+
+impl<'a> CommandResponsePayload<'a> {
+    pub fn new(
+        timestamp_ms: u64,
+        board: &'a str,
+        command: &'a str,
+        result: Result<Option<&'a str>, CommandRejection<'a>>,
+    ) -> Self {
+        CommandResponsePayload {
+            timestamp_ms,
+            board,
+            command,
+            result,
+        }
+    }
+}
+
+impl JsonPayload for CommandResponsePayload<'_> {
+    fn write_json<'a>(&self, buffer: &'a mut [u8]) -> Result<&'a str, fmt::Error> {
+        let mut writer = FormatString::new(buffer);
+        write_header(
+            &mut writer,
+            "command_response",
+            self.timestamp_ms,
+            self.board,
+        )?;
+        writer.write_str(",\"command\":")?;
+        write_json_string(&mut writer, self.command)?;
+        let (id, error) = match self.result {
+            Ok(id) => (id, None),
+            Err(rejection) => (rejection.id, Some(rejection.error)),
+        };
+        writer.write_str(",\"id\":")?;
+        match id {
+            Some(id) => write_json_string(&mut writer, id)?,
+            None => writer.write_str("null")?,
+        }
+        match error {
+            Some(error) => {
+                writer.write_str(",\"status\":\"error\",\"error\":")?;
+                write_json_string(&mut writer, error.into())?;
+                if let CommandError::Interlocked(interlock) = error {
+                    writer.write_str(",\"interlock\":")?;
+                    write_json_string(&mut writer, interlock)?;
+                }
+            }
+            None => writer.write_str(",\"status\":\"ack\",\"error\":null")?,
+        }
+        writer.write_char('}')?;
+        writer.as_str().ok_or(fmt::Error)
+    }
+}
+
+/// Writes the fields common to all payloads, leaving the object open for more fields
+fn write_header<W: Write>(
+    writer: &mut W,
+    payload_type: &str,
+    timestamp_ms: u64,
+    board: &str,
+) -> fmt::Result {
+    write!(
+        writer,
+        "{{\"version\":{},\"type\":\"{}\",\"timestamp_ms\":{},\"board\":",
+        PAYLOAD_SCHEMA_VERSION, payload_type, timestamp_ms
+    )?;
+    write_json_string(writer, board)
+}
+
+/// Writes a measurement value as a JSON value.
+/// Non-finite floats are not valid JSON, so are written as `null`.
+fn write_json_value<W: Write>(writer: &mut W, value: &MeasurementValue) -> fmt::Result {
+    match value {
+        MeasurementValue::Bool(b) => write!(writer, "{}", b),
+        MeasurementValue::Integer(i) => write!(writer, "{}", i),
+        MeasurementValue::Float(f) if f.is_finite() => write!(writer, "{}", f),
+        MeasurementValue::Float(_) => writer.write_str("null"),
+        MeasurementValue::Pair([a, b]) => write!(writer, "[{},{}]", a, b),
+    }
+}
+
+/// Writes a string as a quoted JSON string, escaping any special characters
+pub fn write_json_string<W: Write>(writer: &mut W, s: &str) -> fmt::Result {
+    writer.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '"' => writer.write_str("\\\"")?,
+            '\\' => writer.write_str("\\\\")?,
+            '\n' => writer.write_str("\\n")?,
+            '\r' => writer.write_str("\\r")?,
+            '\t' => writer.write_str("\\t")?,
+            c if (c as u32) < 0x20 => write!(writer, "\\u{:04x}", c as u32)?,
+            c => writer.write_char(c)?,
+        }
+    }
+    writer.write_char('"')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_measurement_payload() {
+        let payload = MeasurementPayload::new(
+            1200,
+            "navigation",
+            MeasurementId::Velocity,
+            MeasurementValue::Float(4.5),
+        );
+        let mut buffer = [0u8; MAX_PAYLOAD_SIZE];
+        assert_eq!(
+            payload.write_json(&mut buffer),
+            Ok("{\"version\":2,\"type\":\"measurement\",\"timestamp_ms\":1200,\"board\":\"navigation\",\"measurement\":\"velocity\",\"value\":4.5,\"unit\":\"m/s\",\"range\":\"safe\"}")
+        );
+    }
+
+    #[test]
+    fn test_measurement_payload_critical() {
+        let payload = MeasurementPayload::new(
+            0,
+            "pneumatics",
+            MeasurementId::PressureBrakesReservoir,
+            MeasurementValue::Float(2.0),
+        );
+        assert_eq!(payload.range(), Some(MeasurementRange::Critical));
+    }
+
+    #[test]
+    fn test_measurement_payload_non_numeric() {
+        let payload = MeasurementPayload::new(
+            0,
+            "navigation",
+            MeasurementId::Keyence1,
+            MeasurementValue::Pair([1, 2]),
+        );
+        let mut buffer = [0u8; MAX_PAYLOAD_SIZE];
+        let json = payload.write_json(&mut buffer).unwrap();
+        assert!(json.contains("\"value\":[1,2]"));
+        assert!(json.ends_with("\"range\":null}"));
+    }
+
+    #[test]
+    fn test_measurement_payload_nan() {
+        let payload = MeasurementPayload::new(
+            0,
+            "navigation",
+            MeasurementId::Velocity,
+            MeasurementValue::Float(f32::NAN),
+        );
+        let mut buffer = [0u8; MAX_PAYLOAD_SIZE];
+        let json = payload.write_json(&mut buffer).unwrap();
+        assert!(json.contains("\"value\":null"));
+        assert!(json.ends_with("\"range\":null}"));
+    }
+
+    #[test]
+    fn test_aggregate_payload() {
+        let payload = AggregatePayload {
+            timestamp_ms: 100,
+            board: "pneumatics",
+            measurement_id: MeasurementId::PressureBackPull,
+            window_ms: 90,
+            count: 10,
+            min: 1.0,
+            max: 5.25,
+            mean: 3.5,
+            last: 2.0,
+        };
+        assert_eq!(payload.range(), MeasurementRange::Warning);
+        assert_eq!(
+            payload.to_payload().unwrap().as_str(),
+            "{\"version\":2,\"type\":\"aggregate\",\"timestamp_ms\":100,\"board\":\"pneumatics\",\"measurement\":\"pressure_back_pull\",\"value\":2,\"min\":1,\"max\":5.25,\"mean\":3.5,\"count\":10,\"window_ms\":90,\"unit\":\"bar\",\"range\":\"warning\"}"
+        );
+    }
+
+    #[test]
+    fn test_state_payload() {
+        let payload = StatePayload::new(5, "telemetry", "calibrate", "LEVITATION_ONLY");
+        assert_eq!(
+            payload.to_payload().unwrap().as_str(),
+            "{\"version\":2,\"type\":\"state\",\"timestamp_ms\":5,\"board\":\"telemetry\",\"state\":\"calibrate\",\"mode\":\"LEVITATION_ONLY\"}"
+        );
+    }
+
+    #[test]
+    fn test_heartbeat_payload() {
+        let payload = HeartbeatPayload::new(5, "telemetry", "STATIC_TEST");
+        assert_eq!(
+            payload.to_payload().unwrap().as_str(),
+            "{\"version\":2,\"type\":\"heartbeat\",\"timestamp_ms\":5,\"board\":\"telemetry\",\"mode\":\"STATIC_TEST\"}"
+        );
+    }
+
+    #[test]
+    fn test_log_payload_escapes_message() {
+        let payload = LogPayload::new(5, "telemetry", LogLevel::Warn, "Bad \"topic\"\n");
+        assert_eq!(
+            payload.to_payload().unwrap().as_str(),
+            "{\"version\":2,\"type\":\"log\",\"timestamp_ms\":5,\"board\":\"telemetry\",\"level\":\"warn\",\"message\":\"Bad \\\"topic\\\"\\n\"}"
+        );
+    }
+
+    #[test]
+    fn test_command_response_payload() {
+        let ack = CommandResponsePayload::new(5, "telemetry", "state", Ok(Some("42")));
+        assert_eq!(
+            ack.to_payload().unwrap().as_str(),
+            "{\"version\":2,\"type\":\"command_response\",\"timestamp_ms\":5,\"board\":\"telemetry\",\"command\":\"state\",\"id\":\"42\",\"status\":\"ack\",\"error\":null}"
+        );
+
+        let rejection = CommandRejection {
+            id: None,
+            error: CommandError::InvalidJson,
+        };
+        let error = CommandResponsePayload::new(5, "telemetry", "state", Err(rejection));
+        assert!(error
+            .to_payload()
+            .unwrap()
+            .ends_with("\"id\":null,\"status\":\"error\",\"error\":\"invalid_json\"}"));
+
+        let interlocked = CommandRejection {
+            id: Some("3"),
+            error: CommandError::Interlocked("brakes_released"),
+        };
+        let error = CommandResponsePayload::new(5, "telemetry", "actuator", Err(interlocked));
+        assert!(error.to_payload().unwrap().ends_with(
+            "\"status\":\"error\",\"error\":\"interlocked\",\"interlock\":\"brakes_released\"}"
+        ));
+    }
+
+    #[test]
+    fn test_state_transition_rejected_payload() {
+        let payload = StateTransitionRejectedPayload {
+            timestamp_ms: 5,
+            board: "telemetry",
+            requesting_board: "mqtt",
+            current_state: "ready",
+            requested_state: "accelerate",
+            reason: "guards_failed",
+            reason_code: 1,
+            failed_guards: &["brakes_engaged", "localisation_invalid"],
+        };
+        assert_eq!(
+            payload.to_payload().unwrap().as_str(),
+            "{\"version\":2,\"type\":\"state_transition_rejected\",\"timestamp_ms\":5,\"board\":\"telemetry\",\"requesting_board\":\"mqtt\",\"current_state\":\"ready\",\"requested_state\":\"accelerate\",\"reason\":\"guards_failed\",\"reason_code\":1,\"failed_guards\":[\"brakes_engaged\",\"localisation_invalid\"]}"
+        );
+    }
+
+    #[test]
+    fn test_state_audit_payload() {
+        let mut payload = StateAuditPayload {
+            timestamp_ms: 5,
+            board: "telemetry",
+            sequence: 3,
+            requester: "mqtt",
+            from_state: "idle",
+            to_state: "calibrate",
+            outcome: "accepted",
+            reason: None,
+        };
+        assert_eq!(
+            payload.to_payload().unwrap().as_str(),
+            "{\"version\":2,\"type\":\"state_audit\",\"timestamp_ms\":5,\"board\":\"telemetry\",\"sequence\":3,\"requester\":\"mqtt\",\"from_state\":\"idle\",\"to_state\":\"calibrate\",\"outcome\":\"accepted\",\"reason\":null}"
+        );
+
+        payload.outcome = "rejected";
+        payload.reason = Some("unauthorised");
+        assert!(payload
+            .to_payload()
+            .unwrap()
+            .ends_with("\"outcome\":\"rejected\",\"reason\":\"unauthorised\"}"));
+    }
+
+    #[test]
+    fn test_payload_too_large() {
+        let payload = LogPayload::new(5, "telemetry", LogLevel::Info, "message");
+        let mut buffer = [0u8; 16];
+        assert!(payload.write_json(&mut buffer).is_err());
+    }
+}
@@ -5,13 +5,30 @@ use core::str::FromStr;
 use heapless::String;
 
 pub const MQTT_MEASUREMENT_TOPIC_PREFIX: &str = "hyped/poddington/measurement/";
+pub const MQTT_MEASUREMENT_BATCH_TOPIC_PREFIX: &str = "hyped/poddington/measurement_batch/";
 
 /// Enum representing all MQTT topics used by the pod
 #[derive(Debug, defmt::Format, PartialEq, Eq)]
 pub enum MqttTopic {
     Measurement(MeasurementId),
+    /// Batches of measurements, encoded as CBOR
+    MeasurementBatch(MeasurementId),
     State,
     StateRequest,
+    /// State transition requests rejected by the state machine,
+    /// see `mqtt_payload::StateTransitionRejectedPayload`
+    StateTransitionRejected,
+    /// Entries of the state machine's audit log, see `mqtt_payload::StateAuditPayload`.
+    /// The latest entry is retained by the broker.
+    StateAudit,
+    /// Queries of the state machine's audit log, answered on `StateAudit`
+    StateAuditRequest,
+    /// Actuator commands from the base station, only accepted in the maintenance state
+    MaintenanceCommand,
+    /// Responses to commands received over MQTT, see `mqtt_payload::CommandResponsePayload`
+    CommandResponse,
+    /// Remote emergency stop from the base station, handled ahead of all other messages
+    EmergencyStop,
     Heartbeat,
     Logs,
     Debug,
@@ -25,6 +42,12 @@ impl FromStr for MqttTopic {
         match s {
             "hyped/poddington/state/state" => Ok(MqttTopic::State),
             "hyped/poddington/state/state_request" => Ok(MqttTopic::StateRequest),
+            "hyped/poddington/state/rejected" => Ok(MqttTopic::StateTransitionRejected),
+            "hyped/poddington/state/audit" => Ok(MqttTopic::StateAudit),
+            "hyped/poddington/state/audit_request" => Ok(MqttTopic::StateAuditRequest),
+            "hyped/poddington/maintenance/command" => Ok(MqttTopic::MaintenanceCommand),
+            "hyped/poddington/command_response" => Ok(MqttTopic::CommandResponse),
+            "hyped/poddington/controls/stop" => Ok(MqttTopic::EmergencyStop),
             "hyped/poddington/heartbeat" => Ok(MqttTopic::Heartbeat),
             "hyped/poddington/logs" => Ok(MqttTopic::Logs),
             "debug" => Ok(MqttTopic::Debug),
@@ -32,8 +55,13 @@ impl FromStr for MqttTopic {
             _ => {
                 if s.starts_with(MQTT_MEASUREMENT_TOPIC_PREFIX) {
                     let measurement_id_string = &s[MQTT_MEASUREMENT_TOPIC_PREFIX.len()..s.len()];
-                    let measurement_id = measurement_id_string.into();
+                    let measurement_id = measurement_id_string.parse()?;
                     Ok(MqttTopic::Measurement(measurement_id))
+                } else if s.starts_with(MQTT_MEASUREMENT_BATCH_TOPIC_PREFIX) {
+                    let measurement_id_string =
+                        &s[MQTT_MEASUREMENT_BATCH_TOPIC_PREFIX.len()..s.len()];
+                    let measurement_id = measurement_id_string.parse()?;
+                    Ok(MqttTopic::MeasurementBatch(measurement_id))
                 } else {
                     Err("Invalid topic")
                 }
@@ -50,6 +78,20 @@ impl From<MqttTopic> for String<100> {
             MqttTopic::StateRequest => topic
                 .push_str("hyped/poddington/state/state_request")
                 .unwrap(),
+            MqttTopic::StateTransitionRejected => {
+                topic.push_str("hyped/poddington/state/rejected").unwrap()
+            }
+            MqttTopic::StateAudit => topic.push_str("hyped/poddington/state/audit").unwrap(),
+            MqttTopic::StateAuditRequest => topic
+                .push_str("hyped/poddington/state/audit_request")
+                .unwrap(),
+            MqttTopic::MaintenanceCommand => topic
+                .push_str("hyped/poddington/maintenance/command")
+                .unwrap(),
+            MqttTopic::CommandResponse => {
+                topic.push_str("hyped/poddington/command_response").unwrap()
+            }
+            MqttTopic::EmergencyStop => topic.push_str("hyped/poddington/controls/stop").unwrap(),
             MqttTopic::Heartbeat => topic.push_str("hyped/poddington/heartbeat").unwrap(),
             MqttTopic::Logs => topic.push_str("hyped/poddington/logs").unwrap(),
             MqttTopic::Debug => topic.push_str("debug").unwrap(),
@@ -58,6 +100,10 @@ impl From<MqttTopic> for String<100> {
                 topic.push_str(MQTT_MEASUREMENT_TOPIC_PREFIX).unwrap();
                 topic.push_str(measurement_id.into()).unwrap();
             }
+            MqttTopic::MeasurementBatch(measurement_id) => {
+                topic.push_str(MQTT_MEASUREMENT_BATCH_TOPIC_PREFIX).unwrap();
+                topic.push_str(measurement_id.into()).unwrap();
+            }
         }
         topic
     }
@@ -23,6 +23,43 @@ impl From<DigitalSignal> for bool {
     }
 }
 
+/// The sensors the localiser is relying on, sent as the `localisation_mode` status so the state
+/// machine can decide whether it is safe to keep going
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, defmt::Format)]
+#[repr(u8)]
+pub enum LocalisationMode {
+    /// Accelerometers, corrected by the keyence sensors and optical flow
+    #[default]
+    Nominal = 0,
+    /// Accelerometers alone, so the error grows without anything to correct it
+    DeadReckoning = 1,
+    /// Keyence sensors and optical flow, without knowing the acceleration between their readings
+    NoAccelerometers = 2,
+    /// None of the sensors can be trusted
+    Lost = 3,
+}
+
+impl LocalisationMode {
+    /// Whether the localiser has lost a class of sensors
+    pub fn is_degraded(&self) -> bool {
+        *self != LocalisationMode::Nominal
+    }
+}
+
+impl TryFrom<u8> for LocalisationMode {
+    type Error = &'static str;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(LocalisationMode::Nominal),
+            1 => Ok(LocalisationMode::DeadReckoning),
+            2 => Ok(LocalisationMode::NoAccelerometers),
+            3 => Ok(LocalisationMode::Lost),
+            _ => Err("Invalid localisation mode"),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -32,4 +69,19 @@ mod tests {
         assert_eq!(DigitalSignal::from_bool(true), DigitalSignal::High);
         assert_eq!(DigitalSignal::from_bool(false), DigitalSignal::Low);
     }
+
+    #[test]
+    fn test_localisation_mode() {
+        for mode in [
+            LocalisationMode::Nominal,
+            LocalisationMode::DeadReckoning,
+            LocalisationMode::NoAccelerometers,
+            LocalisationMode::Lost,
+        ] {
+            assert_eq!(LocalisationMode::try_from(mode as u8), Ok(mode));
+        }
+        assert!(LocalisationMode::try_from(4).is_err());
+        assert!(!LocalisationMode::Nominal.is_degraded());
+        assert!(LocalisationMode::Lost.is_degraded());
+    }
 }
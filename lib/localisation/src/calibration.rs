@@ -0,0 +1,154 @@
+use crate::{
+    config::{
+        CALIBRATION_SAMPLES, MAX_ACCELEROMETER_BIAS, MAX_ACCELEROMETER_VARIANCE,
+        NUM_ACCELEROMETERS, NUM_AXIS,
+    },
+    types::RawAccelerometerData,
+};
+use nalgebra::Vector3;
+
+/// Calibration of a single accelerometer, on its own axes
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct SensorCalibration {
+    /// Mean reading while the pod is stationary, which is gravity plus the bias (m/s^2)
+    pub gravity_reference: Vector3<f32>,
+    /// Difference of the mean reading from what a perfect accelerometer would read (m/s^2)
+    pub bias: Vector3<f32>,
+    /// Variance of the readings ((m/s^2)^2)
+    pub variance: Vector3<f32>,
+    /// Whether the bias and variance are within tolerance on every axis
+    pub passed: bool,
+}
+
+/// Calibration of every accelerometer, as returned by `AccelerometerCalibrator::finish`.
+/// Whether each accelerometer passed is sent over CAN for the guard on leaving the calibrate
+/// state.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct AccelerometerCalibration {
+    pub sensors: [SensorCalibration; NUM_ACCELEROMETERS],
+}
+
+impl AccelerometerCalibration {
+    /// Whether each accelerometer passed calibration
+    pub fn passed(&self) -> [bool; NUM_ACCELEROMETERS] {
+        self.sensors.map(|sensor| sensor.passed)
+    }
+
+    pub fn all_passed(&self) -> bool {
+        self.sensors.iter().all(|sensor| sensor.passed)
+    }
+}
+
+/// Collects accelerometer readings while the pod is stationary to estimate the bias and noise of
+/// each axis of each accelerometer
+pub struct AccelerometerCalibrator {
+    num_samples: usize,
+    /// Running mean of each axis
+    means: [Vector3<f64>; NUM_ACCELEROMETERS],
+    /// Running sum of squared differences from the mean of each axis, as in Welford's algorithm
+    squared_differences: [Vector3<f64>; NUM_ACCELEROMETERS],
+}
+
+impl Default for AccelerometerCalibrator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AccelerometerCalibrator {
+    pub fn new() -> Self {
+        AccelerometerCalibrator {
+            num_samples: 0,
+            means: [Vector3::zeros(); NUM_ACCELEROMETERS],
+            squared_differences: [Vector3::zeros(); NUM_ACCELEROMETERS],
+        }
+    }
+
+    /// Adds a reading of every accelerometer, taken while the pod is stationary
+    pub fn add_sample(&mut self, data: &RawAccelerometerData<NUM_ACCELEROMETERS, NUM_AXIS>) {
+        self.num_samples += 1;
+        let num_samples = self.num_samples as f64;
+        for (i, axes) in data.iter().enumerate() {
+            let reading = Vector3::new(axes[0] as f64, axes[1] as f64, axes[2] as f64);
+            let difference = reading - self.means[i];
+            self.means[i] += difference / num_samples;
+            self.squared_differences[i] += difference.component_mul(&(reading - self.means[i]));
+        }
+    }
+
+    pub fn num_samples(&self) -> usize {
+        self.num_samples
+    }
+
+    /// Whether enough readings have been collected to calibrate the accelerometers
+    pub fn is_complete(&self) -> bool {
+        self.num_samples >= CALIBRATION_SAMPLES
+    }
+
+    /// Calibrates each accelerometer from the readings collected, given what each would read
+    /// while stationary if it were perfect. Returns `None` until enough readings are collected.
+    pub fn finish(
+        &self,
+        expected_readings: [Vector3<f32>; NUM_ACCELEROMETERS],
+    ) -> Option<AccelerometerCalibration> {
+        if !self.is_complete() || self.num_samples < 2 {
+            return None;
+        }
+
+        let mut calibration = AccelerometerCalibration::default();
+        for (i, sensor) in calibration.sensors.iter_mut().enumerate() {
+            let gravity_reference = self.means[i].cast::<f32>();
+            let bias = gravity_reference - expected_readings[i];
+            let variance = (self.squared_differences[i] / (self.num_samples - 1) as f64).cast();
+            *sensor = SensorCalibration {
+                gravity_reference,
+                bias,
+                variance,
+                passed: bias.iter().all(|bias| bias.abs() <= MAX_ACCELEROMETER_BIAS)
+                    && variance
+                        .iter()
+                        .all(|variance| *variance <= MAX_ACCELEROMETER_VARIANCE),
+            };
+        }
+        Some(calibration)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::GRAVITY;
+    use heapless::Vec;
+
+    #[test]
+    fn test_calibration() {
+        let mut calibrator = AccelerometerCalibrator::new();
+        let expected_readings = [Vector3::new(0.0, 0.0, GRAVITY); NUM_ACCELEROMETERS];
+        assert!(calibrator.finish(expected_readings).is_none());
+
+        // The first accelerometer reads 0.1 m/s^2 high on x, the second is far too noisy and
+        // the third is knocked off level
+        for sample in 0..CALIBRATION_SAMPLES {
+            let noise = if sample % 2 == 0 { 0.05 } else { -0.05 };
+            let data: RawAccelerometerData<NUM_ACCELEROMETERS, NUM_AXIS> =
+                RawAccelerometerData::from_slice(&[
+                    Vec::from_slice(&[0.1 + noise, noise, GRAVITY - noise]).unwrap(),
+                    Vec::from_slice(&[noise * 10.0, 0.0, GRAVITY]).unwrap(),
+                    Vec::from_slice(&[1.0, 0.0, GRAVITY]).unwrap(),
+                    Vec::from_slice(&[noise, noise, GRAVITY + noise]).unwrap(),
+                ])
+                .unwrap();
+            calibrator.add_sample(&data);
+        }
+        assert!(calibrator.is_complete());
+
+        let calibration = calibrator.finish(expected_readings).unwrap();
+        let first = calibration.sensors[0];
+        assert!((first.bias.x - 0.1).abs() < 1e-4);
+        assert!(first.bias.z.abs() < 1e-4);
+        assert!((first.gravity_reference.z - GRAVITY).abs() < 1e-4);
+        assert!((first.variance.x - 0.0025).abs() < 1e-4);
+        assert_eq!(calibration.passed(), [true, false, false, true]);
+        assert!(!calibration.all_passed());
+    }
+}
@@ -6,3 +6,64 @@ pub const NUM_OPTICAL_FLOW_SENSORS: usize = LOCALISATION_CONFIG.optical_flow.num
 pub const NUM_KEYENCE_SENSORS: usize = LOCALISATION_CONFIG.keyence.num_sensors as usize;
 pub const NUM_ALLOWED_ACCELEROMETER_OUTLIERS: usize =
     LOCALISATION_CONFIG.accelerometers.num_allowed_outliers as usize;
+pub const GRAVITY: f32 = LOCALISATION_CONFIG.accelerometers.gravity as f32;
+/// Roll, pitch and yaw of each accelerometer's mounting, in degrees
+pub const ACCELEROMETER_MOUNTING: [[f32; 3]; NUM_ACCELEROMETERS] = {
+    let mounting = &LOCALISATION_CONFIG.accelerometers.mounting;
+    [
+        [
+            mounting.sensor_0.roll as f32,
+            mounting.sensor_0.pitch as f32,
+            mounting.sensor_0.yaw as f32,
+        ],
+        [
+            mounting.sensor_1.roll as f32,
+            mounting.sensor_1.pitch as f32,
+            mounting.sensor_1.yaw as f32,
+        ],
+        [
+            mounting.sensor_2.roll as f32,
+            mounting.sensor_2.pitch as f32,
+            mounting.sensor_2.yaw as f32,
+        ],
+        [
+            mounting.sensor_3.roll as f32,
+            mounting.sensor_3.pitch as f32,
+            mounting.sensor_3.yaw as f32,
+        ],
+    ]
+};
+pub const CALIBRATION_SAMPLES: usize = LOCALISATION_CONFIG.calibration.num_samples as usize;
+pub const MAX_ACCELEROMETER_BIAS: f32 = LOCALISATION_CONFIG.calibration.max_bias as f32;
+pub const MAX_ACCELEROMETER_VARIANCE: f32 = LOCALISATION_CONFIG.calibration.max_variance as f32;
+pub const OPTICAL_FLOW_FIELD_OF_VIEW: f64 = LOCALISATION_CONFIG.optical_flow.field_of_view;
+pub const OPTICAL_FLOW_RESOLUTION: f64 = LOCALISATION_CONFIG.optical_flow.resolution as f64;
+pub const OPTICAL_FLOW_SAMPLE_INTERVAL: f64 = LOCALISATION_CONFIG.optical_flow.sample_interval;
+pub const OPTICAL_FLOW_NOMINAL_HEIGHT: f64 = LOCALISATION_CONFIG.optical_flow.nominal_height;
+pub const OPTICAL_FLOW_HEIGHT_OFFSET: f64 = LOCALISATION_CONFIG.optical_flow.height_offset;
+pub const OPTICAL_FLOW_ORIENTATION: u32 = LOCALISATION_CONFIG.optical_flow.orientation as u32;
+pub const STRIPE_SPACING: f64 = LOCALISATION_CONFIG.keyence.stripe_spacing;
+pub const INITIAL_DISPLACEMENT_VARIANCE: f64 = LOCALISATION_CONFIG
+    .kalman_filter
+    .initial_displacement_variance;
+pub const INITIAL_VELOCITY_VARIANCE: f64 =
+    LOCALISATION_CONFIG.kalman_filter.initial_velocity_variance;
+pub const ACCELEROMETER_VARIANCE: f64 = LOCALISATION_CONFIG.kalman_filter.accelerometer_variance;
+pub const KEYENCE_VARIANCE: f64 = LOCALISATION_CONFIG.kalman_filter.keyence_variance;
+pub const OPTICAL_FLOW_VARIANCE: f64 = LOCALISATION_CONFIG.kalman_filter.optical_flow_variance;
+pub const ZERO_VELOCITY_VARIANCE: f64 = LOCALISATION_CONFIG.kalman_filter.zero_velocity_variance;
+pub const INITIAL_ACCELEROMETER_BIAS_VARIANCE: f64 = LOCALISATION_CONFIG
+    .kalman_filter
+    .initial_accelerometer_bias_variance;
+pub const ACCELEROMETER_BIAS_DRIFT: f64 =
+    LOCALISATION_CONFIG.kalman_filter.accelerometer_bias_drift;
+pub const INNOVATION_GATE: f64 = LOCALISATION_CONFIG.kalman_filter.innovation_gate;
+pub const MAX_CONSECUTIVE_REJECTIONS: u32 =
+    LOCALISATION_CONFIG.kalman_filter.max_consecutive_rejections as u32;
+pub const RECOVERY_PASSES: u32 = LOCALISATION_CONFIG.kalman_filter.recovery_passes as u32;
+pub const DEAD_RECKONING_VARIANCE_SCALE: f64 = LOCALISATION_CONFIG
+    .degraded_modes
+    .dead_reckoning_variance_scale;
+pub const UNMEASURED_ACCELERATION_VARIANCE: f64 = LOCALISATION_CONFIG
+    .degraded_modes
+    .unmeasured_acceleration_variance;
@@ -1,90 +1,104 @@
 use core::slice::from_ref;
 
 use crate::{
-    config::{NUM_ACCELEROMETERS, NUM_AXIS},
-    filtering::kalman_filter::KalmanFilter,
+    calibration::{AccelerometerCalibration, AccelerometerCalibrator},
+    config::{
+        ACCELEROMETER_BIAS_DRIFT, ACCELEROMETER_VARIANCE, DEAD_RECKONING_VARIANCE_SCALE,
+        INITIAL_ACCELEROMETER_BIAS_VARIANCE, INITIAL_DISPLACEMENT_VARIANCE,
+        INITIAL_VELOCITY_VARIANCE, INNOVATION_GATE, KEYENCE_VARIANCE, MAX_CONSECUTIVE_REJECTIONS,
+        NUM_ACCELEROMETERS, NUM_AXIS, NUM_KEYENCE_SENSORS, OPTICAL_FLOW_NOMINAL_HEIGHT,
+        OPTICAL_FLOW_VARIANCE, RECOVERY_PASSES, STRIPE_SPACING, UNMEASURED_ACCELERATION_VARIANCE,
+        ZERO_VELOCITY_VARIANCE,
+    },
+    filtering::{
+        kalman_filter::{KalmanFilter, UpdateError},
+        models::{
+            position_velocity_bias, TransitionModel, ACCELEROMETER_BIAS, DISPLACEMENT, VELOCITY,
+        },
+    },
     preprocessing::{
         accelerometer::AccelerometerPreprocessor,
         keyence::{KeyenceAgrees, SensorChecks},
         optical::process_optical_data,
     },
-    types::RawAccelerometerData,
+    types::{LocalisationEstimate, LocaliserHealth, RawAccelerometerData, SensorHealth},
 };
 
 use heapless::Vec;
-use libm::pow;
-use nalgebra::{Matrix2, Vector1, Vector2};
-
-//TODOLater: Confirm values are correct
-
-// Time step (s)
-const DELTA_T: f64 = 0.01;
-
-// Stripe width (m)
-const STRIPE_WIDTH: f64 = 1.0;
+use hyped_core::types::LocalisationMode;
+use libm::sqrt;
+use nalgebra::{Matrix3, RowVector3, Vector1, Vector3};
+
+/// Sensor data received since the previous iteration of the localiser.
+/// Sensors report at different rates, so any of them may be missing.
+#[derive(Debug, Clone, Default)]
+pub struct SensorReadings {
+    /// Pixels counted along the x and y axes of the optical flow sensor
+    pub optical_data: Option<Vec<f64, 2>>,
+    /// Height of the pod above the track from laser triangulation, in metres
+    pub height: Option<f64>,
+    pub keyence_data: Option<Vec<u32, NUM_KEYENCE_SENSORS>>,
+    pub accelerometer_data: Option<RawAccelerometerData<NUM_ACCELEROMETERS, NUM_AXIS>>,
+    /// Whether the pod is known to be stationary, e.g. while the brakes are engaged
+    pub stationary: bool,
+}
 
 pub struct Localizer {
-    displacement: f64,
-    velocity: f64,
-    previous_velocity: f64,
-    acceleration: f64,
-    kalman_filter: KalmanFilter,
+    estimate: LocalisationEstimate,
+    /// When the previous sensor data was read, to work out the time step of the filter
+    previous_timestamp_ms: Option<u64>,
+    /// Estimates the displacement, velocity and accelerometer bias
+    kalman_filter: KalmanFilter<3>,
     keyence_checker: KeyenceAgrees,
-    keyence_val: f64,
-    optical_val: f64,
+    /// Stripes counted when the filter was last updated with the keyence sensors
+    stripe_count: u32,
+    /// Latest acceleration, held until the accelerometers are read again
     accelerometer_val: f64,
+    /// Variance of the acceleration, as configured or measured during calibration
+    accelerometer_variance: f64,
+    /// Latest height above the track, which scales the optical flow into ground speed
+    height: f64,
     accelerometer_preprocessor: AccelerometerPreprocessor,
+    health: LocaliserHealth,
+}
+
+/// Builds the model of the filter for a time step of `delta_t` seconds
+fn transition_model(delta_t: f64, accelerometer_variance: f64) -> TransitionModel<3> {
+    position_velocity_bias(delta_t, accelerometer_variance, ACCELEROMETER_BIAS_DRIFT)
 }
 
 impl Localizer {
     pub fn new() -> Localizer {
-        let initial_state = Vector2::new(0.0, 0.0);
-        let initial_covariance = Matrix2::new(1.0, 0.0, 0.0, 1.0);
-        let transition_matrix = Matrix2::new(1.0, DELTA_T, 0.0, 1.0);
-        let control_matrix = Vector2::new(0.5 * DELTA_T * DELTA_T, DELTA_T);
-        let observation_matrix = Matrix2::new(1.0, 0.0, 0.0, DELTA_T);
-
-        // Assuming frequency of 6400hz for IMU at 120 mu g / sqrt(Hz)
-        // standard deviation = 120 * sqrt(6400) = 9600 mu g = 0.0096 g
-        //                    = 0.0096 * 9.81 = 0.094176 m/s^2
-        // variance =  0.094176^2 = 0.0089 m/s^2
-
-        let process_noise: Matrix2<f64> = Matrix2::new(
-            0.25 * pow(DELTA_T, 4.0),
-            0.5 * pow(DELTA_T, 3.0),
-            0.5 * pow(DELTA_T, 3.0),
-            pow(DELTA_T, 2.0) * 0.0089,
-        );
-
-        //  We assume the stripe counter is accurate
-        //  Optical flow expects standard deviation of 0.01% of the measured value
-        //  Assuming top speed 10m/s,
-        //  standard deviation = 0.01 * 10 = 0.1 m/s
-        //  variance = 0.1^2 = 0.01 m/s^2
-
-        let measurement_noise: Matrix2<f64> = Matrix2::new(0.01, 0.0, 0.0, 0.0);
-
-        let kalman_filter = KalmanFilter::new(
+        let initial_state = Vector3::zeros();
+        let initial_covariance = Matrix3::from_diagonal(&Vector3::new(
+            INITIAL_DISPLACEMENT_VARIANCE,
+            INITIAL_VELOCITY_VARIANCE,
+            INITIAL_ACCELEROMETER_BIAS_VARIANCE,
+        ));
+        // The model is rebuilt for the actual time step before every prediction
+        let (transition_matrix, control_matrix, process_noise) =
+            transition_model(0.0, ACCELEROMETER_VARIANCE);
+
+        let mut kalman_filter = KalmanFilter::new(
             initial_state,
             initial_covariance,
             transition_matrix,
             control_matrix,
-            observation_matrix,
             process_noise,
-            measurement_noise,
         );
+        kalman_filter.set_innovation_gate(INNOVATION_GATE);
 
         Localizer {
-            displacement: 0.0,
-            velocity: 0.0,
-            previous_velocity: 0.0,
-            acceleration: 0.0,
+            estimate: LocalisationEstimate::default(),
+            previous_timestamp_ms: None,
             kalman_filter,
             keyence_checker: KeyenceAgrees::new(),
-            keyence_val: 0.0,
-            optical_val: 0.0,
+            stripe_count: 0,
             accelerometer_val: 0.0,
+            accelerometer_variance: ACCELEROMETER_VARIANCE,
+            height: OPTICAL_FLOW_NOMINAL_HEIGHT,
             accelerometer_preprocessor: AccelerometerPreprocessor::new(),
+            health: LocaliserHealth::default(),
         }
     }
 }
@@ -95,110 +109,607 @@ impl Default for Localizer {
     }
 }
 
+/// Why a class of sensors couldn't be used in an iteration. The localiser carries on without it
+/// in a degraded `LocalisationMode`.
 #[derive(Debug)]
 pub enum PreprocessorError {
-    KeyenceUnacceptable,
     AccelerometerUnnaceptable,
 }
 
 impl Localizer {
-    /// Preprocesses the data from the sensors, checking for errors and outliers
-    pub fn preprocessor(
-        &mut self,
-        optical_data: Vec<f64, 2>,
-        keyence_data: Vec<u32, 2>,
-        accelerometer_data: RawAccelerometerData<NUM_ACCELEROMETERS, NUM_AXIS>,
-    ) -> Result<(), PreprocessorError> {
-        let processed_optical_data =
-            process_optical_data(Vec::from_slice(from_ref(&optical_data)).unwrap());
-        self.optical_val = processed_optical_data as f64;
+    /// Moves the state on by `delta_t` seconds at the given measured acceleration
+    pub fn predict(&mut self, acceleration: f64, delta_t: f64) {
+        self.predict_with_variance(acceleration, self.accelerometer_variance, delta_t);
+    }
 
-        let keyence_status = self
-            .keyence_checker
-            .check_keyence_agrees(keyence_data.clone());
+    fn predict_with_variance(&mut self, acceleration: f64, variance: f64, delta_t: f64) {
+        let (transition_matrix, control_matrix, process_noise) =
+            transition_model(delta_t, variance);
+        self.kalman_filter
+            .set_transition_model(transition_matrix, control_matrix, process_noise);
+        self.kalman_filter.predict(&Vector1::new(acceleration));
+    }
 
-        if keyence_status == SensorChecks::Unacceptable {
-            return Err(PreprocessorError::KeyenceUnacceptable);
-        } else {
-            self.keyence_val = (keyence_data[0] as f64) * STRIPE_WIDTH;
-        }
+    /// Corrects the displacement with the number of stripes counted by the keyence sensors
+    pub fn update_keyence(&mut self, stripe_count: u32) -> Result<(), UpdateError> {
+        self.stripe_count = stripe_count;
+        Self::update_sensor(
+            &mut self.kalman_filter,
+            &mut self.health.keyence,
+            stripe_count as f64 * STRIPE_SPACING,
+            &RowVector3::new(1.0, 0.0, 0.0),
+            KEYENCE_VARIANCE,
+        )
+    }
 
-        let processed_accelerometer_data = self
-            .accelerometer_preprocessor
-            .process_data(accelerometer_data);
-        if processed_accelerometer_data.is_none() {
-            return Err(PreprocessorError::AccelerometerUnnaceptable);
+    /// Corrects the velocity with the speed measured by optical flow
+    pub fn update_optical_velocity(&mut self, velocity: f64) -> Result<(), UpdateError> {
+        Self::update_sensor(
+            &mut self.kalman_filter,
+            &mut self.health.optical_flow,
+            velocity,
+            &RowVector3::new(0.0, 1.0, 0.0),
+            OPTICAL_FLOW_VARIANCE,
+        )
+    }
+
+    /// Corrects the velocity while the pod is known to be stationary
+    pub fn update_zero_velocity(&mut self) -> Result<(), UpdateError> {
+        self.kalman_filter
+            .update(0.0, &RowVector3::new(0.0, 1.0, 0.0), ZERO_VELOCITY_VARIANCE)
+    }
+
+    /// The fastest the pod could plausibly be moving, three standard deviations above the
+    /// estimated speed, in m/s
+    fn max_velocity(&self) -> f64 {
+        let velocity = self.kalman_filter.get_state()[VELOCITY].abs();
+        let velocity_variance = self.kalman_filter.get_covariance()[(VELOCITY, VELOCITY)];
+        velocity + 3.0 * sqrt(velocity_variance.max(0.0))
+    }
+
+    /// Fuses a measurement from a sensor that hasn't been declared faulty, keeping count of the
+    /// measurements rejected as outliers. The measurements of a faulty sensor are only checked,
+    /// so that it is fused again once they agree with the filter for long enough.
+    fn update_sensor(
+        kalman_filter: &mut KalmanFilter<3>,
+        health: &mut SensorHealth,
+        measurement: f64,
+        observation_matrix: &RowVector3<f64>,
+        measurement_noise: f64,
+    ) -> Result<(), UpdateError> {
+        if health.faulty {
+            let checked = kalman_filter.check(measurement, observation_matrix, measurement_noise);
+            health.record(
+                checked != Err(UpdateError::Outlier),
+                MAX_CONSECUTIVE_REJECTIONS,
+                RECOVERY_PASSES,
+            );
+            return Err(UpdateError::SensorFaulty);
         }
+        let result = kalman_filter.update(measurement, observation_matrix, measurement_noise);
+        health.record(
+            result != Err(UpdateError::Outlier),
+            MAX_CONSECUTIVE_REJECTIONS,
+            RECOVERY_PASSES,
+        );
+        result
+    }
 
-        let processed_accelerometer_data = processed_accelerometer_data.unwrap();
-        self.accelerometer_val = 0.0;
-        for i in 0..NUM_ACCELEROMETERS {
-            for _ in 0..NUM_AXIS {
-                self.accelerometer_val += processed_accelerometer_data[i] as f64;
-            }
+    /// Calibrates the accelerometers from the stationary readings collected by `calibrator`,
+    /// removing their bias and using their measured noise in the filter.
+    /// Returns `None` until enough readings are collected.
+    pub fn calibrate(
+        &mut self,
+        calibrator: &AccelerometerCalibrator,
+    ) -> Option<AccelerometerCalibration> {
+        let calibration =
+            calibrator.finish(self.accelerometer_preprocessor.expected_gravity_readings())?;
+        self.accelerometer_preprocessor
+            .apply_calibration(&calibration);
+
+        let (total_variance, num_passed) = calibration
+            .sensors
+            .iter()
+            .enumerate()
+            .filter(|(_, sensor)| sensor.passed)
+            .fold((0.0, 0), |(total, count), (i, sensor)| {
+                let variance = self
+                    .accelerometer_preprocessor
+                    .longitudinal_variance(i, &sensor.variance);
+                (total + variance as f64, count + 1)
+            });
+        if num_passed > 0 {
+            self.accelerometer_variance = total_variance / num_passed as f64;
         }
-        self.accelerometer_val /= (NUM_ACCELEROMETERS * NUM_AXIS) as f64;
+        Some(calibration)
+    }
 
-        Ok(())
+    /// The acceleration fed into the prediction in `mode`, and its variance. The variance is
+    /// inflated when nothing corrects the accelerometers, and without them the pod is assumed to
+    /// keep its velocity.
+    fn acceleration_model(&self, mode: LocalisationMode) -> (f64, f64) {
+        match mode {
+            LocalisationMode::Nominal => (self.accelerometer_val, self.accelerometer_variance),
+            LocalisationMode::DeadReckoning => (
+                self.accelerometer_val,
+                self.accelerometer_variance * DEAD_RECKONING_VARIANCE_SCALE,
+            ),
+            // The filter subtracts the bias from the input, so this leaves no acceleration
+            LocalisationMode::NoAccelerometers | LocalisationMode::Lost => (
+                self.kalman_filter.get_state()[ACCELEROMETER_BIAS],
+                UNMEASURED_ACCELERATION_VARIANCE,
+            ),
+        }
     }
 
-    /// Preprocesses the sensor data by calling the preprocessor and then runs the kalman filter
-    pub fn iteration(
+    /// Averages the accelerometers, rejecting outliers
+    fn process_accelerometers(
         &mut self,
-        optical_data: Vec<f64, 2>,
-        keyence_data: Vec<u32, 2>,
         accelerometer_data: RawAccelerometerData<NUM_ACCELEROMETERS, NUM_AXIS>,
-    ) -> Result<(), PreprocessorError> {
-        self.preprocessor(
-            optical_data.clone(),
-            keyence_data.clone(),
-            accelerometer_data.clone(),
-        )?;
+    ) -> Result<f64, PreprocessorError> {
+        let processed_accelerometer_data = self
+            .accelerometer_preprocessor
+            .process_data(accelerometer_data)
+            .ok_or(PreprocessorError::AccelerometerUnnaceptable)?;
+        let total: f64 = processed_accelerometer_data
+            .iter()
+            .map(|&acceleration| acceleration as f64)
+            .sum();
+        Ok(total / NUM_ACCELEROMETERS as f64)
+    }
 
-        let control_input = Vector1::new(self.accelerometer_val);
+    /// Fuses the sensor data received since the previous iteration, returning the new estimate.
+    /// `timestamp_ms` is when the sensor data was read.
+    ///
+    /// The state is predicted forward with the latest acceleration, then corrected by each
+    /// sensor that has reported. The keyence sensors only carry information when a stripe
+    /// passes, so they only correct the state when the stripe count changes.
+    ///
+    /// Sensors that can't be used are left out, and the estimate carries on in a degraded
+    /// `LocalisationMode` with a larger uncertainty.
+    pub fn iteration(
+        &mut self,
+        timestamp_ms: u64,
+        readings: SensorReadings,
+    ) -> LocalisationEstimate {
+        if let Some(accelerometer_data) = readings.accelerometer_data {
+            match self.process_accelerometers(accelerometer_data) {
+                Ok(acceleration) => {
+                    self.accelerometer_val = acceleration;
+                    self.health.accelerometers_unreliable = false;
+                }
+                Err(_) => self.health.accelerometers_unreliable = true,
+            }
+        }
 
-        self.kalman_filter.predict(&control_input);
+        // Nothing has happened since the first readings, so the first prediction doesn't move
+        let delta_t = self.previous_timestamp_ms.map_or(0.0, |previous| {
+            timestamp_ms.saturating_sub(previous) as f64 / 1000.0
+        });
+        self.previous_timestamp_ms = Some(timestamp_ms);
+
+        let keyence_check = match readings.keyence_data {
+            Some(keyence_data) => {
+                let check = self.keyence_checker.check_keyence_agrees(
+                    keyence_data,
+                    self.max_velocity(),
+                    delta_t,
+                );
+                self.health.suspect_keyence_sensor = check.suspect;
+                self.health.keyence_disagree = check.status == SensorChecks::Unacceptable;
+                Some(check).filter(|_| !self.health.keyence_disagree)
+            }
+            None => None,
+        };
 
-        let measurement = Vector2::new(self.keyence_val * STRIPE_WIDTH, self.optical_val);
+        let (acceleration, acceleration_variance) = self.acceleration_model(self.health.mode());
+        self.predict_with_variance(acceleration, acceleration_variance, delta_t);
 
-        self.kalman_filter.update(&measurement);
+        // Rejected measurements are left out of the estimate, and counted in the health status
+        if let Some(check) = keyence_check {
+            if check.stripe_count != self.stripe_count {
+                let _ = self.update_keyence(check.stripe_count);
+            }
+        }
+        if let Some(height) = readings.height {
+            self.height = height;
+        }
+        if let Some(optical_data) = readings.optical_data {
+            let velocity = process_optical_data(
+                Vec::from_slice(from_ref(&optical_data)).unwrap(),
+                self.height,
+            );
+            let _ = self.update_optical_velocity(velocity);
+        }
+        if readings.stationary {
+            let _ = self.update_zero_velocity();
+        }
 
+        let mode = self.health.mode();
+        let (acceleration, acceleration_variance) = self.acceleration_model(mode);
         let state = self.kalman_filter.get_state();
+        let covariance = self.kalman_filter.get_covariance();
+
+        let mut estimate = LocalisationEstimate {
+            timestamp_ms,
+            displacement: state[DISPLACEMENT],
+            velocity: state[VELOCITY],
+            acceleration: acceleration - state[ACCELEROMETER_BIAS],
+            // Rounding can leave a variance the measurements pin down slightly below zero
+            displacement_std: sqrt(covariance[(DISPLACEMENT, DISPLACEMENT)].max(0.0)),
+            velocity_std: sqrt(covariance[(VELOCITY, VELOCITY)].max(0.0)),
+            acceleration_std: sqrt(
+                acceleration_variance
+                    + covariance[(ACCELEROMETER_BIAS, ACCELEROMETER_BIAS)].max(0.0),
+            ),
+            mode,
+            valid: false,
+        };
+        estimate.valid = [
+            estimate.displacement,
+            estimate.velocity,
+            estimate.acceleration,
+            estimate.displacement_std,
+            estimate.velocity_std,
+            estimate.acceleration_std,
+        ]
+        .iter()
+        .all(|value| value.is_finite());
+        self.estimate = estimate;
+
+        estimate
+    }
 
-        self.displacement = state[0];
-        self.velocity = state[1];
-        self.acceleration = self.accelerometer_val;
-        self.previous_velocity = self.velocity;
+    /// The latest estimate, which is invalid until the first iteration
+    pub fn estimate(&self) -> LocalisationEstimate {
+        self.estimate
+    }
 
-        Ok(())
+    /// Which sensors are having their measurements rejected, and the mode that leaves the
+    /// localiser in
+    pub fn health(&self) -> LocaliserHealth {
+        self.health
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{
+        config::{CALIBRATION_SAMPLES, GRAVITY},
+        preprocessing::optical::OpticalFlowModel,
+    };
+    use hyped_core::config::MeasurementId;
+
+    fn accelerometer_data(acceleration: f32) -> RawAccelerometerData<NUM_ACCELEROMETERS, NUM_AXIS> {
+        let sensor_data = Vec::from_slice(&[acceleration, 0.0, 0.0]).unwrap();
+        RawAccelerometerData::from_slice(&[
+            sensor_data.clone(),
+            sensor_data.clone(),
+            sensor_data.clone(),
+            sensor_data,
+        ])
+        .unwrap()
+    }
+
+    /// Pixel counts read by the optical flow sensor at `velocity` m/s and the nominal height
+    fn optical_data(velocity: f64) -> Vec<f64, 2> {
+        let model = OpticalFlowModel::from_config();
+        let pixels =
+            velocity * model.sample_interval / model.metres_per_pixel(OPTICAL_FLOW_NOMINAL_HEIGHT);
+        Vec::from_slice(&[pixels, 0.0]).unwrap()
+    }
+
+    fn all_sensors(acceleration: f32) -> SensorReadings {
+        SensorReadings {
+            optical_data: Some(optical_data(0.0)),
+            height: None,
+            keyence_data: Some(Vec::from_slice(&[0, 0]).unwrap()),
+            accelerometer_data: Some(accelerometer_data(acceleration)),
+            stationary: false,
+        }
+    }
+
+    #[test]
+    fn test_localizer_with_zeros() {
+        let mut localizer = Localizer::default();
+
+        assert!(!localizer.estimate().valid);
+        let estimate = localizer.iteration(10, all_sensors(0.0));
+
+        assert_eq!(estimate.timestamp_ms, 10);
+        assert_eq!(estimate.displacement, 0.0);
+        assert_eq!(estimate.velocity, 0.0);
+        assert_eq!(estimate.acceleration, 0.0);
+        assert!(estimate.displacement_std > 0.0);
+        assert!(estimate.valid);
+        assert_eq!(localizer.estimate(), estimate);
+
+        let measurements = estimate.measurements();
+        assert_eq!(measurements[1], (MeasurementId::Velocity, 0.0));
+        assert_eq!(measurements[3], (MeasurementId::LocalisationMode, 0.0));
+        let invalid = LocalisationEstimate::default().measurements();
+        assert!(invalid[..3].iter().all(|(_, value)| value.is_nan()));
+    }
+
+    #[test]
+    fn test_localizer_time_step() {
+        let mut localizer = Localizer::default();
+
+        let first = localizer.iteration(1000, all_sensors(1.0));
+        assert_eq!(first.velocity, 0.0);
+
+        // The uncertainty grows with the time since the previous readings
+        let mut short_step = Localizer::default();
+        short_step.iteration(1000, all_sensors(1.0));
+        let short_step = short_step.iteration(1005, all_sensors(1.0));
+        let long_step = localizer.iteration(1500, all_sensors(1.0));
+        assert!(long_step.velocity > short_step.velocity);
+        assert!(long_step.velocity_std > short_step.velocity_std);
+    }
+
+    #[test]
+    fn test_localizer_multi_rate() {
+        let mut localizer = Localizer::default();
+        let accelerometers_only = || SensorReadings {
+            accelerometer_data: Some(accelerometer_data(1.0)),
+            ..Default::default()
+        };
+
+        // Dead reckoning on the accelerometers alone, which grows the uncertainty
+        localizer.iteration(0, accelerometers_only());
+        let mut estimate = localizer.estimate();
+        for timestamp_ms in (100..=1000).step_by(100) {
+            let next = localizer.iteration(timestamp_ms, accelerometers_only());
+            assert!(next.displacement_std > estimate.displacement_std);
+            estimate = next;
+        }
+        assert!((estimate.velocity - 1.0).abs() < 1e-9);
+        assert!((estimate.displacement - 0.5).abs() < 1e-9);
+
+        // The latest acceleration is held when only the keyence sensors report, and a stripe
+        // passing pins down the displacement
+        let keyence = localizer.iteration(
+            1100,
+            SensorReadings {
+                keyence_data: Some(Vec::from_slice(&[1, 1]).unwrap()),
+                ..Default::default()
+            },
+        );
+        assert!((keyence.acceleration - 1.0).abs() < 0.01);
+        assert!(keyence.displacement > estimate.displacement);
+        assert!(keyence.displacement_std < estimate.displacement_std);
+        assert!(keyence.displacement_std < sqrt(KEYENCE_VARIANCE));
+
+        // An unchanged stripe count carries no information
+        let same_stripe = localizer.iteration(
+            1100,
+            SensorReadings {
+                keyence_data: Some(Vec::from_slice(&[1, 1]).unwrap()),
+                ..Default::default()
+            },
+        );
+        assert_eq!(same_stripe.displacement, keyence.displacement);
+        assert_eq!(same_stripe.displacement_std, keyence.displacement_std);
+
+        // Optical flow corrects the velocity
+        let optical = localizer.iteration(
+            1100,
+            SensorReadings {
+                optical_data: Some(optical_data(2.0)),
+                ..Default::default()
+            },
+        );
+        assert!(optical.velocity > keyence.velocity);
+        assert!(optical.velocity_std < keyence.velocity_std);
+
+        // The pod is clearly moving, so a claim that it is stationary is rejected
+        let moving = localizer.iteration(
+            1100,
+            SensorReadings {
+                stationary: true,
+                ..Default::default()
+            },
+        );
+        assert_eq!(moving.velocity, optical.velocity);
+
+        // While stopped, the velocity is known to be zero
+        let mut stopped = Localizer::default();
+        let stopped = stopped.iteration(
+            0,
+            SensorReadings {
+                accelerometer_data: Some(accelerometer_data(0.0)),
+                stationary: true,
+                ..Default::default()
+            },
+        );
+        assert_eq!(stopped.velocity, 0.0);
+        assert!(stopped.velocity_std < sqrt(ZERO_VELOCITY_VARIANCE) * 2.0);
+    }
 
     #[test]
-    fn test_localizer_with_zeros() -> Result<(), PreprocessorError> {
+    fn test_localizer_sensor_faults() {
         let mut localizer = Localizer::default();
+        let optical = |velocity: f64| SensorReadings {
+            optical_data: Some(optical_data(velocity)),
+            accelerometer_data: Some(accelerometer_data(0.0)),
+            ..Default::default()
+        };
+
+        for timestamp_ms in (0..=500).step_by(100) {
+            localizer.iteration(timestamp_ms, optical(0.0));
+        }
+        assert!(localizer.health().is_healthy());
+
+        // A glitch is rejected without moving the estimate
+        let before = localizer.estimate();
+        let glitch = localizer.iteration(600, optical(20.0));
+        assert_eq!(glitch.velocity, before.velocity);
+        assert_eq!(localizer.health().optical_flow.consecutive_rejections, 1);
+        localizer.iteration(700, optical(0.0));
+        assert_eq!(localizer.health().optical_flow.consecutive_rejections, 0);
+        assert_eq!(localizer.health().optical_flow.total_rejections, 1);
+
+        // A sensor that keeps disagreeing is declared faulty and no longer fused
+        for i in 0..MAX_CONSECUTIVE_REJECTIONS as u64 {
+            localizer.iteration(800 + i * 100, optical(20.0));
+        }
+        let health = localizer.health();
+        assert!(health.optical_flow.faulty);
+        assert!(!health.keyence.faulty);
+        assert!(!health.is_healthy());
+        assert_eq!(
+            localizer.update_optical_velocity(0.0),
+            Err(UpdateError::SensorFaulty)
+        );
+
+        // Stripes can't have been counted this far from where the pod is thought to be
+        assert_eq!(localizer.update_keyence(5), Err(UpdateError::Outlier));
+        assert_eq!(localizer.health().keyence.total_rejections, 1);
 
-        let optical_data: Vec<f64, 2> = Vec::from_slice(&[0.0, 0.0]).unwrap();
-        let raw_keyence_data: Vec<u32, 2> = Vec::from_slice(&[0, 0]).unwrap();
-        let raw_accelerometer_data: RawAccelerometerData<NUM_ACCELEROMETERS, NUM_AXIS> =
-            RawAccelerometerData::from_slice(&[
-                Vec::from_slice(&[0.0, 0.0, 0.0]).unwrap(),
-                Vec::from_slice(&[0.0, 0.0, 0.0]).unwrap(),
-                Vec::from_slice(&[0.0, 0.0, 0.0]).unwrap(),
-                Vec::from_slice(&[0.0, 0.0, 0.0]).unwrap(),
-            ])
-            .unwrap();
+        // Once the faulty sensor agrees with the filter again for long enough, it is fused again
+        localizer.iteration(1400, optical(20.0));
+        for i in 0..RECOVERY_PASSES as u64 - 1 {
+            localizer.iteration(1500 + i * 100, optical(0.0));
+        }
+        assert!(localizer.health().optical_flow.faulty);
+        localizer.iteration(1500 + RECOVERY_PASSES as u64 * 100, optical(0.0));
+        assert!(!localizer.health().optical_flow.faulty);
+        assert!(localizer.health().is_healthy());
+        assert_eq!(localizer.update_optical_velocity(0.0), Ok(()));
+    }
+
+    #[test]
+    fn test_localizer_optical_flow_height() {
+        let optical = |height: Option<f64>| SensorReadings {
+            optical_data: Some(optical_data(1.0)),
+            height,
+            accelerometer_data: Some(accelerometer_data(0.0)),
+            ..Default::default()
+        };
+
+        // The same pixel counts from twice as high cover twice as much track
+        let mut nominal = Localizer::default();
+        let mut raised = Localizer::default();
+        nominal.iteration(0, optical(None));
+        raised.iteration(0, optical(Some(OPTICAL_FLOW_NOMINAL_HEIGHT * 2.0)));
+        let nominal = nominal.iteration(100, optical(None));
+        let raised = raised.iteration(100, optical(None));
+        assert!(nominal.velocity > 0.0);
+        assert!(raised.velocity > nominal.velocity);
+    }
 
-        localizer.iteration(optical_data, raw_keyence_data, raw_accelerometer_data)?;
+    #[test]
+    fn test_localizer_keyence_voting() {
+        let mut localizer = Localizer::default();
+        let keyence = |counts: &[u32]| SensorReadings {
+            keyence_data: Some(Vec::from_slice(counts).unwrap()),
+            accelerometer_data: Some(accelerometer_data(0.0)),
+            ..Default::default()
+        };
+
+        localizer.iteration(0, keyence(&[0, 0]));
+        let estimate = localizer.iteration(100, keyence(&[0, 1]));
+        assert_eq!(localizer.health().suspect_keyence_sensor, None);
+
+        // A stationary pod can't have passed ten stripes, so the jump is ignored
+        let jump = localizer.iteration(200, keyence(&[0, 10]));
+        assert_eq!(localizer.health().suspect_keyence_sensor, Some(1));
+        assert!((jump.displacement - estimate.displacement).abs() < STRIPE_SPACING);
+    }
 
-        assert_eq!(localizer.displacement, 0.0);
-        assert_eq!(localizer.velocity, 0.0);
-        assert_eq!(localizer.acceleration, 0.0);
+    #[test]
+    fn test_localizer_calibration() {
+        let mut localizer = Localizer::default();
+        let mut calibrator = AccelerometerCalibrator::new();
+        assert!(localizer.calibrate(&calibrator).is_none());
+
+        // Every accelerometer reads 0.3 m/s^2 too high along the track
+        for sample in 0..CALIBRATION_SAMPLES {
+            let noise = if sample % 2 == 0 { 0.01 } else { -0.01 };
+            let sensor_data = Vec::from_slice(&[0.3 + noise, 0.0, GRAVITY]).unwrap();
+            calibrator.add_sample(
+                &RawAccelerometerData::from_slice(&[
+                    sensor_data.clone(),
+                    sensor_data.clone(),
+                    sensor_data.clone(),
+                    sensor_data,
+                ])
+                .unwrap(),
+            );
+        }
+        let calibration = localizer.calibrate(&calibrator).unwrap();
+        assert!(calibration.all_passed());
+
+        let estimate = localizer.iteration(
+            0,
+            SensorReadings {
+                accelerometer_data: Some(accelerometer_data(1.3)),
+                ..Default::default()
+            },
+        );
+        assert!((estimate.acceleration - 1.0).abs() < 1e-4);
+        // The measured noise is far below the configured noise
+        let uncalibrated = Localizer::default().iteration(0, all_sensors(1.0));
+        assert!(estimate.acceleration_std < uncalibrated.acceleration_std);
+    }
 
-        Ok(())
+    #[test]
+    fn test_localizer_degraded_modes() {
+        let readings = |velocity: f64, keyence: &[u32]| SensorReadings {
+            optical_data: Some(optical_data(velocity)),
+            keyence_data: Some(Vec::from_slice(keyence).unwrap()),
+            accelerometer_data: Some(accelerometer_data(0.0)),
+            ..Default::default()
+        };
+
+        // Losing the keyence sensors and optical flow leaves dead reckoning, whose uncertainty
+        // grows faster than the accelerometers' alone
+        let mut nominal = Localizer::default();
+        let mut dead_reckoning = Localizer::default();
+        let accelerometers_only = SensorReadings {
+            accelerometer_data: Some(accelerometer_data(0.0)),
+            ..Default::default()
+        };
+        nominal.iteration(0, accelerometers_only.clone());
+        dead_reckoning.iteration(0, readings(0.0, &[0, 2]));
+        for timestamp_ms in (100..=600).step_by(100) {
+            nominal.iteration(timestamp_ms, accelerometers_only.clone());
+            dead_reckoning.iteration(timestamp_ms, readings(20.0, &[0, 2]));
+        }
+        let nominal = nominal.estimate();
+        let estimate = dead_reckoning.estimate();
+        assert_eq!(nominal.mode, LocalisationMode::Nominal);
+        assert_eq!(estimate.mode, LocalisationMode::DeadReckoning);
+        assert!(estimate.valid);
+        assert!(estimate.acceleration_std > nominal.acceleration_std);
+
+        // Two accelerometers fail calibration, leaving too few to trust
+        let mut localizer = Localizer::default();
+        let mut calibrator = AccelerometerCalibrator::new();
+        for _ in 0..CALIBRATION_SAMPLES {
+            let good = Vec::from_slice(&[0.0, 0.0, GRAVITY]).unwrap();
+            let bad = Vec::from_slice(&[5.0, 0.0, GRAVITY]).unwrap();
+            calibrator.add_sample(
+                &RawAccelerometerData::from_slice(&[good.clone(), good, bad.clone(), bad]).unwrap(),
+            );
+        }
+        assert!(!localizer.calibrate(&calibrator).unwrap().all_passed());
+
+        // Optical flow and the keyence sensors carry on, assuming the velocity holds
+        let estimate = localizer.iteration(0, readings(0.0, &[0, 0]));
+        assert_eq!(estimate.mode, LocalisationMode::NoAccelerometers);
+        assert!(estimate.valid);
+        assert_eq!(estimate.acceleration, 0.0);
+        assert!(estimate.acceleration_std > nominal.acceleration_std);
+        assert!(!localizer.health().is_healthy());
+
+        // Without them too, the pod is lost, but still has its best guess
+        for timestamp_ms in (100..=600).step_by(100) {
+            localizer.iteration(timestamp_ms, readings(20.0, &[0, 2]));
+        }
+        let lost = localizer.estimate();
+        assert_eq!(lost.mode, LocalisationMode::Lost);
+        assert!(lost.valid);
     }
 }
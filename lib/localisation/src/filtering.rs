@@ -1 +1,2 @@
 pub mod kalman_filter;
+pub mod models;
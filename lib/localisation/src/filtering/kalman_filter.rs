@@ -1,50 +1,76 @@
-use nalgebra::{Matrix2, Vector1, Vector2, SVD};
+use nalgebra::{RowSVector, SMatrix, SVector, Vector1};
+
+/// Why a measurement was not fused into the state estimate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpdateError {
+    /// The measurement is too far from the prediction to be believed, given their uncertainties
+    Outlier,
+    /// The uncertainty of the measurement can't be worked out, so it can't be weighed
+    Degenerate,
+    /// The sensor has been declared faulty, so its measurements are no longer fused
+    SensorFaulty,
+}
 
 /// Kalman filter implementation for cart for sensor fusion.
 /// Recursively estimates the state from a series of nois measurements.
 ///
-/// Uses keyence, optical flow and accelerometer data.
+/// Uses keyence, optical flow and accelerometer data. The state has `N` elements, described by
+/// one of the models in `filtering::models`.
 ///
 /// Control input: Accelerometer data
-/// Measurement: Optical flow data, keyence stripe counter
-pub struct KalmanFilter {
-    /// Current state estimate (2x1)
-    state: Vector2<f64>,
-    /// Current error covariance (2x2)
-    covariance: Matrix2<f64>,
-    /// State transition matrix (2x2)
-    transition_matrix: Matrix2<f64>,
-    /// Control matrix (2x1)
-    control_matrix: Vector2<f64>,
-    /// Observation matrix (1x2)
-    observation_matrix: Matrix2<f64>,
-    /// Process noise covariance (2x2)
-    process_noise: Matrix2<f64>,
-    /// Measurement noise covariance (1x1)
-    measurement_noise: Matrix2<f64>,
+/// Measurement: Optical flow data, keyence stripe counter, each fused as it arrives with its own
+/// observation model
+pub struct KalmanFilter<const N: usize> {
+    /// Current state estimate (Nx1)
+    state: SVector<f64, N>,
+    /// Current error covariance (NxN)
+    covariance: SMatrix<f64, N, N>,
+    /// State transition matrix (NxN)
+    transition_matrix: SMatrix<f64, N, N>,
+    /// Control matrix (Nx1)
+    control_matrix: SVector<f64, N>,
+    /// Process noise covariance (NxN)
+    process_noise: SMatrix<f64, N, N>,
+    /// Largest squared Mahalanobis distance of a measurement from the prediction that is fused
+    innovation_gate: f64,
 }
 
-impl KalmanFilter {
+impl<const N: usize> KalmanFilter<N> {
     pub fn new(
-        initial_state: Vector2<f64>,
-        initial_covariance: Matrix2<f64>,
-        transition_matrix: Matrix2<f64>,
-        control_matrix: Vector2<f64>,
-        observation_matrix: Matrix2<f64>,
-        process_noise: Matrix2<f64>,
-        measurement_noise: Matrix2<f64>,
+        initial_state: SVector<f64, N>,
+        initial_covariance: SMatrix<f64, N, N>,
+        transition_matrix: SMatrix<f64, N, N>,
+        control_matrix: SVector<f64, N>,
+        process_noise: SMatrix<f64, N, N>,
     ) -> Self {
         KalmanFilter {
             state: initial_state,
             covariance: initial_covariance,
             transition_matrix,
-            observation_matrix,
             control_matrix,
             process_noise,
-            measurement_noise,
+            innovation_gate: f64::INFINITY,
         }
     }
 
+    /// Rejects measurements whose squared Mahalanobis distance from the prediction is above
+    /// `innovation_gate`, e.g. a chi-square quantile with one degree of freedom
+    pub fn set_innovation_gate(&mut self, innovation_gate: f64) {
+        self.innovation_gate = innovation_gate;
+    }
+
+    /// Replaces the model of how the state changes between steps, e.g. when the time step changes
+    pub fn set_transition_model(
+        &mut self,
+        transition_matrix: SMatrix<f64, N, N>,
+        control_matrix: SVector<f64, N>,
+        process_noise: SMatrix<f64, N, N>,
+    ) {
+        self.transition_matrix = transition_matrix;
+        self.control_matrix = control_matrix;
+        self.process_noise = process_noise;
+    }
+
     /// Predict step
     /// Predicts the next state of the system, using the accelerometer data as control input.
     /// Predicts the next state covariance.
@@ -58,41 +84,85 @@ impl KalmanFilter {
                 + self.process_noise;
     }
 
-    /// Update step: Corrects the state estimate based on the measurement.
-    /// Uses the stripe counter (displacement) and optical flow data (velocity).
-    pub fn update(&mut self, measurement: &Vector2<f64>) {
-        // y_k = z_k - H * x_k
-        let innovation = measurement - self.observation_matrix * self.state;
-
-        // S = H * P_k * H^T + R
-        let innovation_covariance =
-            self.observation_matrix * self.covariance * self.observation_matrix.transpose()
-                + self.measurement_noise;
-
-        // Calculate the pseudo-inverse using Singular Value Decomposition
-        // If the matrix is invertible, the pseudo-inverse is the same as the inverse
-        let svd = SVD::new(innovation_covariance, true, true);
-        let innovation_covariance_inv = svd.pseudo_inverse(1e-10).unwrap();
+    /// Update step: Corrects the state estimate based on a single measurement, e.g. the
+    /// displacement from the stripe counter or the velocity from optical flow.
+    /// `observation_matrix` maps the state to the measurement, and `measurement_noise` is the
+    /// variance of the measurement.
+    ///
+    /// The state is left alone if the measurement is rejected by the innovation gate.
+    pub fn update(
+        &mut self,
+        measurement: f64,
+        observation_matrix: &RowSVector<f64, N>,
+        measurement_noise: f64,
+    ) -> Result<(), UpdateError> {
+        let (innovation, innovation_covariance) =
+            self.innovation(measurement, observation_matrix, measurement_noise)?;
 
         // K = P_k * H^T * S^-1
-        let kalman_gain =
-            self.covariance * self.observation_matrix.transpose() * innovation_covariance_inv;
+        let kalman_gain = self.covariance * observation_matrix.transpose() / innovation_covariance;
 
         self.state += kalman_gain * innovation;
 
-        let identity = Matrix2::identity();
-        self.covariance = (identity - kalman_gain * self.observation_matrix) * self.covariance;
+        // Joseph form, P_k = (I - K * H) * P_k * (I - K * H)^T + K * R * K^T, which keeps the
+        // covariance symmetric and positive definite despite rounding
+        let correction = SMatrix::<f64, N, N>::identity() - kalman_gain * observation_matrix;
+        self.covariance = correction * self.covariance * correction.transpose()
+            + kalman_gain * kalman_gain.transpose() * measurement_noise;
+
+        Ok(())
+    }
+
+    /// Checks a measurement against the innovation gate, leaving the state alone
+    pub fn check(
+        &self,
+        measurement: f64,
+        observation_matrix: &RowSVector<f64, N>,
+        measurement_noise: f64,
+    ) -> Result<(), UpdateError> {
+        self.innovation(measurement, observation_matrix, measurement_noise)
+            .map(|_| ())
+    }
+
+    /// Returns the innovation of a measurement and its variance, if the measurement passes the
+    /// innovation gate
+    fn innovation(
+        &self,
+        measurement: f64,
+        observation_matrix: &RowSVector<f64, N>,
+        measurement_noise: f64,
+    ) -> Result<(f64, f64), UpdateError> {
+        // y_k = z_k - H * x_k
+        let innovation = measurement - (observation_matrix * self.state)[0];
+
+        // S = H * P_k * H^T + R
+        let innovation_covariance =
+            (observation_matrix * self.covariance * observation_matrix.transpose())[0]
+                + measurement_noise;
+        if innovation_covariance <= 0.0 || !innovation_covariance.is_finite() {
+            return Err(UpdateError::Degenerate);
+        }
+        let squared_distance = innovation * innovation / innovation_covariance;
+        if squared_distance.is_nan() || squared_distance > self.innovation_gate {
+            return Err(UpdateError::Outlier);
+        }
+        Ok((innovation, innovation_covariance))
     }
 
-    pub fn get_state(&self) -> Vector2<f64> {
+    pub fn get_state(&self) -> SVector<f64, N> {
         self.state
     }
+
+    /// Error covariance of the current state estimate
+    pub fn get_covariance(&self) -> SMatrix<f64, N, N> {
+        self.covariance
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use nalgebra::{Matrix2, Vector1, Vector2};
+    use nalgebra::{Matrix2, RowVector2, Vector1, Vector2};
 
     #[test]
     fn test_kalman_filter() {
@@ -117,17 +187,17 @@ mod tests {
         let transition_matrix = Matrix2::new(1.0, 1.0, 0.0, 1.0);
         let control_matrix = Vector2::new(0.5, 1.0);
         let process_noise = Matrix2::new(0.25 * 3.0, 0.5 * 3.0, 0.5 * 3.0, 1.0 * 3.0);
-        let observation_matrix = Matrix2::new(1.0, 0.0, 0.0, 1.0);
-        let measurement_noise = Matrix2::new(1.0, 0.0, 0.0, 0.0);
+        let dist_observation = RowVector2::new(1.0, 0.0);
+        let dist_noise = 1.0;
+        let vel_observation = RowVector2::new(0.0, 1.0);
+        let vel_noise = 0.0;
 
         let mut kalman_filter = KalmanFilter::new(
             initial_state,
             initial_covariance,
             transition_matrix,
             control_matrix,
-            observation_matrix,
             process_noise,
-            measurement_noise,
         );
 
         let acc_measurements = [
@@ -144,15 +214,54 @@ mod tests {
         ];
 
         for i in 0..20 {
-            let measurement = Vector2::new(dist_measurements[i], vel_measurements[i]);
             let control_input = Vector1::new(acc_measurements[i]);
 
             kalman_filter.predict(&control_input);
-            kalman_filter.update(&measurement);
+            kalman_filter
+                .update(dist_measurements[i], &dist_observation, dist_noise)
+                .unwrap();
+            kalman_filter
+                .update(vel_measurements[i], &vel_observation, vel_noise)
+                .unwrap();
         }
 
         let final_state = kalman_filter.get_state();
         assert!(final_state[0] - 4000.0 < 1e-10);
         assert!(final_state[1] - 400.0 < 1e-10);
     }
+
+    #[test]
+    fn test_innovation_gate() {
+        let mut kalman_filter = KalmanFilter::new(
+            Vector2::new(0.0, 0.0),
+            Matrix2::identity(),
+            Matrix2::identity(),
+            Vector2::zeros(),
+            Matrix2::zeros(),
+        );
+        kalman_filter.set_innovation_gate(10.83);
+        let observation = RowVector2::new(0.0, 1.0);
+
+        // 3 standard deviations away is believable, 10 is not
+        assert_eq!(kalman_filter.update(3.0, &observation, 0.0), Ok(()));
+        let state = kalman_filter.get_state();
+        let covariance = kalman_filter.get_covariance();
+        assert_eq!(
+            kalman_filter.update(state[1] + 10.0, &observation, 1e-4),
+            Err(UpdateError::Outlier)
+        );
+        assert_eq!(
+            kalman_filter.update(f64::NAN, &observation, 1e-4),
+            Err(UpdateError::Outlier)
+        );
+        assert_eq!(kalman_filter.get_state(), state);
+        assert_eq!(kalman_filter.get_covariance(), covariance);
+
+        // Nothing can be learnt from a measurement of a state that is already known exactly
+        assert_eq!(
+            kalman_filter.update(3.0, &observation, 0.0),
+            Err(UpdateError::Degenerate)
+        );
+        assert_eq!(covariance, covariance.transpose());
+    }
 }
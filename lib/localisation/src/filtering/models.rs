@@ -0,0 +1,143 @@
+use nalgebra::{Matrix2, Matrix3, RowVector3, SMatrix, SVector, Vector2, Vector3};
+
+/// Transition matrix, control matrix and process noise of a model over one time step
+pub type TransitionModel<const N: usize> =
+    (SMatrix<f64, N, N>, SVector<f64, N>, SMatrix<f64, N, N>);
+
+/// Index of the displacement in the state of each model
+pub const DISPLACEMENT: usize = 0;
+/// Index of the velocity in the state of each model
+pub const VELOCITY: usize = 1;
+/// Index of the accelerometer bias in the state of `position_velocity_bias`
+pub const ACCELEROMETER_BIAS: usize = 2;
+
+/// Displacement and velocity, driven by the measured acceleration over `delta_t` seconds.
+/// Any bias in the acceleration integrates straight into the velocity.
+pub fn position_velocity(delta_t: f64, accelerometer_variance: f64) -> TransitionModel<2> {
+    let transition_matrix = Matrix2::new(1.0, delta_t, 0.0, 1.0);
+    let control_matrix = Vector2::new(0.5 * delta_t * delta_t, delta_t);
+    // Noise in the acceleration is carried into the state through the control matrix
+    let process_noise = control_matrix * control_matrix.transpose() * accelerometer_variance;
+    (transition_matrix, control_matrix, process_noise)
+}
+
+/// Displacement, velocity and accelerometer bias, driven by the measured acceleration over
+/// `delta_t` seconds. The bias is taken off the measured acceleration, and is estimated from how
+/// the displacement and velocity measurements drift away from the acceleration.
+///
+/// `bias_drift` is how quickly the bias wanders, in (m/s^2)^2/s.
+pub fn position_velocity_bias(
+    delta_t: f64,
+    accelerometer_variance: f64,
+    bias_drift: f64,
+) -> TransitionModel<3> {
+    let half_delta_t_squared = 0.5 * delta_t * delta_t;
+    let transition_matrix = Matrix3::from_rows(&[
+        RowVector3::new(1.0, delta_t, -half_delta_t_squared),
+        RowVector3::new(0.0, 1.0, -delta_t),
+        RowVector3::new(0.0, 0.0, 1.0),
+    ]);
+    let control_matrix = Vector3::new(half_delta_t_squared, delta_t, 0.0);
+    let mut process_noise = control_matrix * control_matrix.transpose() * accelerometer_variance;
+    process_noise[(ACCELEROMETER_BIAS, ACCELEROMETER_BIAS)] = bias_drift * delta_t;
+    (transition_matrix, control_matrix, process_noise)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filtering::kalman_filter::{KalmanFilter, UpdateError};
+    use nalgebra::{RowVector2, Vector1};
+
+    /// Deterministic noise, roughly uniform in [-amplitude, amplitude]
+    struct Noise(u32);
+
+    impl Noise {
+        fn next(&mut self, amplitude: f64) -> f64 {
+            self.0 = self.0.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (self.0 as f64 / u32::MAX as f64 * 2.0 - 1.0) * amplitude
+        }
+    }
+
+    #[test]
+    fn test_bias_convergence() -> Result<(), UpdateError> {
+        // Synthetic run at 100 Hz: the pod accelerates at 0.5 m/s^2 for 10 s, then cruises for
+        // 10 s, while the accelerometers read 0.2 m/s^2 too high. Optical flow reports the
+        // velocity at 10 Hz and a stripe passes every metre.
+        let delta_t = 0.01;
+        let true_bias = 0.2;
+        let accelerometer_variance = 0.0089;
+        let optical_flow_variance = 0.01;
+        let keyence_variance = 1.0 / 12.0;
+        let mut noise = Noise(42);
+
+        let (transition_matrix, control_matrix, process_noise) =
+            position_velocity_bias(delta_t, accelerometer_variance, 1e-6);
+        let mut with_bias = KalmanFilter::new(
+            Vector3::zeros(),
+            Matrix3::from_diagonal(&Vector3::new(1.0, 1.0, 0.1)),
+            transition_matrix,
+            control_matrix,
+            process_noise,
+        );
+        let (transition_matrix, control_matrix, process_noise) =
+            position_velocity(delta_t, accelerometer_variance);
+        let mut without_bias = KalmanFilter::new(
+            Vector2::zeros(),
+            Matrix2::identity(),
+            transition_matrix,
+            control_matrix,
+            process_noise,
+        );
+
+        let mut displacement: f64 = 0.0;
+        let mut velocity: f64 = 0.0;
+        let mut stripes = 0;
+        for step in 1..=2000 {
+            let acceleration = if step <= 1000 { 0.5 } else { 0.0 };
+            displacement += velocity * delta_t + 0.5 * acceleration * delta_t * delta_t;
+            velocity += acceleration * delta_t;
+
+            let measured_acceleration = Vector1::new(acceleration + true_bias + noise.next(0.15));
+            with_bias.predict(&measured_acceleration);
+            without_bias.predict(&measured_acceleration);
+
+            if step % 10 == 0 {
+                let measured_velocity = velocity + noise.next(0.15);
+                with_bias.update(
+                    measured_velocity,
+                    &RowVector3::new(0.0, 1.0, 0.0),
+                    optical_flow_variance,
+                )?;
+                without_bias.update(
+                    measured_velocity,
+                    &RowVector2::new(0.0, 1.0),
+                    optical_flow_variance,
+                )?;
+            }
+            if displacement as u32 > stripes {
+                stripes = displacement as u32;
+                with_bias.update(
+                    stripes as f64,
+                    &RowVector3::new(1.0, 0.0, 0.0),
+                    keyence_variance,
+                )?;
+                without_bias.update(
+                    stripes as f64,
+                    &RowVector2::new(1.0, 0.0),
+                    keyence_variance,
+                )?;
+            }
+        }
+
+        let state = with_bias.get_state();
+        assert!((state[ACCELEROMETER_BIAS] - true_bias).abs() < 0.05);
+        assert!(with_bias.get_covariance()[(ACCELEROMETER_BIAS, ACCELEROMETER_BIAS)] < 1e-3);
+        // Without the bias in the state, the velocity is dragged away from the truth
+        let velocity_error = (state[VELOCITY] - velocity).abs();
+        let velocity_error_without_bias = (without_bias.get_state()[VELOCITY] - velocity).abs();
+        assert!(velocity_error < 0.1);
+        assert!(velocity_error < velocity_error_without_bias);
+        Ok(())
+    }
+}
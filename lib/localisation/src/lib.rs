@@ -1,5 +1,6 @@
 #![cfg_attr(not(test), no_std)]
 
+pub mod calibration;
 pub mod config;
 pub mod control;
 pub mod filtering;
@@ -1,9 +1,13 @@
 use crate::{
-    config::{NUM_ACCELEROMETERS, NUM_ALLOWED_ACCELEROMETER_OUTLIERS, NUM_AXIS},
+    calibration::AccelerometerCalibration,
+    config::{
+        ACCELEROMETER_MOUNTING, GRAVITY, NUM_ACCELEROMETERS, NUM_ALLOWED_ACCELEROMETER_OUTLIERS,
+        NUM_AXIS,
+    },
     types::{AccelerometerData, RawAccelerometerData, SensorChecks},
 };
 use heapless::Vec;
-use nalgebra::{ComplexField, SimdComplexField};
+use nalgebra::{Rotation3, SimdComplexField, Vector3};
 
 /// Stores the quartiles of the data and the bounds for outliers
 /// which are calculated from the quartiles
@@ -35,8 +39,11 @@ impl Quartiles {
 }
 
 /// Responsible for processing accelerometer data and removing outliers
-#[derive(Default)]
 pub struct AccelerometerPreprocessor {
+    /// Turns each accelerometer's axes into the pod's
+    mounting_rotations: [Rotation3<f32>; NUM_ACCELEROMETERS],
+    /// What each accelerometer reads, on its own axes, while the pod is stationary and level
+    gravity_references: [Vector3<f32>; NUM_ACCELEROMETERS],
     /// number of true values in reliable_accelerometers
     num_reliable_accelerometers: i32,
     /// true if accelerometer at index is reliable
@@ -45,17 +52,91 @@ pub struct AccelerometerPreprocessor {
     num_outliers_per_accelerometer: [i32; NUM_ACCELEROMETERS],
 }
 
+impl Default for AccelerometerPreprocessor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl AccelerometerPreprocessor {
-    /// Creates a new AccelerometerPreprocessor
+    /// Creates a new AccelerometerPreprocessor, with the accelerometers mounted as configured
     /// By default, all accelerometers are deemed as reliable
     pub fn new() -> Self {
-        Self {
+        Self::with_mounting_rotations(ACCELEROMETER_MOUNTING.map(|[roll, pitch, yaw]| {
+            Rotation3::from_euler_angles(roll.to_radians(), pitch.to_radians(), yaw.to_radians())
+        }))
+    }
+
+    /// Creates a new AccelerometerPreprocessor for accelerometers mounted with the given
+    /// rotations from their axes to the pod's
+    /// Until the accelerometers are calibrated, they are assumed to read exactly `GRAVITY` upwards
+    /// while the pod is stationary
+    pub fn with_mounting_rotations(
+        mounting_rotations: [Rotation3<f32>; NUM_ACCELEROMETERS],
+    ) -> Self {
+        let mut preprocessor = Self {
+            mounting_rotations,
+            gravity_references: [Vector3::zeros(); NUM_ACCELEROMETERS],
             num_reliable_accelerometers: NUM_ACCELEROMETERS as i32,
             reliable_accelerometers: [true; NUM_ACCELEROMETERS],
             num_outliers_per_accelerometer: [0; NUM_ACCELEROMETERS],
+        };
+        preprocessor.gravity_references = preprocessor.expected_gravity_readings();
+        preprocessor
+    }
+
+    /// What each accelerometer would read, on its own axes, while the pod is stationary and level
+    /// if it had no bias
+    pub fn expected_gravity_readings(&self) -> [Vector3<f32>; NUM_ACCELEROMETERS] {
+        let gravity = Vector3::new(0.0, 0.0, GRAVITY);
+        self.mounting_rotations
+            .map(|rotation| rotation.inverse() * gravity)
+    }
+
+    /// Uses the readings of the accelerometers that passed calibration as their gravity
+    /// references, removing their bias, and stops trusting those that failed
+    pub fn apply_calibration(&mut self, calibration: &AccelerometerCalibration) {
+        for (i, sensor) in calibration.sensors.iter().enumerate() {
+            if sensor.passed {
+                self.gravity_references[i] = sensor.gravity_reference;
+            } else if self.reliable_accelerometers[i] {
+                self.reliable_accelerometers[i] = false;
+                self.num_reliable_accelerometers -= 1;
+            }
         }
     }
 
+    /// Variance of the acceleration along the track measured by an accelerometer, given the
+    /// variance of each of its axes
+    pub fn longitudinal_variance(&self, accelerometer: usize, variance: &Vector3<f32>) -> f32 {
+        let along_track = self.mounting_rotations[accelerometer].matrix().row(0);
+        along_track
+            .component_mul(&along_track)
+            .dot(&variance.transpose())
+    }
+
+    /// Replaces what each accelerometer is assumed to read, on its own axes, while the pod is
+    /// stationary and level, e.g. with the readings averaged during calibration
+    pub fn set_gravity_references(
+        &mut self,
+        gravity_references: [Vector3<f32>; NUM_ACCELEROMETERS],
+    ) {
+        self.gravity_references = gravity_references;
+    }
+
+    /// Acceleration of the pod along the track measured by an accelerometer, which is negative
+    /// while braking
+    pub fn longitudinal_acceleration(
+        &self,
+        accelerometer: usize,
+        axes: &Vec<f32, NUM_AXIS>,
+    ) -> f32 {
+        let reading = Vector3::new(axes[0], axes[1], axes[2]);
+        let acceleration = self.mounting_rotations[accelerometer]
+            * (reading - self.gravity_references[accelerometer]);
+        acceleration.x
+    }
+
     /// Removes points in data that are deemed as outliers
     /// This is based on bounds calculated from the quartiles of the data
     /// Any points from unreliable accelerometers or those that are out of bounds
@@ -111,8 +192,8 @@ impl AccelerometerPreprocessor {
     }
 
     /// Main function to process accelerometer data
-    /// This function calculates the magnitude of the acceleration (across all axes) for each
-    /// accelerometer
+    /// This function calculates the signed acceleration along the track for each accelerometer,
+    /// with gravity removed
     /// It then removes outliers from the data and checks if the data is reliable
     /// Unreliable data is deemed unacceptable and the function returns None
     pub fn process_data(
@@ -121,12 +202,8 @@ impl AccelerometerPreprocessor {
     ) -> Option<AccelerometerData<NUM_ACCELEROMETERS>> {
         let accelerometer_data: AccelerometerData<NUM_ACCELEROMETERS> = data
             .iter()
-            .map(|axis| {
-                axis.iter()
-                    .fold(0.0, |acc, val| acc + val * val)
-                    .try_sqrt()
-                    .expect("Error calculating magnitude of acceleration")
-            })
+            .enumerate()
+            .map(|(i, axes)| self.longitudinal_acceleration(i, axes))
             .collect();
 
         let clean_accelerometer_data = self.handle_outliers(accelerometer_data)?;
@@ -174,8 +251,8 @@ impl AccelerometerPreprocessor {
                 let index_quartile_floor = index_quartile.simd_floor() as usize - 1;
                 let index_quartile_ceil = index_quartile.simd_ceil() as usize - 1;
 
-                (data.get(index_quartile_floor).unwrap_or(&0.0)
-                    + data.get(index_quartile_ceil).unwrap_or(&0.0))
+                (sorted_data.get(index_quartile_floor).unwrap_or(&0.0)
+                    + sorted_data.get(index_quartile_ceil).unwrap_or(&0.0))
                     / 2.0
             })
             .collect();
@@ -207,12 +284,13 @@ mod tests {
             [0; NUM_ACCELEROMETERS]
         );
 
+        // Braking at 2 m/s^2 while level
         let raw_data: RawAccelerometerData<NUM_ACCELEROMETERS, NUM_AXIS> =
             RawAccelerometerData::from_slice(&[
-                Vec::from_slice(&[1.0, 2.0, 3.0]).unwrap(), // sqrt(14) = 3.74
-                Vec::from_slice(&[4.0, 5.0, 6.0]).unwrap(), // sqrt(77) = 8.77
-                Vec::from_slice(&[7.0, 8.0, 9.0]).unwrap(), // sqrt(194) = 13.93
-                Vec::from_slice(&[10.0, 11.0, 12.0]).unwrap(), // sqrt(365) = 19.1
+                Vec::from_slice(&[-2.0, 0.0, GRAVITY]).unwrap(),
+                Vec::from_slice(&[-2.5, 0.1, GRAVITY]).unwrap(),
+                Vec::from_slice(&[-1.5, -0.1, GRAVITY]).unwrap(),
+                Vec::from_slice(&[-2.0, 0.0, GRAVITY + 0.2]).unwrap(),
             ])
             .unwrap();
 
@@ -220,10 +298,10 @@ mod tests {
         assert!(processed_data.is_some());
 
         let processed_data = processed_data.unwrap();
-        assert_eq!(processed_data[0], (14.0_f32).sqrt());
-        assert_eq!(processed_data[1], (77.0_f32).sqrt());
-        assert_eq!(processed_data[2], (194.0_f32).sqrt());
-        assert_eq!(processed_data[3], (365.0_f32).sqrt());
+        assert_eq!(processed_data[0], -2.0);
+        assert_eq!(processed_data[1], -2.5);
+        assert_eq!(processed_data[2], -1.5);
+        assert_eq!(processed_data[3], -2.0);
     }
 
     #[test]
@@ -245,11 +323,10 @@ mod tests {
 
         let raw_data: RawAccelerometerData<NUM_ACCELEROMETERS, NUM_AXIS> =
             RawAccelerometerData::from_slice(&[
-                Vec::from_slice(&[1.0, 2.0, 3.0]).unwrap(), // sqrt(14) = 3.74
-                Vec::from_slice(&[4.0, 5.0, 6.0]).unwrap(), // sqrt(Median (3.74, 13.93,
-                // 19.1)) = 13.93
-                Vec::from_slice(&[7.0, 8.0, 9.0]).unwrap(), // sqrt(194) = 13.93
-                Vec::from_slice(&[10.0, 11.0, 12.0]).unwrap(), // sqrt(365) = 19.1
+                Vec::from_slice(&[-1.0, 0.0, GRAVITY]).unwrap(),
+                Vec::from_slice(&[5.0, 0.0, GRAVITY]).unwrap(), // Median (-1, -3, -4) = -3
+                Vec::from_slice(&[-3.0, 0.0, GRAVITY]).unwrap(),
+                Vec::from_slice(&[-4.0, 0.0, GRAVITY]).unwrap(),
             ])
             .unwrap();
 
@@ -257,10 +334,76 @@ mod tests {
         assert!(processed_data.is_some());
 
         let processed_data = processed_data.unwrap();
-        assert_eq!(processed_data[0], (14.0_f32).sqrt());
-        assert_eq!(processed_data[1], (194.0_f32).sqrt());
-        assert_eq!(processed_data[2], (194.0_f32).sqrt());
-        assert_eq!(processed_data[3], (365.0_f32).sqrt());
+        assert_eq!(processed_data[0], -1.0);
+        assert_eq!(processed_data[1], -3.0);
+        assert_eq!(processed_data[2], -3.0);
+        assert_eq!(processed_data[3], -4.0);
+    }
+
+    #[test]
+    fn test_process_data_mounting_rotations() {
+        let degrees = |roll: f32, pitch: f32, yaw: f32| {
+            Rotation3::from_euler_angles(roll.to_radians(), pitch.to_radians(), yaw.to_radians())
+        };
+        let mut preprocessor = AccelerometerPreprocessor::with_mounting_rotations([
+            degrees(0.0, 0.0, 0.0),
+            // Facing backwards
+            degrees(0.0, 0.0, 180.0),
+            // z axis along the track, x axis down
+            degrees(0.0, 90.0, 0.0),
+            // Tilted sideways
+            degrees(30.0, 0.0, 0.0),
+        ]);
+
+        // Braking at 2 m/s^2 while level, as read on each accelerometer's axes
+        let raw_data: RawAccelerometerData<NUM_ACCELEROMETERS, NUM_AXIS> =
+            RawAccelerometerData::from_slice(&[
+                Vec::from_slice(&[-2.0, 0.0, GRAVITY]).unwrap(),
+                Vec::from_slice(&[2.0, 0.0, GRAVITY]).unwrap(),
+                Vec::from_slice(&[-GRAVITY, 0.0, -2.0]).unwrap(),
+                Vec::from_slice(&[-2.0, GRAVITY * 0.5, GRAVITY * 0.75_f32.sqrt()]).unwrap(),
+            ])
+            .unwrap();
+
+        let processed_data = preprocessor.process_data(raw_data).unwrap();
+        for acceleration in processed_data {
+            assert!((acceleration + 2.0).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn test_apply_calibration() {
+        let mut preprocessor = AccelerometerPreprocessor::new();
+        let mut calibration = AccelerometerCalibration::default();
+        for sensor in calibration.sensors.iter_mut() {
+            sensor.gravity_reference = Vector3::new(0.1, 0.0, GRAVITY);
+            sensor.variance = Vector3::new(0.01, 0.02, 0.03);
+            sensor.passed = true;
+        }
+        calibration.sensors[2].passed = false;
+        preprocessor.apply_calibration(&calibration);
+
+        assert_eq!(
+            preprocessor.reliable_accelerometers,
+            [true, true, false, true]
+        );
+        assert_eq!(preprocessor.num_reliable_accelerometers, 3);
+        let stationary: Vec<f32, NUM_AXIS> = Vec::from_slice(&[0.1, 0.0, GRAVITY]).unwrap();
+        assert_eq!(preprocessor.longitudinal_acceleration(0, &stationary), 0.0);
+        assert_eq!(
+            preprocessor.longitudinal_variance(0, &calibration.sensors[0].variance),
+            0.01
+        );
+    }
+
+    #[test]
+    fn test_gravity_references() {
+        let mut preprocessor = AccelerometerPreprocessor::new();
+        let stationary: Vec<f32, NUM_AXIS> = Vec::from_slice(&[0.1, 0.0, 9.7]).unwrap();
+        assert!((preprocessor.longitudinal_acceleration(0, &stationary) - 0.1).abs() < 1e-6);
+
+        preprocessor.set_gravity_references([Vector3::new(0.1, 0.0, 9.7); NUM_ACCELEROMETERS]);
+        assert_eq!(preprocessor.longitudinal_acceleration(0, &stationary), 0.0);
     }
 
     #[test]
@@ -276,6 +419,19 @@ mod tests {
         assert_eq!(processed_data.q3, 3.5);
     }
 
+    #[test]
+    fn test_get_quartiles_unsorted() {
+        let preprocessor = AccelerometerPreprocessor::new();
+
+        let data: AccelerometerData<NUM_ACCELEROMETERS> =
+            AccelerometerData::from_slice(&[4.0, -1.0, 3.0, 2.0]).unwrap();
+        let processed_data = preprocessor.get_quartiles(&data);
+
+        assert_eq!(processed_data.q1, 0.5);
+        assert_eq!(processed_data.q2, 2.5);
+        assert_eq!(processed_data.q3, 3.5);
+    }
+
     #[test]
     fn test_calculate_quartiles_max_reliable() {
         let preprocessor = AccelerometerPreprocessor::new();
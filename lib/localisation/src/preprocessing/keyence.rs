@@ -1,16 +1,34 @@
-use crate::config::NUM_KEYENCE_SENSORS;
+use crate::config::{NUM_KEYENCE_SENSORS, STRIPE_SPACING};
 use heapless::Vec;
+use libm::ceil;
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone, Copy)]
 pub enum SensorChecks {
     Acceptable,
     Unacceptable,
 }
 
-/// Checks if the two Keyence sensors are in agreement.
-/// If the sensors disagree for two consecutive readings, the check fails.
+/// Stripe counts of neighbouring sensors may be one apart while a stripe passes between them
+const STRIPE_SKEW_TOLERANCE: u32 = 1;
+
+/// Outcome of voting on the stripe counts of the Keyence sensors
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct KeyenceCheck {
+    pub status: SensorChecks,
+    /// Stripe count agreed by the sensors
+    pub stripe_count: u32,
+    /// Sensor whose count jumped implausibly or disagrees with the others, if any
+    pub suspect: Option<usize>,
+}
+
+/// Votes on the stripe count of the Keyence sensors, taking the median of the sensors whose counts
+/// are plausible given how fast the pod is moving.
+/// Counts within one stripe of the median agree. If most sensors disagree for two consecutive
+/// readings, the check fails.
 pub struct KeyenceAgrees {
     previous_keyence_agreement: bool,
+    /// Stripe count agreed at the previous check
+    previous_stripe_count: Option<u32>,
 }
 
 impl Default for KeyenceAgrees {
@@ -23,21 +41,76 @@ impl KeyenceAgrees {
     pub fn new() -> Self {
         KeyenceAgrees {
             previous_keyence_agreement: true,
+            previous_stripe_count: None,
         }
     }
 
+    /// Votes on the stripe count. `max_velocity` is the fastest the pod could be moving, in m/s,
+    /// and `delta_t` the time since the previous check, in seconds.
     pub fn check_keyence_agrees(
         &mut self,
         keyence_data: Vec<u32, NUM_KEYENCE_SENSORS>,
-    ) -> SensorChecks {
-        // TODOLater: support more than 2 sensors
-        if keyence_data[0] != keyence_data[1] && !self.previous_keyence_agreement {
-            return SensorChecks::Unacceptable;
+        max_velocity: f64,
+        delta_t: f64,
+    ) -> KeyenceCheck {
+        // Stripes are only ever added, and no faster than the pod can pass them
+        let max_stripes_passed =
+            ceil(max_velocity.abs() * delta_t / STRIPE_SPACING) as u32 + STRIPE_SKEW_TOLERANCE;
+        let plausible: Vec<bool, NUM_KEYENCE_SENSORS> = keyence_data
+            .iter()
+            .map(|&count| match self.previous_stripe_count {
+                Some(previous) => {
+                    count + STRIPE_SKEW_TOLERANCE >= previous
+                        && count <= previous + max_stripes_passed
+                }
+                None => true,
+            })
+            .collect();
+
+        let mut candidates: Vec<u32, NUM_KEYENCE_SENSORS> = keyence_data
+            .iter()
+            .zip(&plausible)
+            .filter(|(_, &plausible)| plausible)
+            .map(|(&count, _)| count)
+            .collect();
+        candidates.sort_unstable();
+        // The lower median, so that a stripe is only counted once most sensors have seen it
+        let previous_stripe_count = self.previous_stripe_count.unwrap_or(0);
+        let stripe_count = match candidates.len() {
+            0 => previous_stripe_count,
+            len => candidates[(len - 1) / 2].max(previous_stripe_count),
+        };
+        self.previous_stripe_count = Some(stripe_count);
+        let num_agreeing = candidates
+            .iter()
+            .filter(|count| count.abs_diff(stripe_count) <= STRIPE_SKEW_TOLERANCE)
+            .count();
+        let agreement = num_agreeing * 2 > candidates.len();
+
+        let suspect = plausible
+            .iter()
+            .position(|plausible| !plausible)
+            .or_else(|| {
+                keyence_data
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, count)| count.abs_diff(stripe_count) > STRIPE_SKEW_TOLERANCE)
+                    .max_by_key(|(_, count)| count.abs_diff(stripe_count))
+                    .map(|(i, _)| i)
+            });
+
+        let status = if !agreement && !self.previous_keyence_agreement {
+            SensorChecks::Unacceptable
         } else {
-            self.previous_keyence_agreement = keyence_data[0] == keyence_data[1];
-        }
+            SensorChecks::Acceptable
+        };
+        self.previous_keyence_agreement = agreement;
 
-        SensorChecks::Acceptable
+        KeyenceCheck {
+            status,
+            stripe_count,
+            suspect,
+        }
     }
 }
 
@@ -45,22 +118,28 @@ impl KeyenceAgrees {
 mod tests {
     use super::*;
 
+    /// Fast enough to pass any number of stripes in the tests
+    const MAX_VELOCITY: f64 = 100.0;
+
     #[test]
     fn test_acceptable_success() {
         let keyence_data: Vec<u32, 2> = Vec::from_slice(&[0, 1]).unwrap();
         let mut keyence_agrees = KeyenceAgrees::new();
         let desired_outcome = SensorChecks::Acceptable;
-        let result = keyence_agrees.check_keyence_agrees(keyence_data);
-        assert_eq!(result, desired_outcome);
+        let result = keyence_agrees.check_keyence_agrees(keyence_data, MAX_VELOCITY, 1.0);
+        assert_eq!(result.status, desired_outcome);
+        assert_eq!(result.stripe_count, 0);
+        assert_eq!(result.suspect, None);
     }
 
     #[test]
     fn test_acceptable_false_success() {
-        let keyence_data: Vec<u32, 2> = Vec::from_slice(&[0, 1]).unwrap();
+        let keyence_data: Vec<u32, 2> = Vec::from_slice(&[0, 2]).unwrap();
         let mut keyence_agrees = KeyenceAgrees::new();
         let desired_outcome = SensorChecks::Acceptable;
-        let result = keyence_agrees.check_keyence_agrees(keyence_data);
-        assert_eq!(result, desired_outcome);
+        let result = keyence_agrees.check_keyence_agrees(keyence_data, MAX_VELOCITY, 1.0);
+        assert_eq!(result.status, desired_outcome);
+        assert_eq!(result.suspect, Some(1));
     }
 
     #[test]
@@ -69,34 +148,63 @@ mod tests {
         let second_keyence_data: Vec<u32, 2> = Vec::from_slice(&[1, 1]).unwrap();
         let mut keyence_agrees = KeyenceAgrees::new();
         let desired_outcome = SensorChecks::Acceptable;
-        let initial_try = keyence_agrees.check_keyence_agrees(first_keyence_data);
-        let result = keyence_agrees.check_keyence_agrees(second_keyence_data);
-        assert_eq!(initial_try, desired_outcome);
-        assert_eq!(result, desired_outcome);
+        let initial_try =
+            keyence_agrees.check_keyence_agrees(first_keyence_data, MAX_VELOCITY, 1.0);
+        let result = keyence_agrees.check_keyence_agrees(second_keyence_data, MAX_VELOCITY, 1.0);
+        assert_eq!(initial_try.status, desired_outcome);
+        assert_eq!(result.status, desired_outcome);
     }
 
     #[test]
     fn test_acceptable_prev_false_success() {
-        let first_keyence_data: Vec<u32, 2> = Vec::from_slice(&[1, 2]).unwrap();
-        let second_keyence_data: Vec<u32, 2> = Vec::from_slice(&[1, 1]).unwrap();
+        let first_keyence_data: Vec<u32, 2> = Vec::from_slice(&[1, 3]).unwrap();
+        let second_keyence_data: Vec<u32, 2> = Vec::from_slice(&[3, 3]).unwrap();
         let mut keyence_agrees = KeyenceAgrees::new();
         let desired_outcome = SensorChecks::Acceptable;
-        let initial_try = keyence_agrees.check_keyence_agrees(first_keyence_data);
-        let result = keyence_agrees.check_keyence_agrees(second_keyence_data);
-        assert_eq!(initial_try, desired_outcome);
-        assert_eq!(result, desired_outcome);
+        let initial_try =
+            keyence_agrees.check_keyence_agrees(first_keyence_data, MAX_VELOCITY, 1.0);
+        let result = keyence_agrees.check_keyence_agrees(second_keyence_data, MAX_VELOCITY, 1.0);
+        assert_eq!(initial_try.status, desired_outcome);
+        assert_eq!(result.status, desired_outcome);
+        assert_eq!(result.stripe_count, 3);
     }
 
     #[test]
     fn test_unnacceptable_prev_false_success() {
-        let first_keyence_data: Vec<u32, 2> = Vec::from_slice(&[1, 2]).unwrap();
-        let second_keyence_data: Vec<u32, 2> = Vec::from_slice(&[2, 3]).unwrap();
+        let first_keyence_data: Vec<u32, 2> = Vec::from_slice(&[1, 3]).unwrap();
+        let second_keyence_data: Vec<u32, 2> = Vec::from_slice(&[2, 4]).unwrap();
         let mut keyence_agrees = KeyenceAgrees::new();
         let first_outcome = SensorChecks::Acceptable;
         let second_outcome = SensorChecks::Unacceptable;
-        let initial_try = keyence_agrees.check_keyence_agrees(first_keyence_data);
-        let result = keyence_agrees.check_keyence_agrees(second_keyence_data);
-        assert_eq!(initial_try, first_outcome);
-        assert_eq!(result, second_outcome);
+        let initial_try =
+            keyence_agrees.check_keyence_agrees(first_keyence_data, MAX_VELOCITY, 1.0);
+        let result = keyence_agrees.check_keyence_agrees(second_keyence_data, MAX_VELOCITY, 1.0);
+        assert_eq!(initial_try.status, first_outcome);
+        assert_eq!(result.status, second_outcome);
+    }
+
+    #[test]
+    fn test_implausible_jump() {
+        let mut keyence_agrees = KeyenceAgrees::new();
+        let first_keyence_data: Vec<u32, 2> = Vec::from_slice(&[10, 10]).unwrap();
+        keyence_agrees.check_keyence_agrees(first_keyence_data, 0.0, 0.0);
+
+        // At 2 m/s, at most 1 stripe passes in 0.5 s, plus the skew between sensors
+        let keyence_data: Vec<u32, 2> = Vec::from_slice(&[11, 15]).unwrap();
+        let result = keyence_agrees.check_keyence_agrees(keyence_data, 2.0, 0.5);
+        assert_eq!(result.status, SensorChecks::Acceptable);
+        assert_eq!(result.stripe_count, 11);
+        assert_eq!(result.suspect, Some(1));
+
+        // A sensor a stripe behind is tolerated, but the agreed count never goes down
+        let keyence_data: Vec<u32, 2> = Vec::from_slice(&[10, 16]).unwrap();
+        let result = keyence_agrees.check_keyence_agrees(keyence_data, 2.0, 0.5);
+        assert_eq!(result.stripe_count, 11);
+        assert_eq!(result.suspect, Some(1));
+
+        let keyence_data: Vec<u32, 2> = Vec::from_slice(&[3, 3]).unwrap();
+        let result = keyence_agrees.check_keyence_agrees(keyence_data, 2.0, 0.5);
+        assert_eq!(result.stripe_count, 11);
+        assert_eq!(result.suspect, Some(0));
     }
 }
@@ -1,53 +1,163 @@
-use crate::config::NUM_OPTICAL_FLOW_SENSORS;
+use crate::config::{
+    NUM_OPTICAL_FLOW_SENSORS, OPTICAL_FLOW_FIELD_OF_VIEW, OPTICAL_FLOW_HEIGHT_OFFSET,
+    OPTICAL_FLOW_ORIENTATION, OPTICAL_FLOW_RESOLUTION, OPTICAL_FLOW_SAMPLE_INTERVAL,
+};
 use heapless::Vec;
-use libm::sqrtf;
+use libm::tan;
 
-/// Processes the raw optical data to get the magnitude and added to the optical data for each sensor
-pub fn process_optical_data(raw_optical_data: Vec<Vec<f64, 2>, NUM_OPTICAL_FLOW_SENSORS>) -> f32 {
-    let mut total_magnitude: f32 = 0.0;
+/// Rotation of the optical flow sensor about the vertical. At `Degrees0` the sensor's x axis
+/// points along the track in the direction of travel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Orientation {
+    Degrees0,
+    Degrees90,
+    Degrees180,
+    Degrees270,
+}
+
+impl Orientation {
+    /// Gets the orientation from a rotation in degrees, which must be a multiple of 90
+    pub const fn from_degrees(degrees: u32) -> Self {
+        match degrees % 360 {
+            0 => Orientation::Degrees0,
+            90 => Orientation::Degrees90,
+            180 => Orientation::Degrees180,
+            270 => Orientation::Degrees270,
+            _ => panic!("The optical flow sensor can only be rotated by a multiple of 90 degrees"),
+        }
+    }
+
+    /// Picks the pixel count along the track, in the direction of travel, out of the x and y
+    /// counts of the sensor
+    pub fn along_track(&self, x: f64, y: f64) -> f64 {
+        match self {
+            Orientation::Degrees0 => x,
+            Orientation::Degrees90 => y,
+            Orientation::Degrees180 => -x,
+            Orientation::Degrees270 => -y,
+        }
+    }
+}
 
-    for sensor_data in raw_optical_data {
-        let mut magnitude: f32 = 0.0;
+/// Converts the pixel counts of an optical flow sensor into ground speed.
+///
+/// The sensor images a patch of track whose width grows with its height above the track, so
+/// each pixel covers `2 * height * tan(field_of_view / 2) / resolution` metres of track.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OpticalFlowModel {
+    /// Field of view of the lens, in radians
+    pub field_of_view: f64,
+    /// Pixels across the field of view
+    pub resolution: f64,
+    /// Time over which each reading's pixel counts are accumulated, in seconds
+    pub sample_interval: f64,
+    /// Height of the lens above the height measured by laser triangulation, in metres
+    pub height_offset: f64,
+    pub orientation: Orientation,
+}
 
-        for data in sensor_data {
-            let data: f32 = data as f32;
-            magnitude += data * data;
+impl OpticalFlowModel {
+    /// Gets the model configured in `config/localisation.yaml`
+    pub const fn from_config() -> Self {
+        OpticalFlowModel {
+            field_of_view: OPTICAL_FLOW_FIELD_OF_VIEW.to_radians(),
+            resolution: OPTICAL_FLOW_RESOLUTION,
+            sample_interval: OPTICAL_FLOW_SAMPLE_INTERVAL,
+            height_offset: OPTICAL_FLOW_HEIGHT_OFFSET,
+            orientation: Orientation::from_degrees(OPTICAL_FLOW_ORIENTATION),
         }
-        total_magnitude += sqrtf(magnitude);
     }
 
-    total_magnitude / NUM_OPTICAL_FLOW_SENSORS as f32
+    /// Length of track covered by a pixel when the sensor is `height` metres above the track
+    pub fn metres_per_pixel(&self, height: f64) -> f64 {
+        2.0 * (height + self.height_offset) * tan(self.field_of_view / 2.0) / self.resolution
+    }
+
+    /// Velocity along the track, in m/s, from the x and y pixel counts of one reading
+    pub fn ground_speed(&self, motion: &Vec<f64, 2>, height: f64) -> f64 {
+        let pixels = self.orientation.along_track(motion[0], motion[1]);
+        pixels * self.metres_per_pixel(height) / self.sample_interval
+    }
+}
+
+/// Processes the raw optical data of each sensor into velocity along the track, in m/s, averaged
+/// over the sensors. `height` is the height above the track from laser triangulation, in metres.
+pub fn process_optical_data(
+    raw_optical_data: Vec<Vec<f64, 2>, NUM_OPTICAL_FLOW_SENSORS>,
+    height: f64,
+) -> f64 {
+    let model = OpticalFlowModel::from_config();
+    let total_velocity: f64 = raw_optical_data
+        .iter()
+        .map(|motion| model.ground_speed(motion, height))
+        .sum();
+
+    total_velocity / NUM_OPTICAL_FLOW_SENSORS as f64
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::NUM_OPTICAL_FLOW_SENSORS;
+
+    /// 60 degree field of view over 30 pixels at 1 cm above the track: each pixel covers
+    /// 2 * 0.01 * tan(30 degrees) / 30 = 0.385 mm
+    const MODEL: OpticalFlowModel = OpticalFlowModel {
+        field_of_view: core::f64::consts::FRAC_PI_3,
+        resolution: 30.0,
+        sample_interval: 0.01,
+        height_offset: 0.0,
+        orientation: Orientation::Degrees0,
+    };
+
+    fn motion(x: f64, y: f64) -> Vec<f64, 2> {
+        Vec::from_slice(&[x, y]).unwrap()
+    }
 
     #[test]
-    fn test_correct_positive() {
-        let raw_optical_data: Vec<Vec<f64, 2>, NUM_OPTICAL_FLOW_SENSORS> =
-            Vec::from_slice(&[Vec::from_slice(&[1.0, 1.0]).unwrap()]).unwrap();
-        let desired_outcome: f32 = sqrtf(2.0);
-        let result = process_optical_data(raw_optical_data);
-        assert_eq!(result, desired_outcome);
+    fn test_ground_speed() {
+        let metres_per_pixel = 0.02 * tan(core::f64::consts::FRAC_PI_6) / 30.0;
+        assert!((MODEL.metres_per_pixel(0.01) - metres_per_pixel).abs() < 1e-12);
+
+        let velocity = MODEL.ground_speed(&motion(100.0, 3.0), 0.01);
+        assert!((velocity - 100.0 * metres_per_pixel / 0.01).abs() < 1e-9);
+
+        // Twice as high, each pixel covers twice as much track
+        let higher = MODEL.ground_speed(&motion(100.0, 3.0), 0.02);
+        assert!((higher - 2.0 * velocity).abs() < 1e-9);
+
+        assert_eq!(MODEL.ground_speed(&motion(0.0, 0.0), 0.01), 0.0);
     }
 
     #[test]
-    fn test_correct_negative() {
-        let raw_optical_data: Vec<Vec<f64, 2>, NUM_OPTICAL_FLOW_SENSORS> =
-            Vec::from_slice(&[Vec::from_slice(&[-4.0, -6.0]).unwrap()]).unwrap();
-        let desired_outcome: f32 = sqrtf(52.0);
-        let result = process_optical_data(raw_optical_data);
-        assert_eq!(result, desired_outcome);
+    fn test_orientation() {
+        let rotated = |orientation| OpticalFlowModel {
+            orientation,
+            ..MODEL
+        };
+        let forwards = MODEL.ground_speed(&motion(50.0, 0.0), 0.01);
+        assert!(forwards > 0.0);
+        assert_eq!(
+            rotated(Orientation::Degrees90).ground_speed(&motion(0.0, 50.0), 0.01),
+            forwards
+        );
+        assert_eq!(
+            rotated(Orientation::Degrees180).ground_speed(&motion(50.0, 0.0), 0.01),
+            -forwards
+        );
+        assert_eq!(
+            rotated(Orientation::Degrees270).ground_speed(&motion(0.0, -50.0), 0.01),
+            forwards
+        );
+        assert_eq!(Orientation::from_degrees(450), Orientation::Degrees90);
     }
 
     #[test]
-    fn test_correct_zero() {
+    fn test_process_optical_data() {
         let raw_optical_data: Vec<Vec<f64, 2>, NUM_OPTICAL_FLOW_SENSORS> =
-            Vec::from_slice(&[Vec::from_slice(&[0.0, 0.0]).unwrap()]).unwrap();
-        let desired_outcome: f32 = 0.0;
-        let result = process_optical_data(raw_optical_data);
-        assert_eq!(result, desired_outcome);
+            Vec::from_slice(&[motion(-40.0, 7.0)]).unwrap();
+        let model = OpticalFlowModel::from_config();
+        let result = process_optical_data(raw_optical_data, 0.03);
+        assert_eq!(result, model.ground_speed(&motion(-40.0, 7.0), 0.03));
+        assert!(result != 0.0);
     }
 }
@@ -1,4 +1,5 @@
 use heapless::Vec;
+use hyped_core::{config::MeasurementId, types::LocalisationMode};
 
 #[derive(PartialEq)]
 pub enum SensorChecks {
@@ -9,3 +10,116 @@ pub enum SensorChecks {
 pub type RawAccelerometerData<const NUM_ACC: usize, const NUM_AXIS: usize> =
     Vec<Vec<f32, NUM_AXIS>, NUM_ACC>;
 pub type AccelerometerData<const NUM_ACC: usize> = Vec<f32, NUM_ACC>;
+
+/// The pod's estimated position along the track, as returned by `Localizer::iteration`
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct LocalisationEstimate {
+    /// Time the sensor readings behind the estimate were taken, in milliseconds
+    pub timestamp_ms: u64,
+    /// Distance travelled along the track (m)
+    pub displacement: f64,
+    /// Velocity along the track (m/s)
+    pub velocity: f64,
+    /// Acceleration along the track (m/s^2)
+    pub acceleration: f64,
+    /// Standard deviation of the displacement (m)
+    pub displacement_std: f64,
+    /// Standard deviation of the velocity (m/s)
+    pub velocity_std: f64,
+    /// Standard deviation of the acceleration (m/s^2)
+    pub acceleration_std: f64,
+    /// Sensors the estimate is based on
+    pub mode: LocalisationMode,
+    /// Whether the estimate can be used, i.e. every value and standard deviation is finite
+    pub valid: bool,
+}
+
+impl LocalisationEstimate {
+    /// The estimate as readings of the `displacement`, `velocity` and `acceleration`
+    /// measurements and the `localisation_mode` status. Invalid estimates are sent as NaN, so
+    /// that nothing acts on them.
+    pub fn measurements(&self) -> [(MeasurementId, f32); 4] {
+        let value = |value: f64| match self.valid {
+            true => value as f32,
+            false => f32::NAN,
+        };
+        [
+            (MeasurementId::Displacement, value(self.displacement)),
+            (MeasurementId::Velocity, value(self.velocity)),
+            (MeasurementId::Acceleration, value(self.acceleration)),
+            (MeasurementId::LocalisationMode, self.mode as u8 as f32),
+        ]
+    }
+}
+
+/// How well a sensor's measurements agree with the rest of the localiser
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SensorHealth {
+    /// Measurements rejected since the last one that was fused
+    pub consecutive_rejections: u32,
+    /// Measurements that passed the innovation gate since the last one that was rejected
+    pub consecutive_passes: u32,
+    /// Measurements rejected since the localiser started
+    pub total_rejections: u32,
+    /// Whether the sensor has had too many measurements rejected in a row. The measurements of a
+    /// faulty sensor are only checked against the innovation gate, not fused.
+    pub faulty: bool,
+}
+
+impl SensorHealth {
+    /// Counts a measurement as passing or failing the innovation gate. The sensor is declared
+    /// faulty once `max_consecutive_rejections` are rejected in a row, and recovers once
+    /// `recovery_passes` pass in a row.
+    pub fn record(&mut self, passed: bool, max_consecutive_rejections: u32, recovery_passes: u32) {
+        if passed {
+            self.consecutive_rejections = 0;
+            self.consecutive_passes = self.consecutive_passes.saturating_add(1);
+            if self.consecutive_passes >= recovery_passes {
+                self.faulty = false;
+            }
+            return;
+        }
+        self.consecutive_passes = 0;
+        self.consecutive_rejections += 1;
+        self.total_rejections += 1;
+        if self.consecutive_rejections >= max_consecutive_rejections {
+            self.faulty = true;
+        }
+    }
+}
+
+/// Health of the sensors fused by the localiser, as returned by `Localizer::health`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LocaliserHealth {
+    pub keyence: SensorHealth,
+    pub optical_flow: SensorHealth,
+    /// Keyence sensor outvoted by the others at the latest reading, if any
+    pub suspect_keyence_sensor: Option<usize>,
+    /// Whether the keyence sensors have disagreed with each other for consecutive readings
+    pub keyence_disagree: bool,
+    /// Whether too many accelerometers were outliers at the latest reading
+    pub accelerometers_unreliable: bool,
+}
+
+impl LocaliserHealth {
+    /// Whether every sensor is still being fused
+    pub fn is_healthy(&self) -> bool {
+        !self.keyence.faulty
+            && !self.optical_flow.faulty
+            && !self.keyence_disagree
+            && !self.accelerometers_unreliable
+    }
+
+    /// The mode the localiser is left in by the sensors that can still be used. The keyence
+    /// sensors and optical flow both correct the accelerometers, so either is enough.
+    pub fn mode(&self) -> LocalisationMode {
+        let keyence = !self.keyence.faulty && !self.keyence_disagree;
+        let corrected = keyence || !self.optical_flow.faulty;
+        match (!self.accelerometers_unreliable, corrected) {
+            (true, true) => LocalisationMode::Nominal,
+            (true, false) => LocalisationMode::DeadReckoning,
+            (false, true) => LocalisationMode::NoAccelerometers,
+            (false, false) => LocalisationMode::Lost,
+        }
+    }
+}
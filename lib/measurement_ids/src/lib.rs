@@ -16,7 +16,8 @@ pub fn gen_measurement_ids(args: TokenStream) -> TokenStream {
     let yaml_path = args[0].clone().replace("\"", "");
     let pod_id = args[1].clone().replace("\"", "");
 
-    let measurement_ids = get_measurement_ids(yaml_path, pod_id);
+    let measurement_ids = get_measurement_ids(yaml_path.clone(), pod_id.clone());
+    let measurement_infos = get_measurement_infos(yaml_path, pod_id);
 
     // Actual enum
     let mut enum_str =
@@ -105,6 +106,23 @@ pub fn gen_measurement_ids(args: TokenStream) -> TokenStream {
     enum_str.push_str("    }\n");
     enum_str.push_str("}\n");
 
+    // FromStr for MeasurementId, for input that may not be a valid ID
+    enum_str.push_str("\nimpl FromStr for MeasurementId {\n");
+    enum_str.push_str("    type Err = &'static str;\n");
+    enum_str.push_str("    fn from_str(s: &str) -> Result<Self, Self::Err> {\n");
+    enum_str.push_str("        match s {\n");
+    for id in measurement_ids.clone() {
+        enum_str.push_str(&format!(
+            "            \"{}\" => Ok(MeasurementId::{}),\n",
+            id.to_case(Case::Snake),
+            id,
+        ));
+    }
+    enum_str.push_str("            _ => Err(\"Unknown measurement ID\"),\n");
+    enum_str.push_str("        }\n");
+    enum_str.push_str("    }\n");
+    enum_str.push_str("}\n");
+
     // From<MeasurementId> for u16
     enum_str.push_str("\nimpl From<MeasurementId> for u16 {\n");
     enum_str.push_str("    fn from(measurement_id: MeasurementId) -> u16 {\n");
@@ -129,18 +147,195 @@ pub fn gen_measurement_ids(args: TokenStream) -> TokenStream {
     enum_str.push_str("    }\n");
     enum_str.push_str("}\n");
 
+    // impl MeasurementId (kind, unit and limits)
+    enum_str.push_str("\nimpl MeasurementId {\n");
+    enum_str.push_str("    /// The kind of the measurement, as defined in the pod configuration\n");
+    enum_str.push_str("    pub fn kind(&self) -> &'static str {\n");
+    enum_str.push_str("        match self {\n");
+    for info in measurement_infos.clone() {
+        enum_str.push_str(&format!(
+            "            MeasurementId::{} => \"{}\",\n",
+            info.id, info.kind,
+        ));
+    }
+    enum_str.push_str("        }\n");
+    enum_str.push_str("    }\n");
+    enum_str
+        .push_str("\n    /// The unit of the measurement, as defined in the pod configuration\n");
+    enum_str.push_str("    pub fn unit(&self) -> &'static str {\n");
+    enum_str.push_str("        match self {\n");
+    for info in measurement_infos.clone() {
+        enum_str.push_str(&format!(
+            "            MeasurementId::{} => \"{}\",\n",
+            info.id, info.unit,
+        ));
+    }
+    enum_str.push_str("        }\n");
+    enum_str.push_str("    }\n");
+    enum_str
+        .push_str("\n    /// The limits of the measurement, as defined in the pod configuration\n");
+    enum_str.push_str("    pub fn limits(&self) -> MeasurementLimits {\n");
+    enum_str.push_str("        match self {\n");
+    for info in measurement_infos {
+        let warning = match info.warning {
+            Some((low, high)) => format!("Some(Limits {{ low: {low:?}, high: {high:?} }})"),
+            None => "None".to_string(),
+        };
+        enum_str.push_str(&format!(
+            "            MeasurementId::{} => MeasurementLimits {{ critical: Limits {{ low: {:?}, high: {:?} }}, warning: {} }},\n",
+            info.id, info.critical.0, info.critical.1, warning,
+        ));
+    }
+    enum_str.push_str("        }\n");
+    enum_str.push_str("    }\n");
+    enum_str.push_str("}\n");
+
     enum_str.parse().expect("Failed to parse enum END")
 }
 
+/// Generates `MeasurementId::payload_encoding` from the `mqtt.payloads.encodings` section of the
+/// telemetry configuration. Measurements that are not listed are sent as JSON.
+#[proc_macro]
+pub fn gen_payload_encodings(args: TokenStream) -> TokenStream {
+    let yaml_path = args.to_string().replace(" ", "").replace("\"", "");
+    let yaml =
+        get_yaml(yaml_path.clone()).unwrap_or_else(|| panic!("Failed to load yaml: {yaml_path}"));
+
+    let mut impl_str = String::from("impl MeasurementId {\n");
+    impl_str.push_str("    /// The encoding used for MQTT payloads of the measurement\n");
+    impl_str.push_str("    pub fn payload_encoding(&self) -> PayloadEncoding {\n");
+    impl_str.push_str("        #[allow(unreachable_patterns)]\n");
+    impl_str.push_str("        match self {\n");
+    if let Some(encodings) = yaml["mqtt"]["payloads"]["encodings"].as_hash() {
+        for (key, encoding) in encodings {
+            let key = key.as_str().unwrap();
+            let encoding = match encoding.as_str() {
+                Some("json") => "Json",
+                Some("cbor") => "Cbor",
+                _ => panic!("Unknown payload encoding for measurement {key}"),
+            };
+            impl_str.push_str(&format!(
+                "            MeasurementId::{} => PayloadEncoding::{},\n",
+                key.to_case(Case::Pascal),
+                encoding,
+            ));
+        }
+    }
+    impl_str.push_str("            _ => PayloadEncoding::Json,\n");
+    impl_str.push_str("        }\n");
+    impl_str.push_str("    }\n");
+    impl_str.push_str("}\n");
+
+    impl_str.parse().expect("Failed to parse payload encodings")
+}
+
+/// Generates `MeasurementId::aggregation_window_ms` from the `mqtt.aggregation.windows_ms` section
+/// of the telemetry configuration, which is keyed by measurement kind.
+#[proc_macro]
+pub fn gen_aggregation_windows(args: TokenStream) -> TokenStream {
+    let yaml_path = args.to_string().replace(" ", "").replace("\"", "");
+    let yaml =
+        get_yaml(yaml_path.clone()).unwrap_or_else(|| panic!("Failed to load yaml: {yaml_path}"));
+
+    let mut impl_str = String::from("impl MeasurementId {\n");
+    impl_str.push_str(
+        "    /// The window readings are aggregated over before being sent over MQTT, if any\n",
+    );
+    impl_str.push_str("    pub fn aggregation_window_ms(&self) -> Option<u64> {\n");
+    impl_str.push_str("        match self.kind() {\n");
+    if let Some(windows) = yaml["mqtt"]["aggregation"]["windows_ms"].as_hash() {
+        for (kind, window_ms) in windows {
+            let kind = kind.as_str().unwrap();
+            let window_ms = window_ms
+                .as_i64()
+                .filter(|window_ms| *window_ms > 0)
+                .unwrap_or_else(|| panic!("Invalid aggregation window for kind {kind}"));
+            impl_str.push_str(&format!("            \"{kind}\" => Some({window_ms}),\n"));
+        }
+    }
+    impl_str.push_str("            _ => None,\n");
+    impl_str.push_str("        }\n");
+    impl_str.push_str("    }\n");
+    impl_str.push_str("}\n");
+
+    impl_str
+        .parse()
+        .expect("Failed to parse aggregation windows")
+}
+
+/// Information about a measurement that is needed at runtime, e.g. for telemetry payloads
+#[derive(Clone)]
+struct MeasurementInfo {
+    id: String,
+    kind: String,
+    unit: String,
+    critical: (f32, f32),
+    warning: Option<(f32, f32)>,
+}
+
+fn get_measurement_infos(yaml_path: String, pod_id: String) -> Vec<MeasurementInfo> {
+    let mut measurement_infos = Vec::new();
+    for (key, measurement) in get_measurements(yaml_path, pod_id) {
+        let limits = &measurement["limits"];
+        let critical = get_limits(&limits["critical"])
+            .or_else(|| get_value_limits(&measurement["values"]))
+            .unwrap_or_else(|| panic!("Measurement {key} is missing critical limits"));
+        measurement_infos.push(MeasurementInfo {
+            id: key.to_case(Case::Pascal),
+            kind: measurement["kind"].as_str().unwrap_or_default().to_string(),
+            unit: measurement["unit"].as_str().unwrap_or_default().to_string(),
+            critical,
+            warning: get_limits(&limits["warning"]),
+        });
+    }
+    measurement_infos
+}
+
+fn get_limits(limits: &Yaml) -> Option<(f32, f32)> {
+    Some((get_number(&limits["low"])?, get_number(&limits["high"])?))
+}
+
+/// Statuses are limited to the lowest and highest of their values
+fn get_value_limits(values: &Yaml) -> Option<(f32, f32)> {
+    let values = values
+        .as_vec()?
+        .iter()
+        .filter_map(|value| get_number(&value["value"]))
+        .collect::<Vec<f32>>();
+    let low = values.iter().copied().reduce(f32::min)?;
+    let high = values.iter().copied().reduce(f32::max)?;
+    Some((low, high))
+}
+
+fn get_number(value: &Yaml) -> Option<f32> {
+    match value.as_i64() {
+        Some(value) => Some(value as f32),
+        None => value.as_f64().map(|value| value as f32),
+    }
+}
+
 fn get_measurement_ids(yaml_path: String, pod_id: String) -> Vec<String> {
+    get_measurements(yaml_path, pod_id)
+        .into_iter()
+        .map(|(key, _)| key.to_case(Case::Pascal))
+        .collect()
+}
+
+/// The measurements and then the statuses of the pod, by key. Statuses are read like any other
+/// measurement, so they get a `MeasurementId` too.
+fn get_measurements(yaml_path: String, pod_id: String) -> Vec<(String, Yaml)> {
     let yaml =
         get_yaml(yaml_path.clone()).unwrap_or_else(|| panic!("Failed to load yaml: {yaml_path}"));
-    let mut measurement_ids = Vec::new();
-    let measurements = yaml["pods"][pod_id.clone().as_str()]["measurements"].clone();
-    for key in measurements.as_hash().unwrap().keys() {
-        measurement_ids.push(key.as_str().unwrap().to_string().to_case(Case::Pascal));
+    let pod = &yaml["pods"][pod_id.as_str()];
+    let mut measurements = Vec::new();
+    for section in ["measurements", "statuses"] {
+        if let Some(entries) = pod[section].as_hash() {
+            for (key, measurement) in entries {
+                measurements.push((key.as_str().unwrap().to_string(), measurement.clone()));
+            }
+        }
     }
-    measurement_ids
+    measurements
 }
 
 fn get_yaml(yaml_path: String) -> Option<Yaml> {
@@ -0,0 +1,26 @@
+//! Prints the transitions of a run mode as a diagram, for reviewing changes to
+//! `config/states.yaml`, e.g.
+//! `cargo run -p hyped_state_machine --example state_diagram -- mermaid LEVITATION_ONLY`
+
+use hyped_state_machine::{
+    diagram::{write_graphviz, write_mermaid},
+    modes::RunMode,
+};
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let (format, mode) = match args.as_slice() {
+        [_, format, mode] => (format.as_str(), mode.as_str()),
+        _ => panic!("Usage: state_diagram <graphviz|mermaid> <run mode>"),
+    };
+    let mode: RunMode = mode.parse().expect("Invalid run mode");
+
+    let mut diagram = String::new();
+    match format {
+        "graphviz" => write_graphviz(mode, &mut diagram),
+        "mermaid" => write_mermaid(mode, &mut diagram),
+        _ => panic!("Unknown diagram format {format}, expected graphviz or mermaid"),
+    }
+    .expect("Failed to write diagram");
+    print!("{diagram}");
+}
@@ -0,0 +1,253 @@
+use crate::states::State;
+use core::future::Future;
+use heapless::Vec;
+
+/// Why a state action failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum ActionError {
+    /// The action could not be done, but the pod is still safe, so the transition fails
+    Failed,
+    /// The action may have left the pod unsafe, so the pod must enter an emergency
+    Unsafe,
+}
+
+impl From<ActionError> for u8 {
+    fn from(error: ActionError) -> Self {
+        match error {
+            ActionError::Failed => 0,
+            ActionError::Unsafe => 1,
+        }
+    }
+}
+
+impl TryFrom<u8> for ActionError {
+    type Error = &'static str;
+
+    fn try_from(index: u8) -> Result<Self, Self::Error> {
+        match index {
+            0 => Ok(ActionError::Failed),
+            1 => Ok(ActionError::Unsafe),
+            _ => Err("Invalid action error"),
+        }
+    }
+}
+
+/// When an action runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum ActionHook {
+    /// When the pod enters the state
+    Enter,
+    /// When the pod leaves the state
+    Exit,
+}
+
+impl From<ActionHook> for u8 {
+    fn from(hook: ActionHook) -> Self {
+        match hook {
+            ActionHook::Enter => 0,
+            ActionHook::Exit => 1,
+        }
+    }
+}
+
+impl TryFrom<u8> for ActionHook {
+    type Error = &'static str;
+
+    fn try_from(index: u8) -> Result<Self, Self::Error> {
+        match index {
+            0 => Ok(ActionHook::Enter),
+            1 => Ok(ActionHook::Exit),
+            _ => Err("Invalid action hook"),
+        }
+    }
+}
+
+/// An action that failed while the pod was changing state
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct ActionFailure {
+    /// The state the action was registered on
+    pub state: State,
+    pub hook: ActionHook,
+    pub error: ActionError,
+}
+
+impl ActionFailure {
+    /// Whether the pod must enter an emergency, rather than only failing the transition
+    pub fn requires_emergency(&self) -> bool {
+        self.error == ActionError::Unsafe
+    }
+}
+
+/// Something a board does when the pod enters or leaves a state,
+/// e.g. engaging the brakes on entering `State::Brake`.
+/// Boards usually implement this for an enum of all of their actions.
+pub trait StateAction {
+    fn run(&mut self) -> impl Future<Output = Result<(), ActionError>>;
+}
+
+/// The actions a board runs when the pod enters and leaves each state
+pub struct StateActions<A: StateAction, const N: usize> {
+    actions: Vec<(State, ActionHook, A), N>,
+}
+
+impl<A: StateAction, const N: usize> Default for StateActions<A, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<A: StateAction, const N: usize> StateActions<A, N> {
+    pub fn new() -> Self {
+        StateActions {
+            actions: Vec::new(),
+        }
+    }
+
+    /// Registers an action to run when the pod enters `state`
+    pub fn on_enter(&mut self, state: State, action: A) -> Result<(), &'static str> {
+        self.add(state, ActionHook::Enter, action)
+    }
+
+    /// Registers an action to run when the pod leaves `state`
+    pub fn on_exit(&mut self, state: State, action: A) -> Result<(), &'static str> {
+        self.add(state, ActionHook::Exit, action)
+    }
+
+    fn add(&mut self, state: State, hook: ActionHook, action: A) -> Result<(), &'static str> {
+        self.actions
+            .push((state, hook, action))
+            .map_err(|_| "Too many state actions")
+    }
+
+    /// Runs the exit actions of `from`, then the enter actions of `to`, in the order they were
+    /// registered, stopping at the first failure.
+    /// When entering `State::EMERGENCY`, its enter actions run even if an exit action failed.
+    pub async fn transition(&mut self, from: State, to: State) -> Result<(), ActionFailure> {
+        let exited = self.run(from, ActionHook::Exit).await;
+        if exited.is_err() && to != State::EMERGENCY {
+            return exited;
+        }
+        let entered = self.run(to, ActionHook::Enter).await;
+        exited.and(entered)
+    }
+
+    async fn run(&mut self, state: State, hook: ActionHook) -> Result<(), ActionFailure> {
+        for (action_state, action_hook, action) in self.actions.iter_mut() {
+            if *action_state == state && *action_hook == hook {
+                action
+                    .run()
+                    .await
+                    .map_err(|error| ActionFailure { state, hook, error })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::RefCell;
+    use embassy_futures::block_on;
+
+    /// Records that it ran, then returns the configured result
+    struct MockAction<'a> {
+        name: &'static str,
+        result: Result<(), ActionError>,
+        log: &'a RefCell<std::vec::Vec<&'static str>>,
+    }
+
+    impl StateAction for MockAction<'_> {
+        async fn run(&mut self) -> Result<(), ActionError> {
+            self.log.borrow_mut().push(self.name);
+            self.result
+        }
+    }
+
+    fn mock<'a>(
+        name: &'static str,
+        result: Result<(), ActionError>,
+        log: &'a RefCell<std::vec::Vec<&'static str>>,
+    ) -> MockAction<'a> {
+        MockAction { name, result, log }
+    }
+
+    #[test]
+    fn test_exit_then_enter() {
+        let log = RefCell::new(std::vec::Vec::new());
+        let mut actions = StateActions::<_, 4>::new();
+        actions
+            .on_enter(State::Brake, mock("engage_brakes", Ok(()), &log))
+            .unwrap();
+        actions
+            .on_exit(State::Accelerate, mock("stop_motors", Ok(()), &log))
+            .unwrap();
+        actions
+            .on_enter(State::Precharge, mock("close_relay", Ok(()), &log))
+            .unwrap();
+
+        assert_eq!(
+            block_on(actions.transition(State::Accelerate, State::Brake)),
+            Ok(())
+        );
+        assert_eq!(*log.borrow(), ["stop_motors", "engage_brakes"]);
+    }
+
+    #[test]
+    fn test_failure_stops_transition() {
+        let log = RefCell::new(std::vec::Vec::new());
+        let mut actions = StateActions::<_, 4>::new();
+        actions
+            .on_exit(
+                State::Calibrate,
+                mock("finish_calibration", Err(ActionError::Failed), &log),
+            )
+            .unwrap();
+        actions
+            .on_enter(State::Precharge, mock("close_relay", Ok(()), &log))
+            .unwrap();
+
+        let failure = block_on(actions.transition(State::Calibrate, State::Precharge));
+        assert_eq!(
+            failure,
+            Err(ActionFailure {
+                state: State::Calibrate,
+                hook: ActionHook::Exit,
+                error: ActionError::Failed,
+            })
+        );
+        assert!(!failure.unwrap_err().requires_emergency());
+        assert_eq!(*log.borrow(), ["finish_calibration"]);
+    }
+
+    #[test]
+    fn test_emergency_actions_always_run() {
+        let log = RefCell::new(std::vec::Vec::new());
+        let mut actions = StateActions::<_, 4>::new();
+        actions
+            .on_exit(
+                State::Accelerate,
+                mock("stop_motors", Err(ActionError::Unsafe), &log),
+            )
+            .unwrap();
+        actions
+            .on_enter(State::Emergency, mock("engage_brakes", Ok(()), &log))
+            .unwrap();
+
+        let failure = block_on(actions.transition(State::Accelerate, State::Emergency));
+        assert!(failure.unwrap_err().requires_emergency());
+        assert_eq!(*log.borrow(), ["stop_motors", "engage_brakes"]);
+    }
+
+    #[test]
+    fn test_failure_conversions() {
+        for hook in [ActionHook::Enter, ActionHook::Exit] {
+            assert_eq!(ActionHook::try_from(u8::from(hook)), Ok(hook));
+        }
+        for error in [ActionError::Failed, ActionError::Unsafe] {
+            assert_eq!(ActionError::try_from(u8::from(error)), Ok(error));
+        }
+        assert!(ActionHook::try_from(2).is_err());
+        assert!(ActionError::try_from(2).is_err());
+    }
+}
@@ -0,0 +1,259 @@
+use crate::{guards::FailedGuards, state_machine::RejectionReason, states::State};
+use core::str::FromStr;
+use heapless::{Deque, String};
+
+/// Requester recorded for transitions the state machine makes when the pod times out in a state
+pub const TIMEOUT_REQUESTER: &str = "timeout";
+
+/// Requester recorded for transitions the state machine makes when it detects an event
+pub const EVENT_REQUESTER: &str = "event";
+
+/// Sent over CAN in place of a `RejectionReason` code for accepted transitions
+const ACCEPTED_CODE: u8 = u8::MAX;
+
+/// Whether a state transition was made
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum AuditOutcome {
+    Accepted,
+    Rejected(RejectionReason),
+}
+
+impl AuditOutcome {
+    /// Code identifying the outcome, sent over CAN. Rejections use the code of their reason.
+    pub fn code(&self) -> u8 {
+        match self {
+            AuditOutcome::Accepted => ACCEPTED_CODE,
+            AuditOutcome::Rejected(reason) => reason.code(),
+        }
+    }
+
+    /// The failed guards, or none unless the transition was rejected by its guards
+    pub fn failed_guards(&self) -> FailedGuards {
+        match self {
+            AuditOutcome::Accepted => FailedGuards::default(),
+            AuditOutcome::Rejected(reason) => reason.failed_guards(),
+        }
+    }
+
+    /// Rebuilds an outcome from its code and failed guards, e.g. after receiving it over CAN
+    pub fn from_code(code: u8, failed_guards: FailedGuards) -> Result<Self, &'static str> {
+        match code {
+            ACCEPTED_CODE => Ok(AuditOutcome::Accepted),
+            code => RejectionReason::from_code(code, failed_guards).map(AuditOutcome::Rejected),
+        }
+    }
+}
+
+impl From<AuditOutcome> for &str {
+    fn from(val: AuditOutcome) -> Self {
+        match val {
+            AuditOutcome::Accepted => "accepted",
+            AuditOutcome::Rejected(_) => "rejected",
+        }
+    }
+}
+
+/// A state transition, or a request for one, seen by the state machine
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct AuditEntry {
+    /// Number of entries recorded before this one, so that dropped entries can be spotted
+    pub sequence: u32,
+    /// Time since the board keeping the log booted, in milliseconds
+    pub timestamp_ms: u64,
+    /// Name of the board that requested the transition, or `TIMEOUT_REQUESTER` or
+    /// `EVENT_REQUESTER` if the state machine made it by itself
+    pub requester: &'static str,
+    pub from: State,
+    pub to: State,
+    pub outcome: AuditOutcome,
+}
+
+/// Which entries of the audit log to return
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum AuditQuery {
+    #[default]
+    All,
+    /// Only requests that were rejected
+    Rejected,
+    /// Only requests from the given board, or `TIMEOUT_REQUESTER` or `EVENT_REQUESTER`
+    Requester(String<20>),
+}
+
+impl AuditQuery {
+    pub fn matches(&self, entry: &AuditEntry) -> bool {
+        match self {
+            AuditQuery::All => true,
+            AuditQuery::Rejected => matches!(entry.outcome, AuditOutcome::Rejected(_)),
+            AuditQuery::Requester(requester) => entry.requester == requester.as_str(),
+        }
+    }
+}
+
+/// Parses `all`, `rejected`, or the name of a requester
+impl FromStr for AuditQuery {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "" => Err("Empty audit query"),
+            "all" => Ok(AuditQuery::All),
+            "rejected" => Ok(AuditQuery::Rejected),
+            requester => String::try_from(requester)
+                .map(AuditQuery::Requester)
+                .map_err(|_| "Requester name is too long"),
+        }
+    }
+}
+
+/// The latest `N` state transitions and requests, for analysis after a run.
+/// Once full, the oldest entry is dropped to make room for each new one.
+pub struct AuditLog<const N: usize> {
+    entries: Deque<AuditEntry, N>,
+    recorded: u32,
+}
+
+impl<const N: usize> Default for AuditLog<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> AuditLog<N> {
+    pub const fn new() -> Self {
+        AuditLog {
+            entries: Deque::new(),
+            recorded: 0,
+        }
+    }
+
+    /// Records a state transition or request, returning the new entry
+    pub fn record(
+        &mut self,
+        timestamp_ms: u64,
+        requester: &'static str,
+        from: State,
+        to: State,
+        outcome: AuditOutcome,
+    ) -> AuditEntry {
+        let entry = AuditEntry {
+            sequence: self.recorded,
+            timestamp_ms,
+            requester,
+            from,
+            to,
+            outcome,
+        };
+        if self.entries.is_full() {
+            self.entries.pop_front();
+        }
+        // There is always room, as the oldest entry was just dropped if the log was full
+        let _ = self.entries.push_back(entry);
+        self.recorded = self.recorded.wrapping_add(1);
+        entry
+    }
+
+    /// The entries matching `query`, oldest first
+    pub fn query<'a>(&'a self, query: &'a AuditQuery) -> impl Iterator<Item = &'a AuditEntry> {
+        self.entries.iter().filter(|entry| query.matches(entry))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::guards::GuardFailure;
+
+    fn sequences<const N: usize>(log: &AuditLog<N>, query: &AuditQuery) -> std::vec::Vec<u32> {
+        log.query(query).map(|entry| entry.sequence).collect()
+    }
+
+    #[test]
+    fn test_oldest_entries_dropped() {
+        let mut log = AuditLog::<2>::new();
+        log.record(
+            10,
+            "mqtt",
+            State::Idle,
+            State::Calibrate,
+            AuditOutcome::Accepted,
+        );
+        log.record(
+            20,
+            TIMEOUT_REQUESTER,
+            State::Calibrate,
+            State::Emergency,
+            AuditOutcome::Accepted,
+        );
+        let entry = log.record(
+            30,
+            "mqtt",
+            State::Emergency,
+            State::Idle,
+            AuditOutcome::Rejected(RejectionReason::InvalidTransition),
+        );
+
+        assert_eq!(entry.sequence, 2);
+        assert_eq!(log.len(), 2);
+        assert_eq!(sequences(&log, &AuditQuery::All), [1, 2]);
+    }
+
+    #[test]
+    fn test_query() {
+        let mut log = AuditLog::<8>::new();
+        log.record(
+            10,
+            "mqtt",
+            State::Idle,
+            State::Calibrate,
+            AuditOutcome::Accepted,
+        );
+        log.record(
+            20,
+            "navigation",
+            State::Calibrate,
+            State::Brake,
+            AuditOutcome::Rejected(RejectionReason::Unauthorised),
+        );
+        log.record(
+            30,
+            EVENT_REQUESTER,
+            State::Calibrate,
+            State::Precharge,
+            AuditOutcome::Accepted,
+        );
+
+        assert_eq!(sequences(&log, &"all".parse().unwrap()), [0, 1, 2]);
+        assert_eq!(sequences(&log, &"rejected".parse().unwrap()), [1]);
+        assert_eq!(sequences(&log, &"navigation".parse().unwrap()), [1]);
+        assert_eq!(sequences(&log, &"event".parse().unwrap()), [2]);
+        assert!("".parse::<AuditQuery>().is_err());
+        assert!("a_board_with_a_very_long_name"
+            .parse::<AuditQuery>()
+            .is_err());
+    }
+
+    #[test]
+    fn test_outcome_code() {
+        let mut failed = FailedGuards::default();
+        failed.insert(GuardFailure::BrakesEngaged);
+        for outcome in [
+            AuditOutcome::Accepted,
+            AuditOutcome::Rejected(RejectionReason::GuardsFailed(failed)),
+            AuditOutcome::Rejected(RejectionReason::ActionFailed),
+        ] {
+            assert_eq!(
+                AuditOutcome::from_code(outcome.code(), outcome.failed_guards()),
+                Ok(outcome)
+            );
+        }
+        assert!(AuditOutcome::from_code(42, failed).is_err());
+    }
+}
@@ -0,0 +1,91 @@
+use crate::states::State;
+use hyped_state_machine_macros::gen_authorisation_rules;
+
+// `configured_rule` gives the boards that may request a transition into each state, as
+// configured in `authorisation` in `config/state_machine.yaml`
+gen_authorisation_rules!("../../config/state_machine.yaml");
+
+/// The configured rule of every state, in the order of `State::ALL`
+const CONFIGURED_RULES: [(State, &str); State::ALL.len()] = {
+    let mut rules = [(State::EMERGENCY, ""); State::ALL.len()];
+    let mut i = 0;
+    while i < rules.len() {
+        rules[i] = (State::ALL[i], configured_rule(State::ALL[i]));
+        i += 1;
+    }
+    rules
+};
+
+/// Which boards may request a transition into each state.
+/// Each rule is a comma separated list of board names, or `*` for any board.
+/// States without a rule can't be requested by any board, except `State::EMERGENCY`,
+/// which can always be requested.
+#[derive(Debug, Clone, Copy)]
+pub struct AuthorisationPolicy<'a> {
+    rules: &'a [(State, &'a str)],
+}
+
+impl Default for AuthorisationPolicy<'static> {
+    fn default() -> Self {
+        Self::from_config()
+    }
+}
+
+impl<'a> AuthorisationPolicy<'a> {
+    pub const fn new(rules: &'a [(State, &'a str)]) -> Self {
+        AuthorisationPolicy { rules }
+    }
+
+    /// Gets the policy configured in `config/state_machine.yaml`
+    pub const fn from_config() -> AuthorisationPolicy<'static> {
+        AuthorisationPolicy::new(&CONFIGURED_RULES)
+    }
+
+    /// Whether the board with the given name may request a transition into `to`
+    pub fn allows(&self, requesting_board: &str, to: State) -> bool {
+        if to == State::EMERGENCY {
+            return true;
+        }
+        self.rules
+            .iter()
+            .filter(|(state, _)| *state == to)
+            .flat_map(|(_, boards)| boards.split(','))
+            .map(str::trim)
+            .any(|board| board == "*" || board == requesting_board)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy() {
+        let policy = AuthorisationPolicy::new(&[
+            (State::Accelerate, "mqtt"),
+            (State::Brake, "mqtt, navigation"),
+            (State::Idle, "*"),
+        ]);
+        assert!(policy.allows("mqtt", State::Accelerate));
+        assert!(!policy.allows("navigation", State::Accelerate));
+        assert!(policy.allows("navigation", State::Brake));
+        assert!(policy.allows("test", State::Idle));
+        // States without a rule are denied
+        assert!(!policy.allows("mqtt", State::Calibrate));
+        assert!(policy.allows("keyence_tester", State::Emergency));
+    }
+
+    #[test]
+    fn test_configured_policy() {
+        let policy = AuthorisationPolicy::from_config();
+        assert!(policy.allows("mqtt", State::Accelerate));
+        assert!(!policy.allows("test", State::Accelerate));
+        assert!(policy.allows("navigation", State::Brake));
+        assert!(policy.allows("mqtt", State::Maintenance));
+        // Every state gets its rule, so new states need no change here
+        assert!(CONFIGURED_RULES
+            .iter()
+            .map(|(state, _)| *state)
+            .eq(State::ALL));
+    }
+}
@@ -0,0 +1,19 @@
+use hyped_core::config::{
+    HEARTBEAT_CONFIG, LEVITATION_CONFIG, LOCALISATION_CONFIG, STATE_MACHINE_CONFIG,
+};
+
+pub const TARGET_HEIGHT_MM: f32 = LEVITATION_CONFIG.target_height_mm as f32;
+pub const LEVITATION_HEIGHT_TOLERANCE_MM: f32 = STATE_MACHINE_CONFIG
+    .thresholds
+    .levitation_height_tolerance_mm as f32;
+pub const LANDED_HEIGHT_MM: f32 = STATE_MACHINE_CONFIG.thresholds.landed_height_mm as f32;
+pub const STOPPED_VELOCITY: f32 = STATE_MACHINE_CONFIG.thresholds.stopped_velocity as f32;
+pub const CHECK_PERIOD_MS: u64 = STATE_MACHINE_CONFIG.check_period_ms as u64;
+pub const REQUEST_TIMEOUT_MS: u64 = STATE_MACHINE_CONFIG.request_timeout_ms as u64;
+pub const AUDIT_LOG_SIZE: usize = STATE_MACHINE_CONFIG.audit_log_size as usize;
+pub const STATE_MACHINE_CANDIDATES: &str = STATE_MACHINE_CONFIG.redundancy.candidates;
+pub const LEADER_TIMEOUT_MS: u64 = STATE_MACHINE_CONFIG.redundancy.leader_timeout_ms as u64;
+pub const RECOVERY_WINDOW_MS: u64 = STATE_MACHINE_CONFIG.redundancy.recovery_window_ms as u64;
+pub const HEARTBEAT_TIMEOUT_MS: u64 = HEARTBEAT_CONFIG.boards.max_latency_ms as u64;
+pub const MAX_MOTOR_FREQUENCY: u32 = STATE_MACHINE_CONFIG.maintenance.max_motor_frequency as u32;
+pub const NUM_ACCELEROMETERS: usize = LOCALISATION_CONFIG.accelerometers.num_sensors as usize;
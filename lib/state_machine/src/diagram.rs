@@ -0,0 +1,83 @@
+use crate::{modes::RunMode, states::State};
+use core::fmt::{self, Write};
+
+/// Writes the transitions of a run mode as a Graphviz digraph, for reviewing changes to
+/// `config/states.yaml`. Render it with e.g. `dot -Tsvg`.
+pub fn write_graphviz(mode: RunMode, writer: &mut impl Write) -> fmt::Result {
+    writeln!(writer, "digraph {} {{", <&str>::from(mode))?;
+    writeln!(
+        writer,
+        "    {} [shape=doublecircle];",
+        <&str>::from(State::Idle)
+    )?;
+    writeln!(
+        writer,
+        "    {} [shape=octagon, label=\"{}\\n(from every state)\"];",
+        <&str>::from(State::EMERGENCY),
+        <&str>::from(State::EMERGENCY)
+    )?;
+    for (from, to) in mode.transitions() {
+        writeln!(
+            writer,
+            "    {} -> {};",
+            <&str>::from(*from),
+            <&str>::from(*to)
+        )?;
+    }
+    writeln!(writer, "}}")
+}
+
+/// Writes the transitions of a run mode as a Mermaid state diagram, which can be embedded in
+/// markdown for review
+pub fn write_mermaid(mode: RunMode, writer: &mut impl Write) -> fmt::Result {
+    writeln!(writer, "stateDiagram-v2")?;
+    writeln!(writer, "    [*] --> {}", <&str>::from(State::Idle))?;
+    for (from, to) in mode.transitions() {
+        writeln!(
+            writer,
+            "    {} --> {}",
+            <&str>::from(*from),
+            <&str>::from(*to)
+        )?;
+    }
+    writeln!(
+        writer,
+        "    note right of {}: Entered from every state",
+        <&str>::from(State::EMERGENCY)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_graphviz() {
+        let mut diagram = String::new();
+        write_graphviz(RunMode::StaticTest, &mut diagram).unwrap();
+        assert_eq!(
+            diagram,
+            "digraph STATIC_TEST {\n    idle [shape=doublecircle];\n    emergency [shape=octagon, label=\"emergency\\n(from every state)\"];\n    idle -> calibrate;\n    calibrate -> precharge;\n    precharge -> stopped;\n    stopped -> idle;\n    idle -> maintenance;\n    maintenance -> idle;\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_mermaid_has_every_transition() {
+        for mode in [
+            RunMode::FullRun,
+            RunMode::LevitationOnly,
+            RunMode::PropulsionOnly,
+            RunMode::StaticTest,
+        ] {
+            let mut diagram = String::new();
+            write_mermaid(mode, &mut diagram).unwrap();
+            assert!(diagram.starts_with("stateDiagram-v2\n"));
+            // One line per transition, plus the header, initial state and emergency note
+            assert_eq!(diagram.lines().count(), mode.transitions().len() + 3);
+            for (from, to) in mode.transitions() {
+                let line = format!("    {} --> {}", <&str>::from(*from), <&str>::from(*to));
+                assert!(diagram.lines().any(|diagram_line| diagram_line == line));
+            }
+        }
+    }
+}
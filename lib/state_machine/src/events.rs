@@ -0,0 +1,153 @@
+use crate::{
+    config::{
+        LANDED_HEIGHT_MM, LEVITATION_HEIGHT_TOLERANCE_MM, STOPPED_VELOCITY, TARGET_HEIGHT_MM,
+    },
+    guards::SystemSnapshot,
+    state_machine::StateMachine,
+    states::State,
+};
+use hyped_core::config::STATE_MACHINE_CONFIG;
+
+/// Something that happens on the pod that can move the state machine on by itself,
+/// without a transition being requested
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum StateMachineEvent {
+    /// Every levitation height sensor reads the target height
+    LevitationHeightReached,
+    /// The pod is no longer moving
+    PodStopped,
+    /// Every levitation height sensor reads that the pod has landed
+    LevitationStopped,
+    /// Heartbeats are missing from at least one monitored board
+    HeartbeatLost,
+    /// The localiser has lost a class of sensors
+    LocalisationDegraded,
+}
+
+impl StateMachineEvent {
+    /// Whether the event can be seen in the snapshot.
+    /// Unknown values never trigger an event.
+    pub fn detected(&self, snapshot: &SystemSnapshot) -> bool {
+        let all_heights = |condition: fn(f32) -> bool| {
+            snapshot
+                .levitation_heights_mm
+                .iter()
+                .all(|height| height.is_some_and(condition))
+        };
+        match self {
+            StateMachineEvent::LevitationHeightReached => all_heights(|height| {
+                (height - TARGET_HEIGHT_MM).abs() <= LEVITATION_HEIGHT_TOLERANCE_MM
+            }),
+            StateMachineEvent::PodStopped => snapshot
+                .velocity
+                .is_some_and(|velocity| velocity.abs() <= STOPPED_VELOCITY),
+            StateMachineEvent::LevitationStopped => {
+                all_heights(|height| height <= LANDED_HEIGHT_MM)
+            }
+            StateMachineEvent::HeartbeatLost => !snapshot.heartbeats_healthy,
+            StateMachineEvent::LocalisationDegraded => snapshot
+                .localisation_mode
+                .is_some_and(|mode| mode.is_degraded()),
+        }
+    }
+}
+
+/// Registers the event transitions in `config/state_machine.yaml`.
+/// Each event is detected in its `from` state and moves the pod on to the state that follows it
+/// in the state machine's mode. Events are skipped if the mode never reaches their `from` state.
+pub fn add_configured_event_transitions(
+    state_machine: &mut StateMachine,
+) -> Result<(), &'static str> {
+    let events = &STATE_MACHINE_CONFIG.events;
+    let mode = state_machine.mode();
+    for (event, from) in [
+        (
+            StateMachineEvent::LevitationHeightReached,
+            events.levitation_height_reached.from,
+        ),
+        (StateMachineEvent::PodStopped, events.pod_stopped.from),
+        (
+            StateMachineEvent::LevitationStopped,
+            events.levitation_stopped.from,
+        ),
+        (StateMachineEvent::HeartbeatLost, events.heartbeat_lost.from),
+        (
+            StateMachineEvent::LocalisationDegraded,
+            events.localisation_degraded.from,
+        ),
+    ] {
+        let from = from.parse::<State>()?;
+        if let Some(to) = mode.next_state(from) {
+            state_machine.add_event_transition(event, from, to)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modes::RunMode;
+    use hyped_core::types::LocalisationMode;
+
+    #[test]
+    fn test_levitation_height_reached() {
+        let mut snapshot = SystemSnapshot::new();
+        snapshot.levitation_heights_mm = [Some(TARGET_HEIGHT_MM); 4];
+        snapshot.levitation_heights_mm[3] = None;
+        assert!(!StateMachineEvent::LevitationHeightReached.detected(&snapshot));
+        snapshot.levitation_heights_mm[3] = Some(TARGET_HEIGHT_MM + LEVITATION_HEIGHT_TOLERANCE_MM);
+        assert!(StateMachineEvent::LevitationHeightReached.detected(&snapshot));
+        assert!(!StateMachineEvent::LevitationStopped.detected(&snapshot));
+    }
+
+    #[test]
+    fn test_pod_stopped() {
+        let mut snapshot = SystemSnapshot::new();
+        assert!(!StateMachineEvent::PodStopped.detected(&snapshot));
+        snapshot.velocity = Some(-STOPPED_VELOCITY / 2.0);
+        assert!(StateMachineEvent::PodStopped.detected(&snapshot));
+        snapshot.velocity = Some(1.0);
+        assert!(!StateMachineEvent::PodStopped.detected(&snapshot));
+    }
+
+    #[test]
+    fn test_configured_event_transitions_follow_mode() {
+        let mut state_machine = StateMachine::new(RunMode::PropulsionOnly);
+        add_configured_event_transitions(&mut state_machine).unwrap();
+        state_machine.current_state = State::Brake;
+        let snapshot = SystemSnapshot {
+            velocity: Some(0.0),
+            ..Default::default()
+        };
+        assert_eq!(state_machine.handle_events(&snapshot), Some(State::Stopped));
+    }
+
+    #[test]
+    fn test_heartbeat_lost_leaves_maintenance() {
+        let mut state_machine = StateMachine::new(RunMode::FullRun);
+        add_configured_event_transitions(&mut state_machine).unwrap();
+        state_machine.current_state = State::Maintenance;
+        let mut snapshot = SystemSnapshot {
+            heartbeats_healthy: true,
+            ..Default::default()
+        };
+        assert_eq!(state_machine.handle_events(&snapshot), None);
+        snapshot.heartbeats_healthy = false;
+        assert_eq!(state_machine.handle_events(&snapshot), Some(State::Idle));
+    }
+
+    #[test]
+    fn test_localisation_degraded_brakes() {
+        let mut state_machine = StateMachine::new(RunMode::FullRun);
+        add_configured_event_transitions(&mut state_machine).unwrap();
+        state_machine.current_state = State::Accelerate;
+        let mut snapshot = SystemSnapshot {
+            localisation_mode: Some(LocalisationMode::Nominal),
+            ..Default::default()
+        };
+        assert_eq!(state_machine.handle_events(&snapshot), None);
+        snapshot.localisation_mode = Some(LocalisationMode::NoAccelerometers);
+        assert_eq!(state_machine.handle_events(&snapshot), Some(State::Brake));
+    }
+}
@@ -0,0 +1,529 @@
+use crate::{
+    config::NUM_ACCELEROMETERS, modes::RunMode, state_machine::StateMachine, states::State,
+};
+use hyped_core::{
+    config::MeasurementId, measurements::MeasurementRange, mqtt_payload::MeasurementValue,
+    types::LocalisationMode,
+};
+
+/// Number of levitation height sensors
+pub const NUM_LEVITATION_HEIGHTS: usize = 4;
+
+/// The state of the pod's systems, used by guards to decide whether a transition is safe.
+/// Unknown values are treated as unsafe by the default guards.
+#[derive(Debug, Clone, Copy, PartialEq, defmt::Format)]
+pub struct SystemSnapshot {
+    /// Whether the brakes are clamped
+    pub brakes_engaged: Option<bool>,
+    /// Latest pressure of the brakes reservoir, in bar
+    pub brake_reservoir_pressure: Option<f32>,
+    /// Latest pressure of the active suspension reservoir, in bar
+    pub suspension_reservoir_pressure: Option<f32>,
+    /// Whether heartbeats are being received from all monitored boards
+    pub heartbeats_healthy: bool,
+    /// Whether the latest displacement and velocity from the localiser are both valid
+    pub localisation_valid: bool,
+    /// Sensors the latest localisation estimate is based on
+    pub localisation_mode: Option<LocalisationMode>,
+    /// Latest displacement of the pod along the track, in m
+    pub displacement: Option<f32>,
+    /// Latest velocity of the pod, in m/s
+    pub velocity: Option<f32>,
+    /// Latest reading of each levitation height sensor, in mm
+    pub levitation_heights_mm: [Option<f32>; NUM_LEVITATION_HEIGHTS],
+    /// Whether each accelerometer passed its latest calibration
+    pub accelerometers_calibrated: [Option<bool>; NUM_ACCELEROMETERS],
+}
+
+impl Default for SystemSnapshot {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SystemSnapshot {
+    /// Creates a snapshot where nothing is known yet
+    pub const fn new() -> Self {
+        SystemSnapshot {
+            brakes_engaged: None,
+            brake_reservoir_pressure: None,
+            suspension_reservoir_pressure: None,
+            heartbeats_healthy: false,
+            localisation_valid: false,
+            localisation_mode: None,
+            displacement: None,
+            velocity: None,
+            levitation_heights_mm: [None; NUM_LEVITATION_HEIGHTS],
+            accelerometers_calibrated: [None; NUM_ACCELEROMETERS],
+        }
+    }
+
+    /// Records the result of calibrating an accelerometer, ignoring accelerometers that don't
+    /// exist
+    pub fn record_calibration(&mut self, accelerometer: usize, passed: bool) {
+        if let Some(calibrated) = self.accelerometers_calibrated.get_mut(accelerometer) {
+            *calibrated = Some(passed);
+        }
+    }
+
+    /// Updates the snapshot with a measurement reading, ignoring measurements it doesn't track
+    pub fn update(&mut self, measurement_id: MeasurementId, value: MeasurementValue) {
+        match (measurement_id, value) {
+            (MeasurementId::BrakeClampStatus, MeasurementValue::Bool(clamped)) => {
+                self.brakes_engaged = Some(clamped)
+            }
+            (MeasurementId::BrakeClampStatus, MeasurementValue::Integer(clamped)) => {
+                self.brakes_engaged = Some(clamped == 1)
+            }
+            (MeasurementId::PressureBrakesReservoir, value) => {
+                self.brake_reservoir_pressure = value.as_f32()
+            }
+            (MeasurementId::PressureActiveSuspensionReservoir, value) => {
+                self.suspension_reservoir_pressure = value.as_f32()
+            }
+            (MeasurementId::LevitationHeight1, value) => {
+                self.levitation_heights_mm[0] = value.as_f32()
+            }
+            (MeasurementId::LevitationHeight2, value) => {
+                self.levitation_heights_mm[1] = value.as_f32()
+            }
+            (MeasurementId::LevitationHeight3, value) => {
+                self.levitation_heights_mm[2] = value.as_f32()
+            }
+            (MeasurementId::LevitationHeight4, value) => {
+                self.levitation_heights_mm[3] = value.as_f32()
+            }
+            (MeasurementId::LocalisationMode, value) => {
+                self.localisation_mode = value
+                    .as_f32()
+                    .and_then(|mode| LocalisationMode::try_from(mode as u8).ok())
+            }
+            (MeasurementId::Displacement, value) => {
+                self.displacement = value.as_f32();
+                self.localisation_valid = self.localisation_estimate_valid();
+            }
+            (MeasurementId::Velocity, value) => {
+                self.velocity = value.as_f32();
+                self.localisation_valid = self.localisation_estimate_valid();
+            }
+            _ => {}
+        }
+    }
+
+    /// Whether both the displacement and velocity are known, and within their critical limits.
+    /// The localiser sends NaN for both while its estimate is invalid.
+    fn localisation_estimate_valid(&self) -> bool {
+        [
+            (MeasurementId::Displacement, self.displacement),
+            (MeasurementId::Velocity, self.velocity),
+        ]
+        .into_iter()
+        .all(|(measurement_id, value)| {
+            value.is_some_and(|value| {
+                !value.is_nan()
+                    && measurement_id.limits().classify(value) != MeasurementRange::Critical
+            })
+        })
+    }
+}
+
+/// Why a guard prevented a transition
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+#[repr(u8)]
+pub enum GuardFailure {
+    /// The brakes must be released, but are engaged or their state is unknown
+    BrakesEngaged = 0,
+    /// The brakes must be engaged, but are released or their state is unknown
+    BrakesReleased = 1,
+    /// A reservoir pressure is critical or unknown
+    PressureOutOfRange = 2,
+    /// Heartbeats are missing from at least one board
+    HeartbeatUnhealthy = 3,
+    /// The pod does not know where it is
+    LocalisationInvalid = 4,
+    /// At least one accelerometer failed calibration or hasn't been calibrated
+    NotCalibrated = 5,
+    /// The localiser has lost a class of sensors, or its mode is unknown
+    LocalisationDegraded = 6,
+}
+
+impl GuardFailure {
+    /// Number of different guard failures
+    pub const COUNT: usize = 7;
+
+    const ALL: [GuardFailure; Self::COUNT] = [
+        GuardFailure::BrakesEngaged,
+        GuardFailure::BrakesReleased,
+        GuardFailure::PressureOutOfRange,
+        GuardFailure::HeartbeatUnhealthy,
+        GuardFailure::LocalisationInvalid,
+        GuardFailure::NotCalibrated,
+        GuardFailure::LocalisationDegraded,
+    ];
+}
+
+impl From<GuardFailure> for &str {
+    fn from(val: GuardFailure) -> Self {
+        match val {
+            GuardFailure::BrakesEngaged => "brakes_engaged",
+            GuardFailure::BrakesReleased => "brakes_released",
+            GuardFailure::PressureOutOfRange => "pressure_out_of_range",
+            GuardFailure::HeartbeatUnhealthy => "heartbeat_unhealthy",
+            GuardFailure::LocalisationInvalid => "localisation_invalid",
+            GuardFailure::NotCalibrated => "not_calibrated",
+            GuardFailure::LocalisationDegraded => "localisation_degraded",
+        }
+    }
+}
+
+/// The set of guards that failed for a transition, stored as one bit per `GuardFailure`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, defmt::Format)]
+pub struct FailedGuards(u16);
+
+impl FailedGuards {
+    pub fn insert(&mut self, failure: GuardFailure) {
+        self.0 |= 1 << failure as u8;
+    }
+
+    pub fn contains(&self, failure: GuardFailure) -> bool {
+        self.0 & (1 << failure as u8) != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Iterates over the failed guards, in the order they are declared in `GuardFailure`
+    pub fn iter(&self) -> impl Iterator<Item = GuardFailure> + '_ {
+        GuardFailure::ALL
+            .into_iter()
+            .filter(|failure| self.contains(*failure))
+    }
+}
+
+impl From<FailedGuards> for u16 {
+    fn from(val: FailedGuards) -> Self {
+        val.0
+    }
+}
+
+impl From<u16> for FailedGuards {
+    fn from(bits: u16) -> Self {
+        FailedGuards(bits)
+    }
+}
+
+/// A precondition on a state transition.
+/// Implemented for any `Fn(&SystemSnapshot) -> Result<(), GuardFailure>`, so closures and
+/// functions can be used as guards.
+pub trait TransitionGuard {
+    /// Returns the reason the transition must not happen, if any
+    fn check(&self, snapshot: &SystemSnapshot) -> Result<(), GuardFailure>;
+}
+
+impl<F> TransitionGuard for F
+where
+    F: Fn(&SystemSnapshot) -> Result<(), GuardFailure>,
+{
+    fn check(&self, snapshot: &SystemSnapshot) -> Result<(), GuardFailure> {
+        self(snapshot)
+    }
+}
+
+/// The brakes must be released, e.g. before accelerating
+pub fn brakes_released(snapshot: &SystemSnapshot) -> Result<(), GuardFailure> {
+    match snapshot.brakes_engaged {
+        Some(false) => Ok(()),
+        _ => Err(GuardFailure::BrakesEngaged),
+    }
+}
+
+/// The brakes must be engaged
+pub fn brakes_engaged(snapshot: &SystemSnapshot) -> Result<(), GuardFailure> {
+    match snapshot.brakes_engaged {
+        Some(true) => Ok(()),
+        _ => Err(GuardFailure::BrakesReleased),
+    }
+}
+
+/// Both reservoir pressures must be known and within their critical limits
+pub fn pressures_nominal(snapshot: &SystemSnapshot) -> Result<(), GuardFailure> {
+    let in_range = |measurement_id: MeasurementId, pressure: Option<f32>| {
+        pressure.is_some_and(|pressure| {
+            !pressure.is_nan()
+                && measurement_id.limits().classify(pressure) != MeasurementRange::Critical
+        })
+    };
+    if in_range(
+        MeasurementId::PressureBrakesReservoir,
+        snapshot.brake_reservoir_pressure,
+    ) && in_range(
+        MeasurementId::PressureActiveSuspensionReservoir,
+        snapshot.suspension_reservoir_pressure,
+    ) {
+        Ok(())
+    } else {
+        Err(GuardFailure::PressureOutOfRange)
+    }
+}
+
+/// All monitored boards must be sending heartbeats
+pub fn heartbeats_healthy(snapshot: &SystemSnapshot) -> Result<(), GuardFailure> {
+    match snapshot.heartbeats_healthy {
+        true => Ok(()),
+        false => Err(GuardFailure::HeartbeatUnhealthy),
+    }
+}
+
+/// The localisation estimate must be valid
+pub fn localisation_valid(snapshot: &SystemSnapshot) -> Result<(), GuardFailure> {
+    match snapshot.localisation_valid {
+        true => Ok(()),
+        false => Err(GuardFailure::LocalisationInvalid),
+    }
+}
+
+/// The localiser must be using every class of sensor
+pub fn localisation_nominal(snapshot: &SystemSnapshot) -> Result<(), GuardFailure> {
+    match snapshot.localisation_mode {
+        Some(LocalisationMode::Nominal) => Ok(()),
+        _ => Err(GuardFailure::LocalisationDegraded),
+    }
+}
+
+/// Every accelerometer must have passed calibration
+pub fn accelerometers_calibrated(snapshot: &SystemSnapshot) -> Result<(), GuardFailure> {
+    match snapshot
+        .accelerometers_calibrated
+        .iter()
+        .all(|calibrated| *calibrated == Some(true))
+    {
+        true => Ok(()),
+        false => Err(GuardFailure::NotCalibrated),
+    }
+}
+
+/// Registers the guards used on the pod for the state machine's mode
+pub fn add_default_guards(state_machine: &mut StateMachine) -> Result<(), &'static str> {
+    // The pod leaves maintenance when heartbeats are lost, so it must not enter without them
+    state_machine.add_guard(State::Idle, State::Maintenance, &heartbeats_healthy)?;
+    state_machine.add_guard(State::Calibrate, State::Precharge, &heartbeats_healthy)?;
+    state_machine.add_guard(State::Calibrate, State::Precharge, &pressures_nominal)?;
+    match state_machine.mode() {
+        RunMode::FullRun => {
+            add_calibration_guards(state_machine)?;
+            add_before_levitation_guards(state_machine)?;
+            add_before_acceleration_guards(state_machine)?;
+        }
+        RunMode::LevitationOnly => {
+            add_before_levitation_guards(state_machine)?;
+            state_machine.add_guard(
+                State::ReadyForLevitation,
+                State::BeginLevitation,
+                &heartbeats_healthy,
+            )?;
+            state_machine.add_guard(
+                State::ReadyForLevitation,
+                State::BeginLevitation,
+                &pressures_nominal,
+            )?;
+        }
+        RunMode::PropulsionOnly => {
+            add_calibration_guards(state_machine)?;
+            state_machine.add_guard(State::Precharge, State::Ready, &heartbeats_healthy)?;
+            state_machine.add_guard(State::Precharge, State::Ready, &pressures_nominal)?;
+            add_before_acceleration_guards(state_machine)?;
+        }
+        RunMode::StaticTest => {
+            // The pod must not move on the stand
+            state_machine.add_guard(State::Calibrate, State::Precharge, &brakes_engaged)?;
+        }
+    }
+    Ok(())
+}
+
+/// The pod is localised with the accelerometers in the modes where it moves along the track
+fn add_calibration_guards(state_machine: &mut StateMachine) -> Result<(), &'static str> {
+    state_machine.add_guard(
+        State::Calibrate,
+        State::Precharge,
+        &accelerometers_calibrated,
+    )
+}
+
+fn add_before_levitation_guards(state_machine: &mut StateMachine) -> Result<(), &'static str> {
+    state_machine.add_guard(
+        State::Precharge,
+        State::ReadyForLevitation,
+        &heartbeats_healthy,
+    )?;
+    state_machine.add_guard(
+        State::Precharge,
+        State::ReadyForLevitation,
+        &pressures_nominal,
+    )
+}
+
+/// The localisation guards rely on the estimate the navigation board's localiser sends over CAN
+fn add_before_acceleration_guards(state_machine: &mut StateMachine) -> Result<(), &'static str> {
+    state_machine.add_guard(State::Ready, State::Accelerate, &heartbeats_healthy)?;
+    state_machine.add_guard(State::Ready, State::Accelerate, &pressures_nominal)?;
+    state_machine.add_guard(State::Ready, State::Accelerate, &brakes_released)?;
+    state_machine.add_guard(State::Ready, State::Accelerate, &localisation_valid)?;
+    state_machine.add_guard(State::Ready, State::Accelerate, &localisation_nominal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_failed_guards() {
+        let mut failed = FailedGuards::default();
+        assert!(failed.is_empty());
+        failed.insert(GuardFailure::LocalisationInvalid);
+        failed.insert(GuardFailure::BrakesEngaged);
+        assert!(failed.contains(GuardFailure::BrakesEngaged));
+        assert!(!failed.contains(GuardFailure::BrakesReleased));
+        let mut iter = failed.iter();
+        assert_eq!(iter.next(), Some(GuardFailure::BrakesEngaged));
+        assert_eq!(iter.next(), Some(GuardFailure::LocalisationInvalid));
+        assert_eq!(iter.next(), None);
+        assert_eq!(FailedGuards::from(u16::from(failed)), failed);
+    }
+
+    #[test]
+    fn test_snapshot_update() {
+        let mut snapshot = SystemSnapshot::default();
+        snapshot.update(
+            MeasurementId::BrakeClampStatus,
+            MeasurementValue::Integer(1),
+        );
+        snapshot.update(
+            MeasurementId::PressureBrakesReservoir,
+            MeasurementValue::Float(5.0),
+        );
+        snapshot.update(MeasurementId::Velocity, MeasurementValue::Float(-1.0));
+        assert_eq!(snapshot.brakes_engaged, Some(true));
+        assert_eq!(snapshot.brake_reservoir_pressure, Some(5.0));
+        assert!(!snapshot.localisation_valid);
+
+        snapshot.update(
+            MeasurementId::LocalisationMode,
+            MeasurementValue::Float(1.0),
+        );
+        assert_eq!(
+            snapshot.localisation_mode,
+            Some(LocalisationMode::DeadReckoning)
+        );
+        assert_eq!(
+            localisation_nominal(&snapshot),
+            Err(GuardFailure::LocalisationDegraded)
+        );
+        snapshot.update(
+            MeasurementId::LocalisationMode,
+            MeasurementValue::Integer(0),
+        );
+        assert_eq!(localisation_nominal(&snapshot), Ok(()));
+    }
+
+    #[test]
+    fn test_localisation_guards_from_localiser() {
+        // Measurements in the order the navigation board's localiser sends them
+        let update = |snapshot: &mut SystemSnapshot, values: [f32; 4]| {
+            for (measurement_id, value) in [
+                MeasurementId::Displacement,
+                MeasurementId::Velocity,
+                MeasurementId::Acceleration,
+                MeasurementId::LocalisationMode,
+            ]
+            .into_iter()
+            .zip(values)
+            {
+                snapshot.update(measurement_id, MeasurementValue::Float(value));
+            }
+        };
+        let mut snapshot = SystemSnapshot::default();
+        assert_eq!(
+            localisation_valid(&snapshot),
+            Err(GuardFailure::LocalisationInvalid)
+        );
+
+        update(&mut snapshot, [0.0, 0.0, 0.0, 0.0]);
+        assert_eq!(localisation_valid(&snapshot), Ok(()));
+        assert_eq!(localisation_nominal(&snapshot), Ok(()));
+
+        // An invalid estimate is sent as NaN
+        update(&mut snapshot, [f32::NAN, f32::NAN, f32::NAN, 2.0]);
+        assert_eq!(
+            localisation_valid(&snapshot),
+            Err(GuardFailure::LocalisationInvalid)
+        );
+        assert_eq!(
+            snapshot.localisation_mode,
+            Some(LocalisationMode::NoAccelerometers)
+        );
+        assert_eq!(
+            localisation_nominal(&snapshot),
+            Err(GuardFailure::LocalisationDegraded)
+        );
+
+        // A critical displacement isn't hidden by a valid velocity sent after it
+        update(&mut snapshot, [150.0, 10.0, 0.0, 0.0]);
+        assert_eq!(
+            localisation_valid(&snapshot),
+            Err(GuardFailure::LocalisationInvalid)
+        );
+    }
+
+    #[test]
+    fn test_pressures_nominal() {
+        let mut snapshot = SystemSnapshot {
+            brake_reservoir_pressure: Some(5.0),
+            ..Default::default()
+        };
+        assert_eq!(
+            pressures_nominal(&snapshot),
+            Err(GuardFailure::PressureOutOfRange)
+        );
+        snapshot.suspension_reservoir_pressure = Some(5.0);
+        assert_eq!(pressures_nominal(&snapshot), Ok(()));
+        snapshot.brake_reservoir_pressure = Some(8.0);
+        assert_eq!(
+            pressures_nominal(&snapshot),
+            Err(GuardFailure::PressureOutOfRange)
+        );
+    }
+
+    #[test]
+    fn test_accelerometers_calibrated() {
+        let mut snapshot = SystemSnapshot::default();
+        assert_eq!(
+            accelerometers_calibrated(&snapshot),
+            Err(GuardFailure::NotCalibrated)
+        );
+        for accelerometer in 0..NUM_ACCELEROMETERS {
+            snapshot.record_calibration(accelerometer, true);
+        }
+        snapshot.record_calibration(NUM_ACCELEROMETERS, false);
+        assert_eq!(accelerometers_calibrated(&snapshot), Ok(()));
+        snapshot.record_calibration(1, false);
+        assert_eq!(
+            accelerometers_calibrated(&snapshot),
+            Err(GuardFailure::NotCalibrated)
+        );
+    }
+
+    #[test]
+    fn test_default_guards_for_every_mode() {
+        for mode in [
+            RunMode::FullRun,
+            RunMode::LevitationOnly,
+            RunMode::PropulsionOnly,
+            RunMode::StaticTest,
+        ] {
+            let mut state_machine = StateMachine::new(mode);
+            assert_eq!(add_default_guards(&mut state_machine), Ok(()), "{:?}", mode);
+        }
+    }
+}
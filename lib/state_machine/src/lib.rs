@@ -1,4 +1,15 @@
 #![cfg_attr(not(test), no_std)]
 
+pub mod actions;
+pub mod audit;
+pub mod authorisation;
+pub mod config;
+pub mod diagram;
+pub mod events;
+pub mod guards;
+pub mod maintenance;
+pub mod modes;
+pub mod redundancy;
 pub mod state_machine;
 pub mod states;
+pub mod timeouts;
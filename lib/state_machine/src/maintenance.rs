@@ -0,0 +1,274 @@
+use crate::{
+    actions::ActionError, config::MAX_MOTOR_FREQUENCY, guards::SystemSnapshot, states::State,
+};
+use core::{future::Future, str::FromStr};
+
+/// A command to move a single actuator, sent by the base station in `State::Maintenance`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum ActuatorCommand {
+    EngageBrakes,
+    DisengageBrakes,
+    DeployLateralSuspension,
+    RetractLateralSuspension,
+    SwitchOnHighPowerRelay,
+    SwitchOffHighPowerRelay,
+    /// Runs the motors at the given frequency in Hz, or stops them if it is 0
+    SetMotorFrequency(u32),
+}
+
+impl ActuatorCommand {
+    /// Commands that leave every actuator safe. Each board carries out those for its own
+    /// actuators, in order, when the pod leaves maintenance.
+    pub const SAFE: [ActuatorCommand; 4] = [
+        ActuatorCommand::SetMotorFrequency(0),
+        ActuatorCommand::SwitchOffHighPowerRelay,
+        ActuatorCommand::EngageBrakes,
+        ActuatorCommand::RetractLateralSuspension,
+    ];
+
+    /// Whether the command can only make the pod safer, so is carried out in any state
+    pub fn is_safe(&self) -> bool {
+        Self::SAFE.contains(self)
+    }
+
+    /// Identifies the command on the CAN bus
+    pub fn code(&self) -> u8 {
+        match self {
+            ActuatorCommand::EngageBrakes => 0,
+            ActuatorCommand::DisengageBrakes => 1,
+            ActuatorCommand::DeployLateralSuspension => 2,
+            ActuatorCommand::RetractLateralSuspension => 3,
+            ActuatorCommand::SwitchOnHighPowerRelay => 4,
+            ActuatorCommand::SwitchOffHighPowerRelay => 5,
+            ActuatorCommand::SetMotorFrequency(_) => 6,
+        }
+    }
+
+    /// The value sent with the command on the CAN bus, which is 0 for commands without one
+    pub fn argument(&self) -> u32 {
+        match self {
+            ActuatorCommand::SetMotorFrequency(frequency) => *frequency,
+            _ => 0,
+        }
+    }
+
+    /// Reverses `code` and `argument`
+    pub fn from_parts(code: u8, argument: u32) -> Result<Self, &'static str> {
+        match code {
+            0 => Ok(ActuatorCommand::EngageBrakes),
+            1 => Ok(ActuatorCommand::DisengageBrakes),
+            2 => Ok(ActuatorCommand::DeployLateralSuspension),
+            3 => Ok(ActuatorCommand::RetractLateralSuspension),
+            4 => Ok(ActuatorCommand::SwitchOnHighPowerRelay),
+            5 => Ok(ActuatorCommand::SwitchOffHighPowerRelay),
+            6 => Ok(ActuatorCommand::SetMotorFrequency(argument)),
+            _ => Err("Invalid actuator command"),
+        }
+    }
+}
+
+/// Uses the names sent by the base station, without the motor frequency
+impl From<ActuatorCommand> for &str {
+    fn from(command: ActuatorCommand) -> Self {
+        match command {
+            ActuatorCommand::EngageBrakes => "engage_brakes",
+            ActuatorCommand::DisengageBrakes => "disengage_brakes",
+            ActuatorCommand::DeployLateralSuspension => "deploy_lateral_suspension",
+            ActuatorCommand::RetractLateralSuspension => "retract_lateral_suspension",
+            ActuatorCommand::SwitchOnHighPowerRelay => "switch_on_hp_relay",
+            ActuatorCommand::SwitchOffHighPowerRelay => "switch_off_hp_relay",
+            ActuatorCommand::SetMotorFrequency(_) => "motor_frequency",
+        }
+    }
+}
+
+/// Parses a command name, or `motor_frequency:<Hz>` to set the motor frequency
+impl FromStr for ActuatorCommand {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(frequency) = s.strip_prefix("motor_frequency:") {
+            return frequency
+                .trim()
+                .parse()
+                .map(ActuatorCommand::SetMotorFrequency)
+                .map_err(|_| "Invalid motor frequency");
+        }
+        match s {
+            "engage_brakes" => Ok(ActuatorCommand::EngageBrakes),
+            "disengage_brakes" => Ok(ActuatorCommand::DisengageBrakes),
+            "deploy_lateral_suspension" => Ok(ActuatorCommand::DeployLateralSuspension),
+            "retract_lateral_suspension" => Ok(ActuatorCommand::RetractLateralSuspension),
+            "switch_on_hp_relay" => Ok(ActuatorCommand::SwitchOnHighPowerRelay),
+            "switch_off_hp_relay" => Ok(ActuatorCommand::SwitchOffHighPowerRelay),
+            _ => Err("Invalid actuator command"),
+        }
+    }
+}
+
+/// The actuators of a board that can be commanded in maintenance.
+/// Boards ignore commands for actuators they don't have.
+pub trait Actuators {
+    fn actuate(
+        &mut self,
+        command: ActuatorCommand,
+    ) -> impl Future<Output = Result<(), ActionError>>;
+}
+
+/// Why an actuator command was refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Interlock {
+    /// Actuators can only be commanded in `State::Maintenance`
+    NotInMaintenance,
+    /// The motors can only run while the brakes are known to be engaged
+    BrakesReleased,
+    /// The brakes can't be released while the motors are running
+    MotorRunning,
+    /// The motor frequency is above `maintenance.max_motor_frequency` in
+    /// `config/state_machine.yaml`
+    FrequencyTooHigh,
+}
+
+impl From<Interlock> for &str {
+    fn from(interlock: Interlock) -> Self {
+        match interlock {
+            Interlock::NotInMaintenance => "not_in_maintenance",
+            Interlock::BrakesReleased => "brakes_released",
+            Interlock::MotorRunning => "motor_running",
+            Interlock::FrequencyTooHigh => "frequency_too_high",
+        }
+    }
+}
+
+/// Checks actuator commands against the safety interlocks, keeping track of the commands sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, defmt::Format)]
+pub struct MaintenanceInterlocks {
+    /// Latest motor frequency commanded, in Hz
+    motor_frequency: u32,
+}
+
+impl MaintenanceInterlocks {
+    pub const fn new() -> Self {
+        MaintenanceInterlocks { motor_frequency: 0 }
+    }
+
+    /// Returns the interlock preventing `command`, if any
+    pub fn check(
+        &self,
+        state: State,
+        snapshot: &SystemSnapshot,
+        command: ActuatorCommand,
+    ) -> Result<(), Interlock> {
+        if state != State::Maintenance {
+            return Err(Interlock::NotInMaintenance);
+        }
+        match command {
+            ActuatorCommand::SetMotorFrequency(frequency) if frequency > MAX_MOTOR_FREQUENCY => {
+                Err(Interlock::FrequencyTooHigh)
+            }
+            ActuatorCommand::SetMotorFrequency(frequency)
+                if frequency > 0 && snapshot.brakes_engaged != Some(true) =>
+            {
+                Err(Interlock::BrakesReleased)
+            }
+            ActuatorCommand::DisengageBrakes if self.motor_frequency > 0 => {
+                Err(Interlock::MotorRunning)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Records a command that has been sent
+    pub fn apply(&mut self, command: ActuatorCommand) {
+        if let ActuatorCommand::SetMotorFrequency(frequency) = command {
+            self.motor_frequency = frequency;
+        }
+    }
+
+    /// Forgets the commands sent, once the actuators have been made safe
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_conversions() {
+        let commands = [
+            ActuatorCommand::EngageBrakes,
+            ActuatorCommand::DisengageBrakes,
+            ActuatorCommand::DeployLateralSuspension,
+            ActuatorCommand::RetractLateralSuspension,
+            ActuatorCommand::SwitchOnHighPowerRelay,
+            ActuatorCommand::SwitchOffHighPowerRelay,
+            ActuatorCommand::SetMotorFrequency(20),
+        ];
+        for command in commands {
+            assert_eq!(
+                ActuatorCommand::from_parts(command.code(), command.argument()),
+                Ok(command)
+            );
+        }
+        assert_eq!("engage_brakes".parse(), Ok(ActuatorCommand::EngageBrakes));
+        assert_eq!(
+            "motor_frequency:20".parse(),
+            Ok(ActuatorCommand::SetMotorFrequency(20))
+        );
+        assert!("motor_frequency:fast".parse::<ActuatorCommand>().is_err());
+        assert!(ActuatorCommand::from_parts(7, 0).is_err());
+        assert!(ActuatorCommand::SetMotorFrequency(0).is_safe());
+        assert!(!ActuatorCommand::SetMotorFrequency(20).is_safe());
+    }
+
+    #[test]
+    fn test_interlocks() {
+        let mut interlocks = MaintenanceInterlocks::new();
+        let mut snapshot = SystemSnapshot::new();
+        let run_motor = ActuatorCommand::SetMotorFrequency(MAX_MOTOR_FREQUENCY);
+
+        assert_eq!(
+            interlocks.check(State::Idle, &snapshot, ActuatorCommand::EngageBrakes),
+            Err(Interlock::NotInMaintenance)
+        );
+        assert_eq!(
+            interlocks.check(State::Maintenance, &snapshot, run_motor),
+            Err(Interlock::BrakesReleased)
+        );
+        assert_eq!(
+            interlocks.check(
+                State::Maintenance,
+                &snapshot,
+                ActuatorCommand::SetMotorFrequency(MAX_MOTOR_FREQUENCY + 1)
+            ),
+            Err(Interlock::FrequencyTooHigh)
+        );
+
+        snapshot.brakes_engaged = Some(true);
+        assert_eq!(
+            interlocks.check(State::Maintenance, &snapshot, run_motor),
+            Ok(())
+        );
+        interlocks.apply(run_motor);
+        assert_eq!(
+            interlocks.check(
+                State::Maintenance,
+                &snapshot,
+                ActuatorCommand::DisengageBrakes
+            ),
+            Err(Interlock::MotorRunning)
+        );
+
+        interlocks.apply(ActuatorCommand::SetMotorFrequency(0));
+        assert_eq!(
+            interlocks.check(
+                State::Maintenance,
+                &snapshot,
+                ActuatorCommand::DisengageBrakes
+            ),
+            Ok(())
+        );
+    }
+}
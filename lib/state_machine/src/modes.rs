@@ -0,0 +1,156 @@
+use crate::states::{
+    State, FULL_RUN_TRANSITIONS, LEVITATION_ONLY_TRANSITIONS, PROPULSION_ONLY_TRANSITIONS,
+    STATIC_TEST_TRANSITIONS,
+};
+use core::str::FromStr;
+use hyped_core::config::PODS_CONFIG;
+
+/// What the pod is being run for, which decides the states it goes through.
+/// Configured with `mode` in `config/pods.yaml`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, defmt::Format)]
+#[repr(u8)]
+pub enum RunMode {
+    /// Levitate and propel the pod along the track
+    #[default]
+    FullRun = 0,
+    /// Levitate the pod without propelling it
+    LevitationOnly = 1,
+    /// Propel the pod without levitating it
+    PropulsionOnly = 2,
+    /// Power up the pod's systems on a stand, without levitating or moving
+    StaticTest = 3,
+}
+
+impl RunMode {
+    /// Gets the mode configured in `config/pods.yaml`
+    pub fn from_config() -> Result<Self, &'static str> {
+        PODS_CONFIG.pods.poddington.mode.parse()
+    }
+
+    /// The transitions allowed in this mode, as defined in `config/states.yaml`.
+    /// Excludes transitions into `State::EMERGENCY`, which is allowed from every state.
+    pub fn transitions(&self) -> &'static [(State, State)] {
+        match self {
+            RunMode::FullRun => &FULL_RUN_TRANSITIONS,
+            RunMode::LevitationOnly => &LEVITATION_ONLY_TRANSITIONS,
+            RunMode::PropulsionOnly => &PROPULSION_ONLY_TRANSITIONS,
+            RunMode::StaticTest => &STATIC_TEST_TRANSITIONS,
+        }
+    }
+
+    /// Whether the pod can transition from one state to another in this mode
+    pub fn allows(&self, from: State, to: State) -> bool {
+        to == State::EMERGENCY || self.transitions().contains(&(from, to))
+    }
+
+    /// Gets the state that follows `state` in this mode, ignoring emergencies
+    pub fn next_state(&self, state: State) -> Option<State> {
+        self.transitions()
+            .iter()
+            .find(|(from, _)| *from == state)
+            .map(|(_, to)| *to)
+    }
+
+    /// Whether the pod can ever be in `state` in this mode
+    pub fn has_state(&self, state: State) -> bool {
+        state == State::EMERGENCY || self.next_state(state).is_some()
+    }
+}
+
+impl From<RunMode> for u8 {
+    fn from(mode: RunMode) -> Self {
+        mode as u8
+    }
+}
+
+impl TryFrom<u8> for RunMode {
+    type Error = &'static str;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(RunMode::FullRun),
+            1 => Ok(RunMode::LevitationOnly),
+            2 => Ok(RunMode::PropulsionOnly),
+            3 => Ok(RunMode::StaticTest),
+            _ => Err("Invalid run mode"),
+        }
+    }
+}
+
+/// Uses the same names as `config/pods.yaml` and the base station
+impl From<RunMode> for &str {
+    fn from(mode: RunMode) -> Self {
+        match mode {
+            RunMode::FullRun => "ALL_SYSTEMS_ON",
+            RunMode::LevitationOnly => "LEVITATION_ONLY",
+            RunMode::PropulsionOnly => "LIM_ONLY",
+            RunMode::StaticTest => "STATIC_TEST",
+        }
+    }
+}
+
+impl FromStr for RunMode {
+    type Err = &'static str;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "ALL_SYSTEMS_ON" => Ok(RunMode::FullRun),
+            "LEVITATION_ONLY" => Ok(RunMode::LevitationOnly),
+            "LIM_ONLY" => Ok(RunMode::PropulsionOnly),
+            "STATIC_TEST" => Ok(RunMode::StaticTest),
+            _ => Err("Invalid run mode"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODES: [RunMode; 4] = [
+        RunMode::FullRun,
+        RunMode::LevitationOnly,
+        RunMode::PropulsionOnly,
+        RunMode::StaticTest,
+    ];
+
+    #[test]
+    fn test_conversions() {
+        for mode in MODES {
+            assert_eq!(RunMode::try_from(u8::from(mode)), Ok(mode));
+            assert_eq!(<&str>::from(mode).parse(), Ok(mode));
+        }
+        assert!(RunMode::try_from(4).is_err());
+        assert!("FULL_RUN".parse::<RunMode>().is_err());
+    }
+
+    #[test]
+    fn test_every_mode_is_a_cycle_from_idle() {
+        for mode in MODES {
+            let mut state = State::Idle;
+            let mut steps = 0;
+            loop {
+                state = mode.next_state(state).unwrap();
+                steps += 1;
+                if state == State::Idle || steps > mode.transitions().len() {
+                    break;
+                }
+            }
+            assert_eq!(state, State::Idle, "{:?}", mode);
+            // Every transition is on the cycle, apart from entering and leaving maintenance
+            assert_eq!(steps, mode.transitions().len() - 2, "{:?}", mode);
+            assert!(mode.allows(State::Idle, State::Maintenance));
+            assert_eq!(mode.next_state(State::Maintenance), Some(State::Idle));
+        }
+    }
+
+    #[test]
+    fn test_mode_edges() {
+        assert!(RunMode::FullRun.allows(State::Brake, State::StopLevitation));
+        assert!(!RunMode::PropulsionOnly.allows(State::Brake, State::StopLevitation));
+        assert!(RunMode::PropulsionOnly.allows(State::Brake, State::Stopped));
+        assert!(!RunMode::LevitationOnly.has_state(State::Accelerate));
+        assert!(!RunMode::StaticTest.allows(State::Precharge, State::ReadyForLevitation));
+        assert!(RunMode::StaticTest.allows(State::Precharge, State::Emergency));
+    }
+}
@@ -0,0 +1,345 @@
+use crate::{
+    config::{HEARTBEAT_TIMEOUT_MS, LEADER_TIMEOUT_MS, STATE_MACHINE_CANDIDATES},
+    modes::RunMode,
+    states::State,
+};
+use heapless::Vec;
+
+/// Maximum number of boards that can run the state machine
+pub const MAX_CANDIDATES: usize = 4;
+
+/// Maximum number of boards whose states are used to recover the state after a reboot
+pub const MAX_PEERS: usize = 16;
+
+/// Maximum number of boards whose heartbeats are monitored by the state machine
+pub const MAX_MONITORED_BOARDS: usize = 16;
+
+/// What the state machine on a board is doing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Role {
+    /// Handles transition requests, timeouts and events, and commands the other boards
+    Leader,
+    /// Mirrors the leader's state, ready to take over if the leader's heartbeats stop
+    Standby,
+}
+
+/// Decides which of the boards running the state machine is the leader.
+///
+/// The leader is the first candidate, in order of priority, that has been heard from within the
+/// leader timeout. Every candidate applies the same rule to the same heartbeats, so they agree on
+/// the leader without having to vote.
+pub struct LeaderElection<'a> {
+    this_board: &'a str,
+    /// Candidates in order of priority, and when each was last heard from in milliseconds
+    candidates: Vec<(&'a str, u64), MAX_CANDIDATES>,
+    timeout_ms: u64,
+}
+
+impl<'a> LeaderElection<'a> {
+    /// Creates an election between `candidates`, a comma separated list of board names with the
+    /// highest priority first. Every candidate is treated as heard from at `now_ms`, so they all
+    /// have `timeout_ms` to be seen before a lower priority board takes over.
+    pub fn new(
+        candidates: &'a str,
+        this_board: &'a str,
+        timeout_ms: u64,
+        now_ms: u64,
+    ) -> Result<Self, &'static str> {
+        let mut election = LeaderElection {
+            this_board,
+            candidates: Vec::new(),
+            timeout_ms,
+        };
+        for candidate in candidates.split(',').map(str::trim) {
+            election
+                .candidates
+                .push((candidate, now_ms))
+                .map_err(|_| "Too many state machine candidates")?;
+        }
+        if !election.is_candidate(this_board) {
+            return Err("This board is not a state machine candidate");
+        }
+        Ok(election)
+    }
+
+    /// Creates the election configured in `redundancy` in `config/state_machine.yaml`.
+    /// A board that is not configured as a candidate, e.g. a test board, is the only candidate
+    /// of its election, so it always leads.
+    pub fn from_config(this_board: &'a str, now_ms: u64) -> Result<Self, &'static str> {
+        let candidates = match STATE_MACHINE_CANDIDATES
+            .split(',')
+            .any(|candidate| candidate.trim() == this_board)
+        {
+            true => STATE_MACHINE_CANDIDATES,
+            false => this_board,
+        };
+        Self::new(candidates, this_board, LEADER_TIMEOUT_MS, now_ms)
+    }
+
+    pub fn is_candidate(&self, board: &str) -> bool {
+        self.candidates
+            .iter()
+            .any(|(candidate, _)| *candidate == board)
+    }
+
+    /// Records a heartbeat from a board.
+    /// Only heartbeats from boards that know the current state should be recorded, so that a
+    /// board that has just rebooted doesn't become the leader before it has recovered the state.
+    pub fn heartbeat(&mut self, board: &str, now_ms: u64) {
+        if let Some((_, last_seen_ms)) = self
+            .candidates
+            .iter_mut()
+            .find(|(candidate, _)| *candidate == board)
+        {
+            *last_seen_ms = now_ms;
+        }
+    }
+
+    /// The board that should be running the state machine.
+    /// This board is always alive as far as it is concerned.
+    pub fn leader(&self, now_ms: u64) -> &'a str {
+        self.candidates
+            .iter()
+            .find(|(candidate, last_seen_ms)| {
+                *candidate == self.this_board
+                    || now_ms.saturating_sub(*last_seen_ms) <= self.timeout_ms
+            })
+            .map_or(self.this_board, |(candidate, _)| candidate)
+    }
+
+    pub fn role(&self, now_ms: u64) -> Role {
+        match self.leader(now_ms) == self.this_board {
+            true => Role::Leader,
+            false => Role::Standby,
+        }
+    }
+}
+
+/// Keeps track of the heartbeats of the other boards on every board running the state machine,
+/// whether it leads or not, so that a board taking over as the leader knows whether heartbeats
+/// are healthy. Boards are monitored once they are first heard from.
+///
+/// Missing heartbeats are only reported, so that they can be checked by guards and events. Use
+/// `heartbeat_listener` on the board to stop the pod when heartbeats are missing.
+pub struct HeartbeatMonitor<B> {
+    /// Monitored boards, and when each was last heard from in milliseconds
+    boards: Vec<(B, u64), MAX_MONITORED_BOARDS>,
+    timeout_ms: u64,
+}
+
+impl<B: Copy + PartialEq> HeartbeatMonitor<B> {
+    pub fn new(timeout_ms: u64) -> Self {
+        HeartbeatMonitor {
+            boards: Vec::new(),
+            timeout_ms,
+        }
+    }
+
+    /// Creates a monitor using the maximum heartbeat latency in `config/heartbeats.yaml`
+    pub fn from_config() -> Self {
+        Self::new(HEARTBEAT_TIMEOUT_MS)
+    }
+
+    /// Records a heartbeat from a board, monitoring it from now on
+    pub fn heartbeat(&mut self, board: B, now_ms: u64) {
+        match self
+            .boards
+            .iter_mut()
+            .find(|(monitored, _)| *monitored == board)
+        {
+            Some((_, last_seen_ms)) => *last_seen_ms = now_ms,
+            None => {
+                // There are fewer boards on the pod than `MAX_MONITORED_BOARDS`
+                let _ = self.boards.push((board, now_ms));
+            }
+        }
+    }
+
+    /// Each monitored board, and whether its heartbeats are being received
+    pub fn statuses(&self, now_ms: u64) -> impl Iterator<Item = (B, bool)> + '_ {
+        self.boards.iter().map(move |(board, last_seen_ms)| {
+            (
+                *board,
+                now_ms.saturating_sub(*last_seen_ms) <= self.timeout_ms,
+            )
+        })
+    }
+
+    /// Whether heartbeats are being received from every monitored board
+    pub fn healthy(&self, now_ms: u64) -> bool {
+        self.statuses(now_ms).all(|(_, healthy)| healthy)
+    }
+}
+
+/// Works out the pod's state after a reboot from the states reported by the other boards, rather
+/// than restarting in `Idle`
+pub struct StateRecovery<'a> {
+    mode: RunMode,
+    /// The latest state reported by each board
+    reports: Vec<(&'a str, State), MAX_PEERS>,
+}
+
+impl<'a> StateRecovery<'a> {
+    pub fn new(mode: RunMode) -> Self {
+        StateRecovery {
+            mode,
+            reports: Vec::new(),
+        }
+    }
+
+    /// Records the state reported by a board, replacing its previous report.
+    /// States the run mode never reaches are ignored.
+    pub fn report(&mut self, board: &'a str, state: State) {
+        if !self.mode.has_state(state) {
+            return;
+        }
+        match self
+            .reports
+            .iter_mut()
+            .find(|(reporter, _)| *reporter == board)
+        {
+            Some((_, reported)) => *reported = state,
+            None => {
+                // Enough boards have reported for the state to be recovered without this one
+                let _ = self.reports.push((board, state));
+            }
+        }
+    }
+
+    /// The recovered state: an emergency if any board reports one, otherwise the state reported
+    /// by the most boards, or `Idle` if no board has reported a state.
+    /// Ties go to the state defined first in `config/states.yaml`.
+    pub fn state(&self) -> State {
+        let count = |state: State| {
+            self.reports
+                .iter()
+                .filter(|(_, reported)| *reported == state)
+                .count()
+        };
+        if count(State::EMERGENCY) > 0 {
+            return State::EMERGENCY;
+        }
+        State::ALL
+            .iter()
+            .map(|state| (*state, count(*state)))
+            .fold((State::Idle, 0), |best, (state, reports)| {
+                match reports > best.1 {
+                    true => (state, reports),
+                    false => best,
+                }
+            })
+            .0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{events, guards::SystemSnapshot, state_machine::StateMachine};
+
+    #[test]
+    fn test_leader_election() {
+        let mut election =
+            LeaderElection::new("telemetry, navigation", "navigation", 300, 0).unwrap();
+        assert_eq!(election.role(300), Role::Standby);
+
+        election.heartbeat("telemetry", 250);
+        assert_eq!(election.leader(550), "telemetry");
+        // The leader's heartbeats have stopped, so the next candidate takes over
+        assert_eq!(election.leader(551), "navigation");
+        assert_eq!(election.role(551), Role::Leader);
+        // The leader hands back once the higher priority board is heard from again
+        election.heartbeat("telemetry", 900);
+        assert_eq!(election.role(900), Role::Standby);
+
+        assert!(LeaderElection::new("telemetry,navigation", "pneumatics", 300, 0).is_err());
+    }
+
+    #[test]
+    fn test_highest_priority_board_always_leads() {
+        let election = LeaderElection::new("telemetry,navigation", "telemetry", 300, 0).unwrap();
+        assert_eq!(election.role(10_000), Role::Leader);
+    }
+
+    #[test]
+    fn test_non_candidate_leads_alone() {
+        let mut election = LeaderElection::from_config("state_machine_tester", 0).unwrap();
+        election.heartbeat("telemetry", 100);
+        assert_eq!(election.role(100), Role::Leader);
+        assert!(!election.is_candidate("telemetry"));
+
+        let election = LeaderElection::from_config("navigation", 0).unwrap();
+        assert!(election.is_candidate("telemetry"));
+    }
+
+    #[test]
+    fn test_state_recovery() {
+        let mut recovery = StateRecovery::new(RunMode::FullRun);
+        assert_eq!(recovery.state(), State::Idle);
+
+        recovery.report("navigation", State::Accelerate);
+        recovery.report("pneumatics", State::Ready);
+        recovery.report("keyence_tester", State::Accelerate);
+        assert_eq!(recovery.state(), State::Accelerate);
+
+        // A board's latest report replaces its previous one, and ties go to the first state
+        recovery.report("keyence_tester", State::Ready);
+        assert_eq!(recovery.state(), State::Ready);
+
+        recovery.report("pneumatics", State::Emergency);
+        assert_eq!(recovery.state(), State::Emergency);
+    }
+
+    #[test]
+    fn test_state_recovery_ignores_unreachable_states() {
+        let mut recovery = StateRecovery::new(RunMode::StaticTest);
+        recovery.report("navigation", State::Accelerate);
+        assert_eq!(recovery.state(), State::Idle);
+    }
+
+    #[test]
+    fn test_heartbeat_monitor() {
+        let mut monitor = HeartbeatMonitor::new(200);
+        // Boards that have never been heard from are not monitored
+        assert!(monitor.healthy(1000));
+
+        monitor.heartbeat("pneumatics", 1000);
+        monitor.heartbeat("temperature_tester", 1100);
+        assert!(monitor.healthy(1200));
+        assert_eq!(
+            monitor.statuses(1250).collect::<std::vec::Vec<_>>(),
+            [("pneumatics", false), ("temperature_tester", true)]
+        );
+        assert!(!monitor.healthy(1250));
+        monitor.heartbeat("pneumatics", 1300);
+        assert!(monitor.healthy(1300));
+    }
+
+    #[test]
+    fn test_takeover_snapshot() {
+        let mut election =
+            LeaderElection::new("telemetry,navigation", "navigation", 300, 0).unwrap();
+        let mut monitor = HeartbeatMonitor::new(200);
+        let mut state_machine = StateMachine::new(RunMode::FullRun);
+        events::add_configured_event_transitions(&mut state_machine).unwrap();
+        state_machine.follow(State::Maintenance);
+
+        // On standby, the other boards' heartbeats are monitored as well as the leader's
+        election.heartbeat("telemetry", 100);
+        monitor.heartbeat("pneumatics", 100);
+        assert_eq!(election.role(300), Role::Standby);
+        monitor.heartbeat("pneumatics", 450);
+
+        // The leader's heartbeats stop, and this board takes over with healthy heartbeats
+        assert_eq!(election.role(500), Role::Leader);
+        let mut snapshot = SystemSnapshot {
+            heartbeats_healthy: monitor.healthy(500),
+            ..Default::default()
+        };
+        assert_eq!(state_machine.handle_events(&snapshot), None);
+
+        // Once a monitored board goes quiet, the new leader leaves maintenance
+        snapshot.heartbeats_healthy = monitor.healthy(700);
+        assert_eq!(state_machine.handle_events(&snapshot), Some(State::Idle));
+    }
+}
@@ -1,57 +1,478 @@
-use crate::states::State;
+use crate::{
+    events::StateMachineEvent,
+    guards::{FailedGuards, SystemSnapshot, TransitionGuard},
+    modes::RunMode,
+    states::State,
+    timeouts::StateTimeout,
+};
+use heapless::Vec;
 use hyped_core::logging::{info, warn};
 
-pub struct StateMachine {
+/// Maximum number of guards that can be registered across all transitions
+pub const MAX_GUARDS: usize = 32;
+
+/// Maximum number of states that can have a timeout
+pub const MAX_TIMEOUTS: usize = 16;
+
+/// Maximum number of transitions that can be fired by events
+pub const MAX_EVENT_TRANSITIONS: usize = 8;
+
+/// A guard registered on the transition between two states
+struct Guard<'a> {
+    from: State,
+    to: State,
+    guard: &'a dyn TransitionGuard,
+}
+
+/// A transition made when an event is detected in a given state
+struct EventTransition {
+    event: StateMachineEvent,
+    from: State,
+    to: State,
+}
+
+/// Why a transition was rejected
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum RejectionReason {
+    /// There is no transition between the two states
+    InvalidTransition,
+    /// The transition exists, but some of its guards failed
+    GuardsFailed(FailedGuards),
+    /// The requesting board may not request the transition, see `authorisation`
+    Unauthorised,
+    /// A board could not run its state actions after the transition was made, so the state
+    /// machine moved the pod back to the state it came from
+    ActionFailed,
+}
+
+impl RejectionReason {
+    /// Code identifying the reason, sent over CAN and MQTT
+    pub fn code(&self) -> u8 {
+        match self {
+            RejectionReason::InvalidTransition => 0,
+            RejectionReason::GuardsFailed(_) => 1,
+            RejectionReason::Unauthorised => 2,
+            RejectionReason::ActionFailed => 3,
+        }
+    }
+
+    /// The failed guards, or none if the transition was rejected for another reason
+    pub fn failed_guards(&self) -> FailedGuards {
+        match self {
+            RejectionReason::InvalidTransition
+            | RejectionReason::Unauthorised
+            | RejectionReason::ActionFailed => FailedGuards::default(),
+            RejectionReason::GuardsFailed(failed) => *failed,
+        }
+    }
+
+    /// Rebuilds a reason from its code and failed guards, e.g. after receiving it over CAN
+    pub fn from_code(code: u8, failed_guards: FailedGuards) -> Result<Self, &'static str> {
+        match code {
+            0 => Ok(RejectionReason::InvalidTransition),
+            1 => Ok(RejectionReason::GuardsFailed(failed_guards)),
+            2 => Ok(RejectionReason::Unauthorised),
+            3 => Ok(RejectionReason::ActionFailed),
+            _ => Err("Invalid rejection reason"),
+        }
+    }
+}
+
+impl From<RejectionReason> for &str {
+    fn from(val: RejectionReason) -> Self {
+        match val {
+            RejectionReason::InvalidTransition => "invalid_transition",
+            RejectionReason::GuardsFailed(_) => "guards_failed",
+            RejectionReason::Unauthorised => "unauthorised",
+            RejectionReason::ActionFailed => "action_failed",
+        }
+    }
+}
+
+/// A state transition that was rejected by the state machine
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct TransitionRejection {
+    pub from: State,
+    pub to: State,
+    pub reason: RejectionReason,
+}
+
+pub struct StateMachine<'a> {
     pub current_state: State,
+    mode: RunMode,
+    guards: Vec<Guard<'a>, MAX_GUARDS>,
+    timeouts: Vec<(State, StateTimeout), MAX_TIMEOUTS>,
+    event_transitions: Vec<EventTransition, MAX_EVENT_TRANSITIONS>,
 }
 
-impl Default for StateMachine {
+impl Default for StateMachine<'_> {
     fn default() -> Self {
-        Self::new()
+        Self::new(RunMode::default())
     }
 }
 
-impl StateMachine {
-    pub fn new() -> Self {
+impl<'a> StateMachine<'a> {
+    /// Creates a state machine in `Idle`, which only allows the transitions of the given mode
+    pub fn new(mode: RunMode) -> Self {
         StateMachine {
             current_state: State::Idle,
+            mode,
+            guards: Vec::new(),
+            timeouts: Vec::new(),
+            event_transitions: Vec::new(),
         }
     }
 
+    pub fn mode(&self) -> RunMode {
+        self.mode
+    }
+
+    /// Registers a guard that must pass for the transition between two states to happen.
+    /// The transition must exist in the state machine's mode.
+    /// Transitions to `State::Emergency` are never guarded.
+    pub fn add_guard(
+        &mut self,
+        from: State,
+        to: State,
+        guard: &'a dyn TransitionGuard,
+    ) -> Result<(), &'static str> {
+        if to == State::Emergency || !self.mode.allows(from, to) {
+            return Err("Invalid guarded transition");
+        }
+        self.guards
+            .push(Guard { from, to, guard })
+            .map_err(|_| "Too many transition guards")
+    }
+
     /// Handles a transition from the current state to the given state.
-    /// If the state transition is valid, the state machine will transition to the new state.
-    pub fn handle_transition(&mut self, to_state: &State) -> Option<State> {
-        let new_state = match (self.current_state, to_state) {
-            (State::Idle, State::Calibrate) => Some(State::Calibrate),
-            (State::Calibrate, State::Precharge) => Some(State::Precharge),
-            (State::Precharge, State::ReadyForLevitation) => Some(State::ReadyForLevitation),
-            (State::ReadyForLevitation, State::BeginLevitation) => Some(State::BeginLevitation),
-            (State::BeginLevitation, State::Ready) => Some(State::Ready),
-            (State::Ready, State::Accelerate) => Some(State::Accelerate),
-            (State::Accelerate, State::Brake) => Some(State::Brake),
-            (State::Accelerate, State::Emergency) => Some(State::Emergency),
-            (State::Brake, State::StopLevitation) => Some(State::StopLevitation),
-            (State::StopLevitation, State::Stopped) => Some(State::Stopped),
-            (State::Stopped, State::Idle) => Some(State::Idle),
-            _ => None,
-        };
+    /// If the state transition is allowed in the state machine's mode and all of its guards pass
+    /// for the given snapshot, the state machine will transition to the new state.
+    /// An emergency can be entered from any state.
+    pub fn handle_transition(
+        &mut self,
+        to_state: &State,
+        snapshot: &SystemSnapshot,
+    ) -> Result<State, TransitionRejection> {
+        if !self.mode.allows(self.current_state, *to_state) {
+            return Err(self.reject(*to_state, RejectionReason::InvalidTransition));
+        }
+        let new_state = *to_state;
 
-        match new_state {
-            Some(new_state) => {
-                info!(
-                    "Transitioning from {:?} to {:?}",
-                    self.current_state, new_state
-                );
-                self.current_state = new_state;
-                Some(new_state)
+        if new_state != State::Emergency {
+            let failed = self.check_guards(new_state, snapshot);
+            if !failed.is_empty() {
+                return Err(self.reject(new_state, RejectionReason::GuardsFailed(failed)));
             }
-            None => {
-                warn!(
-                    "Invalid transition requested from {:?} to {:?}",
-                    self.current_state, to_state
+        }
+
+        info!(
+            "Transitioning from {:?} to {:?}",
+            self.current_state, new_state
+        );
+        self.current_state = new_state;
+        Ok(new_state)
+    }
+
+    /// Sets the current state to one entered by another state machine, e.g. the leader's state
+    /// while on standby, or the state recovered after a reboot.
+    /// The transition is not checked, as the pod is already in the state.
+    pub fn follow(&mut self, state: State) {
+        self.current_state = state;
+    }
+
+    /// Sets the maximum time the pod may stay in `state`, replacing any previous timeout.
+    /// The timeout target must be reachable from `state`.
+    pub fn set_timeout(&mut self, state: State, timeout: StateTimeout) -> Result<(), &'static str> {
+        if !self.mode.allows(state, timeout.target) {
+            return Err("Invalid timeout target");
+        }
+        self.timeouts
+            .retain(|(timeout_state, _)| *timeout_state != state);
+        self.timeouts
+            .push((state, timeout))
+            .map_err(|_| "Too many state timeouts")
+    }
+
+    /// Gets the timeout of a state, if it has one
+    pub fn timeout(&self, state: State) -> Option<StateTimeout> {
+        self.timeouts
+            .iter()
+            .find(|(timeout_state, _)| *timeout_state == state)
+            .map(|(_, timeout)| *timeout)
+    }
+
+    /// Moves to the timeout target of the current state if the pod has been in it for at least
+    /// its maximum duration, returning the new state.
+    /// Guards are not checked, as timeouts exist to get the pod out of a state that is stuck.
+    pub fn handle_timeout(&mut self, time_in_state_ms: u64) -> Option<State> {
+        let timeout = self.timeout(self.current_state)?;
+        if time_in_state_ms < timeout.max_duration_ms {
+            return None;
+        }
+        warn!(
+            "Timed out in {:?} after {}ms, transitioning to {:?}",
+            self.current_state, time_in_state_ms, timeout.target
+        );
+        self.current_state = timeout.target;
+        Some(timeout.target)
+    }
+
+    /// Registers a transition that is made by the state machine itself when `event` is
+    /// detected in the `from` state
+    pub fn add_event_transition(
+        &mut self,
+        event: StateMachineEvent,
+        from: State,
+        to: State,
+    ) -> Result<(), &'static str> {
+        if !self.mode.allows(from, to) {
+            return Err("Invalid event transition");
+        }
+        self.event_transitions
+            .push(EventTransition { event, from, to })
+            .map_err(|_| "Too many event transitions")
+    }
+
+    /// Makes the first event transition from the current state whose event is detected in the
+    /// snapshot and whose guards pass, returning the new state.
+    /// Transitions with failing guards are retried on the next call rather than rejected.
+    pub fn handle_events(&mut self, snapshot: &SystemSnapshot) -> Option<State> {
+        let new_state = self
+            .event_transitions
+            .iter()
+            .filter(|transition| transition.from == self.current_state)
+            .find(|transition| {
+                transition.event.detected(snapshot)
+                    && self.check_guards(transition.to, snapshot).is_empty()
+            })
+            .map(|transition| {
+                info!(
+                    "{:?} detected, transitioning from {:?} to {:?}",
+                    transition.event, transition.from, transition.to
                 );
-                None
-            }
+                transition.to
+            })?;
+        self.current_state = new_state;
+        Some(new_state)
+    }
+
+    fn reject(&self, to_state: State, reason: RejectionReason) -> TransitionRejection {
+        warn!(
+            "Rejected transition from {:?} to {:?}: {:?}",
+            self.current_state, to_state, reason
+        );
+        TransitionRejection {
+            from: self.current_state,
+            to: to_state,
+            reason,
+        }
+    }
+
+    /// Runs every guard on the transition from the current state, returning those that failed
+    fn check_guards(&self, to_state: State, snapshot: &SystemSnapshot) -> FailedGuards {
+        let mut failed = FailedGuards::default();
+        self.guards
+            .iter()
+            .filter(|guard| guard.from == self.current_state && guard.to == to_state)
+            .filter_map(|guard| guard.guard.check(snapshot).err())
+            .for_each(|failure| failed.insert(failure));
+        failed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        events::StateMachineEvent,
+        guards::{self, GuardFailure},
+    };
+    use hyped_core::types::LocalisationMode;
+
+    #[test]
+    fn test_emergency_from_any_state() {
+        for state in [State::Idle, State::Calibrate, State::Ready, State::Stopped] {
+            let mut state_machine = StateMachine::new(RunMode::FullRun);
+            state_machine.current_state = state;
+            assert_eq!(
+                state_machine.handle_transition(&State::Emergency, &SystemSnapshot::default()),
+                Ok(State::Emergency)
+            );
+            assert_eq!(state_machine.current_state, State::Emergency);
+        }
+    }
+
+    #[test]
+    fn test_mode_transitions() {
+        let mut state_machine = StateMachine::new(RunMode::PropulsionOnly);
+        state_machine.current_state = State::Brake;
+        assert_eq!(
+            state_machine.handle_transition(&State::StopLevitation, &SystemSnapshot::default()),
+            Err(TransitionRejection {
+                from: State::Brake,
+                to: State::StopLevitation,
+                reason: RejectionReason::InvalidTransition,
+            })
+        );
+        assert_eq!(
+            state_machine.handle_transition(&State::Stopped, &SystemSnapshot::default()),
+            Ok(State::Stopped)
+        );
+        assert!(state_machine
+            .add_guard(State::Brake, State::StopLevitation, &guards::brakes_engaged)
+            .is_err());
+    }
+
+    #[test]
+    fn test_invalid_transition() {
+        let mut state_machine = StateMachine::new(RunMode::FullRun);
+        assert_eq!(
+            state_machine.handle_transition(&State::Accelerate, &SystemSnapshot::default()),
+            Err(TransitionRejection {
+                from: State::Idle,
+                to: State::Accelerate,
+                reason: RejectionReason::InvalidTransition,
+            })
+        );
+        assert_eq!(state_machine.current_state, State::Idle);
+    }
+
+    #[test]
+    fn test_guards_list_every_failure() {
+        let mut state_machine = StateMachine::new(RunMode::FullRun);
+        guards::add_default_guards(&mut state_machine).unwrap();
+        state_machine.current_state = State::Ready;
+
+        let snapshot = SystemSnapshot {
+            brakes_engaged: Some(true),
+            brake_reservoir_pressure: Some(5.0),
+            suspension_reservoir_pressure: Some(5.0),
+            heartbeats_healthy: true,
+            localisation_valid: false,
+            localisation_mode: Some(LocalisationMode::Nominal),
+            ..Default::default()
+        };
+        let mut failed = FailedGuards::default();
+        failed.insert(GuardFailure::BrakesEngaged);
+        failed.insert(GuardFailure::LocalisationInvalid);
+        assert_eq!(
+            state_machine.handle_transition(&State::Accelerate, &snapshot),
+            Err(TransitionRejection {
+                from: State::Ready,
+                to: State::Accelerate,
+                reason: RejectionReason::GuardsFailed(failed),
+            })
+        );
+        assert_eq!(state_machine.current_state, State::Ready);
+
+        let snapshot = SystemSnapshot {
+            brakes_engaged: Some(false),
+            localisation_valid: true,
+            ..snapshot
+        };
+        assert_eq!(
+            state_machine.handle_transition(&State::Accelerate, &snapshot),
+            Ok(State::Accelerate)
+        );
+    }
+
+    #[test]
+    fn test_rejection_reason_code() {
+        let mut failed = FailedGuards::default();
+        failed.insert(GuardFailure::PressureOutOfRange);
+        for reason in [
+            RejectionReason::InvalidTransition,
+            RejectionReason::GuardsFailed(failed),
+            RejectionReason::Unauthorised,
+            RejectionReason::ActionFailed,
+        ] {
+            assert_eq!(
+                RejectionReason::from_code(reason.code(), reason.failed_guards()),
+                Ok(reason)
+            );
         }
+        assert!(RejectionReason::from_code(4, failed).is_err());
+    }
+
+    #[test]
+    fn test_closure_guard() {
+        let max_velocity = 0.5;
+        let stationary = |snapshot: &SystemSnapshot| match snapshot.localisation_valid {
+            true if max_velocity > 0.0 => Ok(()),
+            _ => Err(GuardFailure::LocalisationInvalid),
+        };
+        let mut state_machine = StateMachine::new(RunMode::FullRun);
+        state_machine
+            .add_guard(State::Idle, State::Calibrate, &stationary)
+            .unwrap();
+
+        assert!(state_machine
+            .handle_transition(&State::Calibrate, &SystemSnapshot::default())
+            .is_err());
+        let snapshot = SystemSnapshot {
+            localisation_valid: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            state_machine.handle_transition(&State::Calibrate, &snapshot),
+            Ok(State::Calibrate)
+        );
+    }
+
+    #[test]
+    fn test_timeout() {
+        let mut state_machine = StateMachine::new(RunMode::FullRun);
+        let timeout = StateTimeout {
+            max_duration_ms: 100,
+            target: State::Brake,
+        };
+        assert!(state_machine
+            .set_timeout(
+                State::Accelerate,
+                StateTimeout {
+                    target: State::Idle,
+                    ..timeout
+                }
+            )
+            .is_err());
+        state_machine
+            .set_timeout(State::Accelerate, timeout)
+            .unwrap();
+
+        assert_eq!(state_machine.handle_timeout(1000), None);
+        state_machine.current_state = State::Accelerate;
+        assert_eq!(state_machine.handle_timeout(99), None);
+        assert_eq!(state_machine.handle_timeout(100), Some(State::Brake));
+        assert_eq!(state_machine.current_state, State::Brake);
+    }
+
+    #[test]
+    fn test_event_transition() {
+        let mut state_machine = StateMachine::new(RunMode::FullRun);
+        state_machine
+            .add_event_transition(
+                StateMachineEvent::PodStopped,
+                State::Brake,
+                State::StopLevitation,
+            )
+            .unwrap();
+        state_machine
+            .add_guard(State::Brake, State::StopLevitation, &guards::brakes_engaged)
+            .unwrap();
+        let mut snapshot = SystemSnapshot {
+            velocity: Some(0.0),
+            ..Default::default()
+        };
+
+        // Only fired in the `from` state
+        assert_eq!(state_machine.handle_events(&snapshot), None);
+        state_machine.current_state = State::Brake;
+        // Waits for the guards to pass
+        assert_eq!(state_machine.handle_events(&snapshot), None);
+        snapshot.brakes_engaged = Some(true);
+        assert_eq!(
+            state_machine.handle_events(&snapshot),
+            Some(State::StopLevitation)
+        );
     }
 }
@@ -0,0 +1,72 @@
+use crate::{state_machine::StateMachine, states::State};
+use hyped_core::config::STATE_MACHINE_CONFIG;
+
+/// The maximum time the pod may stay in a state, and where it goes when that time runs out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct StateTimeout {
+    pub max_duration_ms: u64,
+    pub target: State,
+}
+
+/// Registers the state timeouts in `config/state_machine.yaml`, skipping states the state
+/// machine's mode never reaches
+pub fn add_configured_timeouts(state_machine: &mut StateMachine) -> Result<(), &'static str> {
+    let timeouts = &STATE_MACHINE_CONFIG.timeouts;
+    let mode = state_machine.mode();
+    for (state, max_duration_ms, target) in [
+        (
+            State::Calibrate,
+            timeouts.calibrate.max_duration_ms,
+            timeouts.calibrate.target,
+        ),
+        (
+            State::Precharge,
+            timeouts.precharge.max_duration_ms,
+            timeouts.precharge.target,
+        ),
+        (
+            State::BeginLevitation,
+            timeouts.begin_levitation.max_duration_ms,
+            timeouts.begin_levitation.target,
+        ),
+        (
+            State::Accelerate,
+            timeouts.accelerate.max_duration_ms,
+            timeouts.accelerate.target,
+        ),
+    ] {
+        if !mode.has_state(state) {
+            continue;
+        }
+        let timeout = StateTimeout {
+            max_duration_ms: max_duration_ms as u64,
+            target: target.parse()?,
+        };
+        state_machine.set_timeout(state, timeout)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modes::RunMode;
+
+    #[test]
+    fn test_configured_timeouts() {
+        let mut state_machine = StateMachine::new(RunMode::FullRun);
+        add_configured_timeouts(&mut state_machine).unwrap();
+        assert_eq!(
+            state_machine.timeout(State::Accelerate),
+            Some(StateTimeout {
+                max_duration_ms: STATE_MACHINE_CONFIG.timeouts.accelerate.max_duration_ms as u64,
+                target: State::Brake,
+            })
+        );
+        assert_eq!(state_machine.timeout(State::Idle), None);
+
+        let mut state_machine = StateMachine::new(RunMode::LevitationOnly);
+        add_configured_timeouts(&mut state_machine).unwrap();
+        assert_eq!(state_machine.timeout(State::Accelerate), None);
+    }
+}
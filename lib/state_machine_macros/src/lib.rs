@@ -0,0 +1,287 @@
+use convert_case::{Case, Casing};
+use proc_macro::TokenStream;
+use saphyr::Yaml;
+
+/// Maximum length of a state name, which must fit in a `String<20>`
+const MAX_NAME_LEN: usize = 20;
+
+/// Generates the `State` enum, its conversions and the transitions of each run mode from the
+/// states configuration. The path is relative to the crate that uses the macro.
+///
+/// For every run mode `mode` under `transitions`, a `MODE_TRANSITIONS` array of
+/// `(from, to)` pairs is generated.
+#[proc_macro]
+pub fn gen_states(args: TokenStream) -> TokenStream {
+    let yaml_path = args.to_string().replace(" ", "").replace("\"", "");
+    let definition = StatesDefinition::load(&yaml_path);
+    definition
+        .generate()
+        .parse()
+        .expect("Failed to parse generated states")
+}
+
+/// A state, named in snake case as in the configuration
+struct StateInfo {
+    name: String,
+    value: u8,
+}
+
+struct StatesDefinition {
+    states: Vec<StateInfo>,
+    emergency: String,
+    /// Transitions of each run mode, by run mode name
+    transitions: Vec<(String, Vec<(String, String)>)>,
+}
+
+impl StatesDefinition {
+    fn load(yaml_path: &str) -> Self {
+        let yaml =
+            get_yaml(yaml_path).unwrap_or_else(|| panic!("Failed to load yaml: {yaml_path}"));
+
+        let mut states: Vec<StateInfo> = Vec::new();
+        let state_values = yaml["states"]
+            .as_hash()
+            .unwrap_or_else(|| panic!("{yaml_path} is missing `states`"));
+        for (name, value) in state_values {
+            let name = name.as_str().unwrap().to_string();
+            let value = value
+                .as_i64()
+                .and_then(|value| u8::try_from(value).ok())
+                .unwrap_or_else(|| panic!("State {name} must have a value between 0 and 255"));
+            if name.len() > MAX_NAME_LEN {
+                panic!("State name {name} is longer than {MAX_NAME_LEN} characters");
+            }
+            if let Some(other) = states.iter().find(|state| state.value == value) {
+                panic!(
+                    "States {} and {name} have the same value {value}",
+                    other.name
+                );
+            }
+            states.push(StateInfo { name, value });
+        }
+
+        let emergency = yaml["emergency"]
+            .as_str()
+            .unwrap_or_else(|| panic!("{yaml_path} is missing `emergency`"))
+            .to_string();
+
+        let mut transitions = Vec::new();
+        let modes = yaml["transitions"]
+            .as_hash()
+            .unwrap_or_else(|| panic!("{yaml_path} is missing `transitions`"));
+        for (mode, mode_transitions) in modes {
+            let mode = mode.as_str().unwrap().to_string();
+            let mode_transitions = mode_transitions
+                .as_vec()
+                .unwrap_or_else(|| panic!("Transitions of {mode} must be a list"))
+                .iter()
+                .map(
+                    |transition| match transition.as_vec().map(|pair| pair.as_slice()) {
+                        Some([from, to]) => (
+                            from.as_str().unwrap_or_default().to_string(),
+                            to.as_str().unwrap_or_default().to_string(),
+                        ),
+                        _ => panic!("Transitions of {mode} must be pairs of states"),
+                    },
+                )
+                .collect();
+            transitions.push((mode, mode_transitions));
+        }
+
+        let definition = StatesDefinition {
+            states,
+            emergency,
+            transitions,
+        };
+        definition.validate();
+        definition
+    }
+
+    fn validate(&self) {
+        let exists = |name: &String| self.states.iter().any(|state| &state.name == name);
+        if !exists(&self.emergency) {
+            panic!("Emergency state {} is not a state", self.emergency);
+        }
+        for (mode, transitions) in &self.transitions {
+            for (from, to) in transitions {
+                if !exists(from) || !exists(to) {
+                    panic!("Transition {from} -> {to} of {mode} uses an unknown state");
+                }
+                if to == &self.emergency {
+                    panic!("Transition {from} -> {to} of {mode} is always allowed");
+                }
+            }
+        }
+    }
+
+    fn variant(&self, name: &str) -> String {
+        format!("State::{}", name.to_case(Case::Pascal))
+    }
+
+    fn generate(&self) -> String {
+        // Actual enum
+        let mut enum_str =
+            String::from("#[derive(PartialEq, Eq, Debug, defmt::Format, Clone, Copy)]\n");
+        enum_str.push_str("#[repr(u8)]\n");
+        enum_str.push_str("pub enum State {\n");
+        for state in &self.states {
+            enum_str.push_str(&format!(
+                "    {} = {},\n",
+                state.name.to_case(Case::Pascal),
+                state.value
+            ));
+        }
+        enum_str.push_str("}\n");
+
+        // impl State (all states and the emergency state)
+        enum_str.push_str("\nimpl State {\n");
+        enum_str
+            .push_str("    /// Every state, in the order they are defined in the configuration\n");
+        enum_str.push_str(&format!(
+            "    pub const ALL: [State; {}] = [\n",
+            self.states.len()
+        ));
+        for state in &self.states {
+            enum_str.push_str(&format!("        {},\n", self.variant(&state.name)));
+        }
+        enum_str.push_str("    ];\n");
+        enum_str.push_str("\n    /// The state that can be entered from every state\n");
+        enum_str.push_str(&format!(
+            "    pub const EMERGENCY: State = {};\n",
+            self.variant(&self.emergency)
+        ));
+        enum_str.push_str("}\n");
+
+        // From<State> for u8
+        enum_str.push_str("\nimpl From<State> for u8 {\n");
+        enum_str.push_str("    fn from(state: State) -> Self {\n");
+        enum_str.push_str("        state as u8\n");
+        enum_str.push_str("    }\n");
+        enum_str.push_str("}\n");
+
+        // TryFrom<u8> for State
+        enum_str.push_str("\nimpl TryFrom<u8> for State {\n");
+        enum_str.push_str("    type Error = &'static str;\n");
+        enum_str.push_str("    fn try_from(value: u8) -> Result<Self, Self::Error> {\n");
+        enum_str.push_str("        match value {\n");
+        for state in &self.states {
+            enum_str.push_str(&format!(
+                "            {} => Ok({}),\n",
+                state.value,
+                self.variant(&state.name)
+            ));
+        }
+        enum_str.push_str("            _ => Err(\"Invalid state\"),\n");
+        enum_str.push_str("        }\n");
+        enum_str.push_str("    }\n");
+        enum_str.push_str("}\n");
+
+        // From<State> for &str
+        enum_str.push_str("\nimpl From<State> for &str {\n");
+        enum_str.push_str("    fn from(state: State) -> Self {\n");
+        enum_str.push_str("        match state {\n");
+        for state in &self.states {
+            enum_str.push_str(&format!(
+                "            {} => \"{}\",\n",
+                self.variant(&state.name),
+                state.name
+            ));
+        }
+        enum_str.push_str("        }\n");
+        enum_str.push_str("    }\n");
+        enum_str.push_str("}\n");
+
+        // FromStr for State
+        enum_str.push_str("\nimpl FromStr for State {\n");
+        enum_str.push_str("    type Err = &'static str;\n");
+        enum_str.push_str("    fn from_str(value: &str) -> Result<Self, Self::Err> {\n");
+        enum_str.push_str("        match value {\n");
+        for state in &self.states {
+            enum_str.push_str(&format!(
+                "            \"{}\" => Ok({}),\n",
+                state.name,
+                self.variant(&state.name)
+            ));
+        }
+        enum_str.push_str("            _ => Err(\"Invalid state\"),\n");
+        enum_str.push_str("        }\n");
+        enum_str.push_str("    }\n");
+        enum_str.push_str("}\n");
+
+        // From<State> for String<20>
+        enum_str.push_str(&format!(
+            "\nimpl From<State> for String<{MAX_NAME_LEN}> {{\n"
+        ));
+        enum_str.push_str("    fn from(state: State) -> Self {\n");
+        enum_str.push_str("        let mut s = String::new();\n");
+        enum_str.push_str("        s.push_str(state.into()).unwrap();\n");
+        enum_str.push_str("        s\n");
+        enum_str.push_str("    }\n");
+        enum_str.push_str("}\n");
+
+        // Transitions of each run mode
+        for (mode, transitions) in &self.transitions {
+            enum_str.push_str(&format!(
+                "\n/// Transitions of the `{mode}` run mode, other than into the emergency state\n"
+            ));
+            enum_str.push_str(&format!(
+                "pub const {}_TRANSITIONS: [(State, State); {}] = [\n",
+                mode.to_case(Case::UpperSnake),
+                transitions.len()
+            ));
+            for (from, to) in transitions {
+                enum_str.push_str(&format!(
+                    "    ({}, {}),\n",
+                    self.variant(from),
+                    self.variant(to)
+                ));
+            }
+            enum_str.push_str("];\n");
+        }
+
+        enum_str
+    }
+}
+
+/// Generates `configured_rule`, a `const fn` giving the boards that may request a transition into
+/// a state, from the `authorisation` map of the state machine configuration. States without a
+/// rule get an empty list. The path is relative to the crate that uses the macro.
+#[proc_macro]
+pub fn gen_authorisation_rules(args: TokenStream) -> TokenStream {
+    let yaml_path = args.to_string().replace(" ", "").replace("\"", "");
+    let yaml = get_yaml(&yaml_path).unwrap_or_else(|| panic!("Failed to load yaml: {yaml_path}"));
+    let rules = yaml["authorisation"]
+        .as_hash()
+        .unwrap_or_else(|| panic!("{yaml_path} is missing `authorisation`"));
+
+    let mut rules_str = String::from("const fn configured_rule(state: State) -> &'static str {\n");
+    rules_str.push_str("    match state {\n");
+    for (state, boards) in rules {
+        let state = state.as_str().unwrap();
+        let boards = boards
+            .as_str()
+            .unwrap_or_else(|| panic!("Authorisation of {state} must be a list of boards"));
+        rules_str.push_str(&format!(
+            "        State::{} => \"{}\",\n",
+            state.to_case(Case::Pascal),
+            boards.escape_default()
+        ));
+    }
+    rules_str.push_str("        #[allow(unreachable_patterns)]\n");
+    rules_str.push_str("        _ => \"\",\n");
+    rules_str.push_str("    }\n");
+    rules_str.push_str("}\n");
+
+    rules_str
+        .parse()
+        .expect("Failed to parse generated authorisation rules")
+}
+
+fn get_yaml(yaml_path: &str) -> Option<Yaml> {
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap_or_default();
+    let path_to_use = std::path::Path::new(&manifest_dir).join(yaml_path);
+    match std::fs::read_to_string(&path_to_use) {
+        Ok(file) => Some(Yaml::load_from_str(&file).unwrap()[0].clone()),
+        Err(_) => panic!("Failed to open file: {}", path_to_use.display()),
+    }
+}
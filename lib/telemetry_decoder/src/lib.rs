@@ -0,0 +1,362 @@
+//! Decodes the binary MQTT payloads sent by the pod, for use on the base station and in tools.
+//!
+//! Measurement batches are CBOR maps of the form
+//! `{0: version, 1: board, 2: measurement, 3: [[timestamp_ms, value], ...]}`,
+//! as produced by `hyped_core::mqtt::cbor::MeasurementBatch`.
+
+use std::fmt;
+
+/// The payload schema version this decoder understands
+pub const SUPPORTED_VERSION: u64 = 1;
+
+const KEY_VERSION: u64 = 0;
+const KEY_BOARD: u64 = 1;
+const KEY_MEASUREMENT: u64 = 2;
+const KEY_SAMPLES: u64 = 3;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodeError {
+    /// The payload ended in the middle of a data item
+    UnexpectedEnd,
+    /// The payload contains a CBOR type that is never sent by the pod
+    UnsupportedType(u8),
+    /// A text string is not valid UTF-8
+    InvalidUtf8,
+    /// A data item has a different type than expected
+    UnexpectedType(&'static str),
+    /// A required key is missing from the batch
+    MissingKey(u64),
+    /// The batch was encoded with an unknown schema version
+    UnsupportedVersion(u64),
+    /// There are bytes left over after the batch
+    TrailingBytes,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnexpectedEnd => write!(f, "unexpected end of payload"),
+            DecodeError::UnsupportedType(byte) => {
+                write!(f, "unsupported CBOR initial byte 0x{:02x}", byte)
+            }
+            DecodeError::InvalidUtf8 => write!(f, "text string is not valid UTF-8"),
+            DecodeError::UnexpectedType(expected) => write!(f, "expected {}", expected),
+            DecodeError::MissingKey(key) => write!(f, "missing key {}", key),
+            DecodeError::UnsupportedVersion(version) => {
+                write!(f, "unsupported payload version {}", version)
+            }
+            DecodeError::TrailingBytes => write!(f, "trailing bytes after payload"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// A generic CBOR data item, limited to the types sent by the pod
+#[derive(Debug, Clone, PartialEq)]
+pub enum CborValue {
+    Unsigned(u64),
+    Negative(i128),
+    Text(String),
+    Array(Vec<CborValue>),
+    Map(Vec<(CborValue, CborValue)>),
+    Bool(bool),
+    Null,
+    Float(f64),
+}
+
+/// The value of a single measurement reading
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    Integer(u64),
+    Float(f32),
+    Pair([u64; 2]),
+}
+
+/// A single reading in a `MeasurementBatch`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sample {
+    /// Time since the sending board booted, in milliseconds
+    pub timestamp_ms: u64,
+    pub value: Value,
+}
+
+/// A decoded batch of readings of one measurement
+#[derive(Debug, Clone, PartialEq)]
+pub struct MeasurementBatch {
+    pub version: u64,
+    pub board: String,
+    pub measurement: String,
+    pub samples: Vec<Sample>,
+}
+
+/// Decodes a CBOR measurement batch
+pub fn decode_measurement_batch(bytes: &[u8]) -> Result<MeasurementBatch, DecodeError> {
+    let entries = match decode(bytes)? {
+        CborValue::Map(entries) => entries,
+        _ => return Err(DecodeError::UnexpectedType("map")),
+    };
+    let get = |key: u64| {
+        entries
+            .iter()
+            .find(|(k, _)| *k == CborValue::Unsigned(key))
+            .map(|(_, v)| v)
+            .ok_or(DecodeError::MissingKey(key))
+    };
+
+    let version = as_unsigned(get(KEY_VERSION)?)?;
+    if version != SUPPORTED_VERSION {
+        return Err(DecodeError::UnsupportedVersion(version));
+    }
+    let samples = match get(KEY_SAMPLES)? {
+        CborValue::Array(samples) => samples
+            .iter()
+            .map(decode_sample)
+            .collect::<Result<Vec<_>, _>>()?,
+        _ => return Err(DecodeError::UnexpectedType("array of samples")),
+    };
+
+    Ok(MeasurementBatch {
+        version,
+        board: as_text(get(KEY_BOARD)?)?,
+        measurement: as_text(get(KEY_MEASUREMENT)?)?,
+        samples,
+    })
+}
+
+/// Decodes a single CBOR data item, which must fill the whole payload
+pub fn decode(bytes: &[u8]) -> Result<CborValue, DecodeError> {
+    let mut decoder = Decoder { bytes, position: 0 };
+    let value = decoder.value()?;
+    if decoder.position != bytes.len() {
+        return Err(DecodeError::TrailingBytes);
+    }
+    Ok(value)
+}
+
+fn decode_sample(sample: &CborValue) -> Result<Sample, DecodeError> {
+    let (timestamp, value) = match sample {
+        CborValue::Array(items) if items.len() == 2 => (&items[0], &items[1]),
+        _ => return Err(DecodeError::UnexpectedType("[timestamp_ms, value]")),
+    };
+    let value = match value {
+        CborValue::Bool(b) => Value::Bool(*b),
+        CborValue::Unsigned(i) => Value::Integer(*i),
+        CborValue::Float(f) => Value::Float(*f as f32),
+        CborValue::Array(pair) if pair.len() == 2 => {
+            Value::Pair([as_unsigned(&pair[0])?, as_unsigned(&pair[1])?])
+        }
+        _ => return Err(DecodeError::UnexpectedType("measurement value")),
+    };
+    Ok(Sample {
+        timestamp_ms: as_unsigned(timestamp)?,
+        value,
+    })
+}
+
+fn as_unsigned(value: &CborValue) -> Result<u64, DecodeError> {
+    match value {
+        CborValue::Unsigned(i) => Ok(*i),
+        _ => Err(DecodeError::UnexpectedType("unsigned integer")),
+    }
+}
+
+fn as_text(value: &CborValue) -> Result<String, DecodeError> {
+    match value {
+        CborValue::Text(text) => Ok(text.clone()),
+        _ => Err(DecodeError::UnexpectedType("text")),
+    }
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl Decoder<'_> {
+    fn value(&mut self) -> Result<CborValue, DecodeError> {
+        let initial = self.take(1)?[0];
+        let major_type = initial >> 5;
+        let info = initial & 0x1F;
+
+        if major_type == 7 {
+            return match info {
+                20 => Ok(CborValue::Bool(false)),
+                21 => Ok(CborValue::Bool(true)),
+                22 => Ok(CborValue::Null),
+                25 => Ok(CborValue::Float(half_to_f64(self.uint(2)? as u16))),
+                26 => Ok(CborValue::Float(f32::from_bits(self.uint(4)? as u32) as f64)),
+                27 => Ok(CborValue::Float(f64::from_bits(self.uint(8)?))),
+                _ => Err(DecodeError::UnsupportedType(initial)),
+            };
+        }
+
+        let argument = match info {
+            0..=23 => info as u64,
+            24 => self.uint(1)?,
+            25 => self.uint(2)?,
+            26 => self.uint(4)?,
+            27 => self.uint(8)?,
+            _ => return Err(DecodeError::UnsupportedType(initial)),
+        };
+
+        match major_type {
+            0 => Ok(CborValue::Unsigned(argument)),
+            1 => Ok(CborValue::Negative(-1 - argument as i128)),
+            3 => {
+                let text = self.take(argument as usize)?;
+                String::from_utf8(text.to_vec())
+                    .map(CborValue::Text)
+                    .map_err(|_| DecodeError::InvalidUtf8)
+            }
+            4 => (0..argument)
+                .map(|_| self.value())
+                .collect::<Result<Vec<_>, _>>()
+                .map(CborValue::Array),
+            5 => (0..argument)
+                .map(|_| Ok((self.value()?, self.value()?)))
+                .collect::<Result<Vec<_>, _>>()
+                .map(CborValue::Map),
+            _ => Err(DecodeError::UnsupportedType(initial)),
+        }
+    }
+
+    fn uint(&mut self, len: usize) -> Result<u64, DecodeError> {
+        Ok(self
+            .take(len)?
+            .iter()
+            .fold(0, |acc, byte| (acc << 8) | *byte as u64))
+    }
+
+    fn take(&mut self, len: usize) -> Result<&[u8], DecodeError> {
+        let end = self
+            .position
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or(DecodeError::UnexpectedEnd)?;
+        let bytes = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+}
+
+/// Converts an IEEE 754 half precision float to an f64
+fn half_to_f64(half: u16) -> f64 {
+    let exponent = (half >> 10) & 0x1F;
+    let mantissa = (half & 0x3FF) as f64;
+    let value = match exponent {
+        0 => mantissa * 2f64.powi(-24),
+        31 if mantissa == 0.0 => f64::INFINITY,
+        31 => f64::NAN,
+        _ => (mantissa + 1024.0) * 2f64.powi(exponent as i32 - 25),
+    };
+    if half & 0x8000 != 0 {
+        -value
+    } else {
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyped_core::{
+        config::MeasurementId,
+        mqtt::cbor::{self, MAX_BATCH_SIZE},
+        mqtt_payload::MeasurementValue,
+    };
+
+    #[test]
+    fn test_round_trip() {
+        let mut batch = cbor::MeasurementBatch::new("navigation", MeasurementId::Accelerometer1);
+        batch.push(0, MeasurementValue::Float(-9.81)).unwrap();
+        batch.push(300, MeasurementValue::Integer(70_000)).unwrap();
+        batch.push(u64::MAX, MeasurementValue::Bool(true)).unwrap();
+        batch.push(1_000, MeasurementValue::Pair([3, 4])).unwrap();
+
+        let decoded = decode_measurement_batch(&batch.to_payload().unwrap()).unwrap();
+        assert_eq!(
+            decoded,
+            MeasurementBatch {
+                version: 1,
+                board: "navigation".to_string(),
+                measurement: "accelerometer_1".to_string(),
+                samples: vec![
+                    Sample {
+                        timestamp_ms: 0,
+                        value: Value::Float(-9.81)
+                    },
+                    Sample {
+                        timestamp_ms: 300,
+                        value: Value::Integer(70_000)
+                    },
+                    Sample {
+                        timestamp_ms: u64::MAX,
+                        value: Value::Bool(true)
+                    },
+                    Sample {
+                        timestamp_ms: 1_000,
+                        value: Value::Pair([3, 4])
+                    },
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn test_round_trip_full_batch() {
+        let mut batch = cbor::MeasurementBatch::new("navigation", MeasurementId::Accelerometer2);
+        for i in 0..MAX_BATCH_SIZE {
+            batch
+                .push(i as u64 * 10, MeasurementValue::Float(i as f32 / 3.0))
+                .unwrap();
+        }
+
+        let decoded = decode_measurement_batch(&batch.to_payload().unwrap()).unwrap();
+        assert_eq!(decoded.samples.len(), MAX_BATCH_SIZE);
+        for (i, sample) in decoded.samples.iter().enumerate() {
+            assert_eq!(sample.timestamp_ms, i as u64 * 10);
+            assert_eq!(sample.value, Value::Float(i as f32 / 3.0));
+        }
+    }
+
+    #[test]
+    fn test_round_trip_empty_batch() {
+        let batch = cbor::MeasurementBatch::new("telemetry", MeasurementId::Accelerometer3);
+        let decoded = decode_measurement_batch(&batch.to_payload().unwrap()).unwrap();
+        assert_eq!(decoded.measurement, "accelerometer_3");
+        assert!(decoded.samples.is_empty());
+    }
+
+    #[test]
+    fn test_decode_truncated() {
+        let mut batch = cbor::MeasurementBatch::new("navigation", MeasurementId::Accelerometer1);
+        batch.push(0, MeasurementValue::Float(1.0)).unwrap();
+        let payload = batch.to_payload().unwrap();
+        assert_eq!(
+            decode_measurement_batch(&payload[..payload.len() - 1]),
+            Err(DecodeError::UnexpectedEnd)
+        );
+    }
+
+    #[test]
+    fn test_decode_unsupported_version() {
+        // {0: 2, 1: "a", 2: "b", 3: []}
+        let payload = [
+            0xA4, 0x00, 0x02, 0x01, 0x61, b'a', 0x02, 0x61, b'b', 0x03, 0x80,
+        ];
+        assert_eq!(
+            decode_measurement_batch(&payload),
+            Err(DecodeError::UnsupportedVersion(2))
+        );
+    }
+
+    #[test]
+    fn test_decode_generic_values() {
+        assert_eq!(decode(&[0x20]), Ok(CborValue::Negative(-1)));
+        assert_eq!(decode(&[0xF9, 0x3C, 0x00]), Ok(CborValue::Float(1.0)));
+        assert_eq!(decode(&[0xF6]), Ok(CborValue::Null));
+        assert_eq!(decode(&[0x01, 0x02]), Err(DecodeError::TrailingBytes));
+    }
+}
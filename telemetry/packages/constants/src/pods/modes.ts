@@ -4,6 +4,7 @@ export const MODES = {
 	ALL_SYSTEMS_ON: 'ALL_SYSTEMS_ON',
 	LEVITATION_ONLY: 'LEVITATION_ONLY',
 	LIM_ONLY: 'LIM_ONLY',
+	STATIC_TEST: 'STATIC_TEST',
 };
 
 export type ModeType = keyof typeof MODES;
@@ -24,4 +25,14 @@ export const MODE_EXCLUDED_STATES: ModeStates = {
 		ACTIVE_STATES.BEGIN_LEVITATION,
 		ACTIVE_STATES.STOP_LEVITATION,
 	],
+	STATIC_TEST: [
+		ACTIVE_STATES.READY_FOR_LEVITATION,
+		ACTIVE_STATES.BEGIN_LEVITATION,
+		ACTIVE_STATES.READY_FOR_LAUNCH,
+		ACTIVE_STATES.ACCELERATE,
+		ACTIVE_STATES.LIM_BRAKE,
+		ACTIVE_STATES.FRICTION_BRAKE,
+		ACTIVE_STATES.STOP_LEVITATION,
+		ACTIVE_STATES.BATTERY_RECHARGE,
+	],
 };
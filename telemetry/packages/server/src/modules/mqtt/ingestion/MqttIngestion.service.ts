@@ -6,6 +6,27 @@ import { Injectable } from '@nestjs/common';
 import { Params, Payload, Subscribe } from 'nest-mqtt';
 import { MqttIngestionError } from './errors/MqttIngestionError';
 
+/**
+ * Structured measurement payload sent by the pod.
+ * Mirrors `MeasurementPayload` and `AggregatePayload` in `lib/core/src/mqtt_payload.rs`.
+ * Aggregates summarise a window of readings, with `value` being the most recent one.
+ */
+type MeasurementPayload = {
+	version: number;
+	type: 'measurement' | 'aggregate';
+	timestamp_ms: number;
+	board: string;
+	measurement: string;
+	value: number | boolean | number[] | null;
+	unit: string;
+	range: 'safe' | 'warning' | 'critical' | null;
+	min?: number | null;
+	max?: number | null;
+	mean?: number | null;
+	count?: number;
+	window_ms?: number;
+};
+
 @Injectable()
 export class MqttIngestionService {
 	constructor(
@@ -16,15 +37,19 @@ export class MqttIngestionService {
 	@Subscribe('hyped/+/measurement/+')
 	async getMeasurementReading(
 		@Params() rawParams: string[],
-		@Payload() rawValue: number, // TODOLater: check that this is correct
+		@Payload() rawValue: number | MeasurementPayload,
 	) {
 		const timestamp = currentTime.nanos();
 		const podId = rawParams[0];
 		const measurementKey = rawParams[1];
-		const value = rawValue;
+		// Pods send structured JSON payloads (schema version 1), but bare values are still accepted
+		const value = typeof rawValue === 'object' ? rawValue.value : rawValue;
 
 		this.validateMqttMessage({ podId, measurementKey, value });
 		this.validatePodId(podId);
+		if (typeof value !== 'number') {
+			throw new MqttIngestionError('Non-numeric measurement value');
+		}
 
 		await this.measurementService.addMeasurementReading({
 			podId,
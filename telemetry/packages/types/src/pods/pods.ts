@@ -42,7 +42,7 @@ export type Status = z.infer<typeof StatusSchema>;
 export const PodSchema = z.object({
 	id: z.string(),
 	label: z.string(),
-	mode: z.enum(['ALL_SYSTEMS_ON', 'LEVITATION_ONLY', 'LIM_ONLY']),
+	mode: z.enum(['ALL_SYSTEMS_ON', 'LEVITATION_ONLY', 'LIM_ONLY', 'STATIC_TEST']),
 	measurements: z.record(z.string(), MeasurementSchema),
 	statuses: z.record(z.string(), StatusSchema),
 });